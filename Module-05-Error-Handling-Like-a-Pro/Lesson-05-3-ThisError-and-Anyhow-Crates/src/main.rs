@@ -17,6 +17,7 @@
 // procedural macro to automatically generate the `Display`, `Error`, and `From`
 // implementations for your error enum.
 
+use std::io::{ErrorKind, Read};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -27,6 +28,25 @@ pub enum MyError {
     Parse(#[from] std::num::ParseIntError),
 }
 
+// Callers often need to know whether trying again could help. A timeout or a
+// dropped connection might succeed next time; a missing file or a malformed
+// number will fail the same way forever.
+impl MyError {
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            MyError::Io(e) => matches!(
+                e.kind(),
+                ErrorKind::Interrupted
+                    | ErrorKind::TimedOut
+                    | ErrorKind::WouldBlock
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+            ),
+            MyError::Parse(_) => false,
+        }
+    }
+}
+
 fn read_and_parse_thiserror() -> Result<i32, MyError> {
     let mut file = std::fs::File::open("number.txt")?;
     let mut contents = String::new();
@@ -58,7 +78,7 @@ fn main() {
     println!("--- Using thiserror ---");
     match read_and_parse_thiserror() {
         Ok(n) => println!("The number is: {}", n),
-        Err(e) => println!("Error: {}", e),
+        Err(e) => println!("Error: {} (retryable: {})", e, e.is_retryable()),
     }
 
     // Using anyhow
@@ -68,3 +88,25 @@ fn main() {
         Err(e) => println!("Error: {:?}", e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_transient_io_errors_are_retryable() {
+        let cases: Vec<(MyError, bool)> = vec![
+            (std::io::Error::from(ErrorKind::TimedOut).into(), true),
+            (std::io::Error::from(ErrorKind::Interrupted).into(), true),
+            (std::io::Error::from(ErrorKind::WouldBlock).into(), true),
+            (std::io::Error::from(ErrorKind::ConnectionReset).into(), true),
+            (std::io::Error::from(ErrorKind::ConnectionAborted).into(), true),
+            (std::io::Error::from(ErrorKind::NotFound).into(), false),
+            (std::io::Error::from(ErrorKind::PermissionDenied).into(), false),
+            ("abc".parse::<i32>().unwrap_err().into(), false),
+        ];
+        for (err, retryable) in cases {
+            assert_eq!(err.is_retryable(), retryable, "{}", err);
+        }
+    }
+}
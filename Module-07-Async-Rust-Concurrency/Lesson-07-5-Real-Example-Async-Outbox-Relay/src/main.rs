@@ -6,8 +6,13 @@
 
 use anyhow::Result;
 use tokio::fs::{self, File, OpenOptions};
-use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{self, AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
 use tokio::time::{self, Duration};
+use tokio_util::sync::CancellationToken;
 
 // --- The Event ---
 
@@ -22,10 +27,6 @@ impl Event {
         Event { id, payload: payload.to_string() }
     }
 
-    fn to_string(&self) -> String {
-        format!("{}:வுகளை", self.id, self.payload)
-    }
-
     fn from_string(s: &str) -> Result<Self> {
         let mut parts = s.splitn(2, ':');
         let id_str = parts.next().ok_or_else(|| anyhow::anyhow!("Missing id"))?;
@@ -37,15 +38,59 @@ impl Event {
     }
 }
 
+// `Display` gives us `event.to_string()` for free. The format is `id:payload`;
+// `from_string` splits on the first `:` only, so payloads may contain `:`.
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.id, self.payload)
+    }
+}
+
 // --- The Outbox ---
 
+// When `write_event` forces its data onto disk. A returned write has only
+// reached the OS page cache; without an fsync a crash or power loss can
+// still drop it. Each fsync waits for the disk, so the stronger the
+// guarantee, the fewer events per second the outbox can take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Durability {
+    // Never fsync. Fastest; recent events can be lost on power loss.
+    None,
+    // fsync after every write. Once `write_event` returns, the event is on
+    // disk. Slowest: one disk round-trip per event.
+    Fsync,
+    // fsync after every N writes. Up to N - 1 acknowledged events can be
+    // lost, in exchange for 1/N of the fsyncs. Call `sync` to seal a
+    // partial batch, e.g. before shutdown.
+    SyncBatched(usize),
+}
+
 struct Outbox {
     file_path: String,
+    durability: Durability,
+    // Writes since the last fsync, for `SyncBatched` and `sync`.
+    unsynced: AtomicUsize,
+    // How many fsyncs this outbox has issued.
+    syncs: AtomicUsize,
 }
 
 impl Outbox {
     fn new(file_path: &str) -> Self {
-        Outbox { file_path: file_path.to_string() }
+        Outbox {
+            file_path: file_path.to_string(),
+            durability: Durability::None,
+            unsynced: AtomicUsize::new(0),
+            syncs: AtomicUsize::new(0),
+        }
+    }
+
+    fn with_durability(mut self, durability: Durability) -> Self {
+        // A batch of 0 would never sync; treat it as a batch of 1.
+        self.durability = match durability {
+            Durability::SyncBatched(n) => Durability::SyncBatched(n.max(1)),
+            other => other,
+        };
+        self
     }
 
     async fn write_event(&self, event: &Event) -> io::Result<()> {
@@ -57,68 +102,281 @@ impl Outbox {
 
         file.write_all(event.to_string().as_bytes()).await?;
         file.write_all(b"\n").await?;
+
+        let unsynced = self.unsynced.fetch_add(1, Ordering::SeqCst) + 1;
+        let due = match self.durability {
+            Durability::None => false,
+            Durability::Fsync => true,
+            Durability::SyncBatched(n) => unsynced >= n,
+        };
+        if due {
+            self.sync_file(&file).await?;
+        }
+        Ok(())
+    }
+
+    // Seals everything written so far: fsyncs the file if any write since
+    // the last fsync hasn't been synced. A no-op when there is nothing to do.
+    async fn sync(&self) -> io::Result<()> {
+        if self.unsynced.load(Ordering::SeqCst) == 0 {
+            return Ok(());
+        }
+        let file = OpenOptions::new().write(true).open(&self.file_path).await?;
+        self.sync_file(&file).await
+    }
+
+    // An fsync through any handle flushes the whole file, so syncing the
+    // handle of the latest write also covers earlier writes in the batch.
+    async fn sync_file(&self, file: &File) -> io::Result<()> {
+        file.sync_all().await?;
+        self.unsynced.store(0, Ordering::SeqCst);
+        self.syncs.fetch_add(1, Ordering::SeqCst);
         Ok(())
     }
+
+    fn sync_count(&self) -> usize {
+        self.syncs.load(Ordering::SeqCst)
+    }
 }
 
 // --- The Event Processor ---
 
 struct EventProcessor {
     file_path: String,
+    // How often `watch_and_process` checks the file for new lines.
+    poll_interval: Duration,
+    // How many `process_with` handlers may run at once.
+    max_concurrency: usize,
 }
 
 impl EventProcessor {
     fn new(file_path: &str) -> Self {
-        EventProcessor { file_path: file_path.to_string() }
+        EventProcessor {
+            file_path: file_path.to_string(),
+            poll_interval: Duration::from_millis(100),
+            max_concurrency: 4,
+        }
+    }
+
+    fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    fn with_max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency.max(1);
+        self
+    }
+
+    // Handles one line of the outbox. Returns whether it held a valid event.
+    async fn process_line(&self, line: &str) -> bool {
+        match Event::from_string(line) {
+            Ok(event) => {
+                println!("Processing event: {:?}", event);
+                // Simulate some work
+                time::sleep(Duration::from_millis(100)).await;
+                true
+            }
+            Err(e) => {
+                eprintln!("Error parsing event: {}", e);
+                false
+            }
+        }
     }
 
-    async fn process_events(&self) -> Result<()> {
+    // Processes events until the file is drained or `cancel` fires, and
+    // returns how many were processed. Cancellation is only checked between
+    // events, so the event in progress always finishes. On cancellation the
+    // unprocessed lines are written back so the next run picks them up; on a
+    // full drain the file is removed.
+    async fn process_events(&self, cancel: &CancellationToken) -> Result<usize> {
         let file = File::open(&self.file_path).await?;
         let reader = BufReader::new(file);
         let mut lines = reader.lines();
+        let mut processed = 0;
 
         while let Some(line) = lines.next_line().await? {
-            match Event::from_string(&line) {
-                Ok(event) => {
-                    println!("Processing event: {:?}", event);
-                    // Simulate some work
-                    time::sleep(Duration::from_millis(100)).await;
+            if cancel.is_cancelled() {
+                let mut remaining = vec![line];
+                while let Some(line) = lines.next_line().await? {
+                    remaining.push(line);
                 }
+                println!("Shutdown requested; leaving {} event(s) in the outbox.", remaining.len());
+                fs::write(&self.file_path, remaining.join("\n") + "\n").await?;
+                return Ok(processed);
+            }
+
+            if self.process_line(&line).await {
+                processed += 1;
+            }
+        }
+
+        fs::remove_file(&self.file_path).await?;
+
+        Ok(processed)
+    }
+
+    // Tails the outbox instead of reading it once: every `poll_interval` it
+    // checks the file length and processes any complete lines appended since
+    // the last look. A byte offset remembers how far it got, so old lines are
+    // never processed twice, and a half-written last line waits for its `\n`.
+    // The file is left in place. Runs until `cancel` fires, finishing the
+    // current event first, and returns how many events it processed.
+    async fn watch_and_process(&self, cancel: CancellationToken) -> Result<usize> {
+        let mut offset = 0u64;
+        let mut processed = 0;
+
+        loop {
+            let len = match fs::metadata(&self.file_path).await {
+                Ok(meta) => meta.len(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+                Err(e) => return Err(e.into()),
+            };
+            // A shorter file means it was truncated or replaced; start over.
+            if len < offset {
+                offset = 0;
+            }
+
+            if len > offset {
+                let mut file = File::open(&self.file_path).await?;
+                file.seek(io::SeekFrom::Start(offset)).await?;
+                let mut appended = Vec::new();
+                file.read_to_end(&mut appended).await?;
+
+                for line in appended.split_inclusive(|&b| b == b'\n') {
+                    if cancel.is_cancelled() {
+                        return Ok(processed);
+                    }
+                    if !line.ends_with(b"\n") {
+                        break;
+                    }
+                    offset += line.len() as u64;
+                    let line = String::from_utf8_lossy(line);
+                    if self.process_line(line.trim_end()).await {
+                        processed += 1;
+                    }
+                }
+            }
+
+            tokio::select! {
+                _ = cancel.cancelled() => return Ok(processed),
+                _ = time::sleep(self.poll_interval) => {}
+            }
+        }
+    }
+
+    // Runs a CPU-heavy `handler` on every event in the outbox. Each call goes
+    // to Tokio's blocking thread pool via `spawn_blocking`, so the runtime's
+    // worker threads stay free to drive other tasks. A semaphore holds back
+    // the reading loop until a permit is free, so at most `max_concurrency`
+    // handlers run at once. Results come back in file order, and the file is
+    // removed once everything is done, as in `process_events`.
+    async fn process_with<F, R>(&self, handler: F) -> Result<Vec<R>>
+    where
+        F: Fn(Event) -> R + Send + Sync + 'static,
+        R: Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let permits = Arc::new(Semaphore::new(self.max_concurrency));
+        let file = File::open(&self.file_path).await?;
+        let mut lines = BufReader::new(file).lines();
+        let mut handles = Vec::new();
+
+        while let Some(line) = lines.next_line().await? {
+            let event = match Event::from_string(&line) {
+                Ok(event) => event,
                 Err(e) => {
                     eprintln!("Error parsing event: {}", e);
+                    continue;
                 }
-            }
+            };
+            let permit = permits.clone().acquire_owned().await?;
+            let handler = handler.clone();
+            handles.push(tokio::task::spawn_blocking(move || {
+                let result = handler(event);
+                drop(permit);
+                result
+            }));
         }
 
+        let mut results = Vec::with_capacity(handles.len());
+        for handle in handles {
+            results.push(handle.await?);
+        }
         fs::remove_file(&self.file_path).await?;
+        Ok(results)
+    }
+}
 
-        Ok(())
+// Stands in for real CPU-bound work (hashing, compression, parsing...):
+// burns the CPU for roughly `duration` without yielding.
+fn busy_work(duration: Duration) -> u64 {
+    let start = std::time::Instant::now();
+    let mut acc = 0u64;
+    while start.elapsed() < duration {
+        acc = acc.wrapping_mul(31).wrapping_add(1);
     }
+    acc
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let outbox_file = "async_outbox.txt";
+    // The demo's outbox files live in a scratch directory that is removed
+    // again at the end, so nothing is left in the working directory.
+    let dir = std::env::temp_dir().join(format!("async-outbox-{}", std::process::id()));
+    fs::create_dir_all(&dir).await?;
+    let result = run_demo(&dir).await;
+    fs::remove_dir_all(&dir).await?;
+    result
+}
+
+async fn run_demo(dir: &Path) -> Result<()> {
+    let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+    let outbox_file = path("async_outbox.txt");
+
+    // --- Shutdown Signal ---
+    // One token is shared by every task. Ctrl-C cancels it; each loop checks
+    // it between units of work and exits cleanly.
+    let shutdown = CancellationToken::new();
+    let ctrl_c = shutdown.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            println!("Ctrl-C received; shutting down.");
+            ctrl_c.cancel();
+        }
+    });
 
     // --- Writer Task ---
-    let outbox = Outbox::new(outbox_file);
+    let outbox = Outbox::new(&outbox_file);
+    let writer_shutdown = shutdown.clone();
     let writer_task = tokio::spawn(async move {
         for i in 1..=10 {
             let event = Event::new(i, &format!("Event {}", i));
             if let Err(e) = outbox.write_event(&event).await {
                 eprintln!("Error writing event: {}", e);
             }
-            time::sleep(Duration::from_millis(50)).await;
+            tokio::select! {
+                _ = writer_shutdown.cancelled() => {
+                    println!("Writer stopped after event {}.", i);
+                    break;
+                }
+                _ = time::sleep(Duration::from_millis(50)) => {}
+            }
         }
     });
 
     // --- Processor Task ---
-    let processor = EventProcessor::new(outbox_file);
+    let processor = EventProcessor::new(&outbox_file);
+    let processor_shutdown = shutdown.clone();
     let processor_task = tokio::spawn(async move {
-        // Wait for the writer to finish
-        time::sleep(Duration::from_secs(1)).await;
-        if let Err(e) = processor.process_events().await {
-            eprintln!("Error processing events: {}", e);
+        // Wait for the writer to finish, unless we're told to stop first
+        tokio::select! {
+            _ = processor_shutdown.cancelled() => {}
+            _ = time::sleep(Duration::from_secs(1)) => {}
+        }
+        match processor.process_events(&processor_shutdown).await {
+            Ok(n) => println!("Processor finished after {} event(s).", n),
+            Err(e) => eprintln!("Error processing events: {}", e),
         }
     });
 
@@ -126,5 +384,285 @@ async fn main() -> Result<()> {
     writer_task.await?;
     processor_task.await?;
 
+    // --- Tailing the Outbox ---
+    // The watcher runs while a writer appends events over time; each event is
+    // picked up once, as soon as the next poll sees it.
+    let tailed_file = path("async_outbox_tailed.txt");
+    let watcher = EventProcessor::new(&tailed_file).with_poll_interval(Duration::from_millis(20));
+    let stop_watching = CancellationToken::new();
+    let watcher_task = tokio::spawn({
+        let cancel = stop_watching.clone();
+        async move { watcher.watch_and_process(cancel).await }
+    });
+
+    let outbox = Outbox::new(&tailed_file);
+    for i in 1..=2 {
+        outbox.write_event(&Event::new(i, &format!("Tailed {}", i))).await?;
+        time::sleep(Duration::from_millis(150)).await;
+    }
+    stop_watching.cancel();
+    let tailed = watcher_task.await??;
+    println!("Watcher processed {} of 2 appended event(s).", tailed);
+    fs::remove_file(&tailed_file).await?;
+
+    // --- Offloading CPU-Heavy Work ---
+    // Six 100ms handlers, two at a time, on the blocking pool.
+    let heavy_file = path("async_outbox_heavy.txt");
+    let outbox = Outbox::new(&heavy_file);
+    for i in 1..=6 {
+        outbox.write_event(&Event::new(i, &format!("Heavy {}", i))).await?;
+    }
+    let started = time::Instant::now();
+    let processor = EventProcessor::new(&heavy_file).with_max_concurrency(2);
+    let results = processor
+        .process_with(|event| {
+            busy_work(Duration::from_millis(100));
+            event.id * 10
+        })
+        .await?;
+    println!("Heavy handlers returned {:?} in {:?}.", results, started.elapsed());
+
+    // --- Durability Modes ---
+    // The same 200 events under each mode. Every mode round-trips all of
+    // them; what differs is how many fsyncs they cost and how long the
+    // writes take. The final `sync` seals whatever the mode left unsynced.
+    for durability in [Durability::None, Durability::SyncBatched(50), Durability::Fsync] {
+        let durable_file = path("async_outbox_durable.txt");
+        let _ = fs::remove_file(&durable_file).await;
+        let outbox = Outbox::new(&durable_file).with_durability(durability);
+        let started = time::Instant::now();
+        for i in 1..=200 {
+            outbox.write_event(&Event::new(i, &format!("Durable {}", i))).await?;
+        }
+        outbox.sync().await?;
+        let elapsed = started.elapsed();
+        let read_back = fs::read_to_string(&durable_file)
+            .await?
+            .lines()
+            .filter_map(|line| Event::from_string(line).ok())
+            .count();
+        println!(
+            "{:?}: wrote 200 events with {} fsync(s) in {:?}; read back {}.",
+            durability,
+            outbox.sync_count(),
+            elapsed,
+            read_back
+        );
+        fs::remove_file(&durable_file).await?;
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A path for `name` inside a fresh temporary directory, which is deleted
+    // when the returned guard is dropped.
+    fn scratch_path(name: &str) -> (tempfile::TempDir, String) {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let path = dir.path().join(name).to_string_lossy().into_owned();
+        (dir, path)
+    }
+
+    #[test]
+    fn an_event_round_trips_through_its_line_format() {
+        for payload in ["Event 1", "order:42:created", "", ":"] {
+            let event = Event::new(7, payload);
+            assert_eq!(event.to_string(), format!("7:{}", payload));
+            let decoded = Event::from_string(&event.to_string()).unwrap();
+            assert_eq!((decoded.id, decoded.payload.as_str()), (7, payload));
+        }
+    }
+
+    #[test]
+    fn a_line_without_a_payload_or_with_a_bad_id_is_rejected() {
+        assert!(Event::from_string("7").is_err());
+        assert!(Event::from_string("seven:Event").is_err());
+    }
+
+    #[tokio::test]
+    async fn written_events_are_all_processed_and_the_file_removed() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let outbox = Outbox::new(&path);
+        for i in 1..=3 {
+            outbox.write_event(&Event::new(i, &format!("order:{}", i))).await.unwrap();
+        }
+
+        let processed = EventProcessor::new(&path).process_events(&CancellationToken::new()).await.unwrap();
+        assert_eq!(processed, 3);
+        assert!(fs::metadata(&path).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn cancelling_mid_event_finishes_it_and_leaves_the_rest() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let outbox = Outbox::new(&path);
+        for i in 1..=5 {
+            outbox.write_event(&Event::new(i, &format!("Event {}", i))).await.unwrap();
+        }
+        let processor = EventProcessor::new(&path);
+        let cancel = CancellationToken::new();
+        tokio::spawn({
+            let cancel = cancel.clone();
+            // Each event takes 100ms, so this lands during the first one.
+            async move {
+                time::sleep(Duration::from_millis(50)).await;
+                cancel.cancel();
+            }
+        });
+
+        assert_eq!(processor.process_events(&cancel).await.unwrap(), 1);
+        let left = fs::read_to_string(&path).await.unwrap();
+        assert_eq!(left.lines().collect::<Vec<_>>(), ["2:Event 2", "3:Event 3", "4:Event 4", "5:Event 5"]);
+
+        assert_eq!(processor.process_events(&CancellationToken::new()).await.unwrap(), 4);
+        assert!(fs::metadata(&path).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn an_already_cancelled_run_processes_nothing_and_keeps_the_file() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        Outbox::new(&path).write_event(&Event::new(1, "Event 1")).await.unwrap();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        assert_eq!(EventProcessor::new(&path).process_events(&cancel).await.unwrap(), 0);
+        assert_eq!(fs::read_to_string(&path).await.unwrap(), "1:Event 1\n");
+    }
+
+    async fn append(path: &str, text: &str) {
+        let mut file = OpenOptions::new().create(true).append(true).open(path).await.unwrap();
+        file.write_all(text.as_bytes()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_watcher_processes_each_appended_event_exactly_once() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let watcher = EventProcessor::new(&path).with_poll_interval(Duration::from_millis(10));
+        let cancel = CancellationToken::new();
+        let watching = tokio::spawn({
+            let cancel = cancel.clone();
+            async move { watcher.watch_and_process(cancel).await }
+        });
+
+        // Several polls see each state of the file, including a line that is
+        // only half written, and none of them may process a line twice.
+        append(&path, "1:Event 1\n").await;
+        time::sleep(Duration::from_millis(150)).await;
+        append(&path, "2:Eve").await;
+        time::sleep(Duration::from_millis(50)).await;
+        append(&path, "nt 2\n3:Event 3\n").await;
+        time::sleep(Duration::from_millis(300)).await;
+
+        cancel.cancel();
+        assert_eq!(watching.await.unwrap().unwrap(), 3);
+        assert!(fs::metadata(&path).await.is_ok(), "the watcher leaves the file in place");
+    }
+
+    #[tokio::test]
+    async fn the_watcher_starts_over_when_the_file_is_truncated() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        append(&path, "1:Event 1\n2:Event 2\n").await;
+        let watcher = EventProcessor::new(&path).with_poll_interval(Duration::from_millis(10));
+        let cancel = CancellationToken::new();
+        let watching = tokio::spawn({
+            let cancel = cancel.clone();
+            async move { watcher.watch_and_process(cancel).await }
+        });
+
+        time::sleep(Duration::from_millis(300)).await;
+        fs::write(&path, "3:Event 3\n").await.unwrap();
+        time::sleep(Duration::from_millis(200)).await;
+
+        cancel.cancel();
+        assert_eq!(watching.await.unwrap().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn heavy_handlers_run_off_the_runtime_at_most_max_concurrency_at_a_time() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let outbox = Outbox::new(&path);
+        for i in 1..=6 {
+            outbox.write_event(&Event::new(i, &format!("Heavy {}", i))).await.unwrap();
+        }
+
+        // The test runtime has a single worker thread, so the ticker only
+        // ticks if the handlers stay off it.
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let ticker = tokio::spawn({
+            let ticks = ticks.clone();
+            async move {
+                loop {
+                    time::sleep(Duration::from_millis(10)).await;
+                    ticks.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+        let running = Arc::new(AtomicUsize::new(0));
+        let most_at_once = Arc::new(AtomicUsize::new(0));
+        let processor = EventProcessor::new(&path).with_max_concurrency(2);
+        let results = processor
+            .process_with({
+                let (running, most_at_once) = (running.clone(), most_at_once.clone());
+                move |event| {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most_at_once.fetch_max(now, Ordering::SeqCst);
+                    busy_work(Duration::from_millis(100));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    event.id * 10
+                }
+            })
+            .await
+            .unwrap();
+        ticker.abort();
+
+        assert_eq!(results, [10, 20, 30, 40, 50, 60]);
+        assert_eq!(most_at_once.load(Ordering::SeqCst), 2);
+        // Three rounds of 100ms; allow for a slow machine.
+        assert!(ticks.load(Ordering::Relaxed) >= 10, "ticked {} times", ticks.load(Ordering::Relaxed));
+        assert!(fs::metadata(&path).await.is_err());
+    }
+
+    async fn read_back(path: &str) -> Vec<String> {
+        let contents = fs::read_to_string(path).await.unwrap();
+        contents.lines().map(|line| Event::from_string(line).unwrap().payload).collect()
+    }
+
+    #[tokio::test]
+    async fn every_durability_mode_round_trips_its_events() {
+        for durability in [Durability::None, Durability::SyncBatched(3), Durability::Fsync] {
+            let (_dir, path) = scratch_path("outbox.txt");
+            let outbox = Outbox::new(&path).with_durability(durability);
+            for i in 1..=5 {
+                outbox.write_event(&Event::new(i, &format!("Event {}", i))).await.unwrap();
+            }
+            outbox.sync().await.unwrap();
+            let expected: Vec<String> = (1..=5).map(|i| format!("Event {}", i)).collect();
+            assert_eq!(read_back(&path).await, expected, "{:?}", durability);
+        }
+    }
+
+    #[tokio::test]
+    async fn each_mode_issues_the_expected_number_of_fsyncs() {
+        // (mode, fsyncs after 10 writes, fsyncs after a final `sync`)
+        let cases = [
+            (Durability::None, 0, 1),
+            (Durability::SyncBatched(4), 2, 3),
+            (Durability::SyncBatched(5), 2, 2),
+            (Durability::SyncBatched(0), 10, 10),
+            (Durability::Fsync, 10, 10),
+        ];
+        for (durability, after_writes, after_sync) in cases {
+            let (_dir, path) = scratch_path("outbox.txt");
+            let outbox = Outbox::new(&path).with_durability(durability);
+            for i in 1..=10 {
+                outbox.write_event(&Event::new(i, "Event")).await.unwrap();
+            }
+            assert_eq!(outbox.sync_count(), after_writes, "{:?}", durability);
+            outbox.sync().await.unwrap();
+            assert_eq!(outbox.sync_count(), after_sync, "{:?}", durability);
+        }
+    }
+}
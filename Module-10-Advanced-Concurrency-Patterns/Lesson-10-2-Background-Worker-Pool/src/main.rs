@@ -4,36 +4,142 @@
 // tasks. A worker pool is a common pattern for processing a large number of
 // jobs concurrently.
 
+use anyhow::Result;
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
-use tokio::time::{self, Duration};
+use tokio::sync::{mpsc, oneshot, watch, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration, Instant};
 
 // --- The Job ---
 
-// First, let's define the job that we want to process.
+// First, let's define the job that we want to process. The pool itself is
+// generic over the job type `J` and the result type `R`; this is the job we
+// use in `main`.
 
 #[derive(Debug)]
 struct Job {
     id: u32,
 }
 
+// Every job travels through the channel with an optional `oneshot` sender.
+// `submit` includes one so the caller can await the job's result;
+// fire-and-forget jobs from `send_job` don't.
+struct Envelope<J, R> {
+    job: J,
+    reply: Option<oneshot::Sender<R>>,
+}
+
+// The function that turns a job into its result. It is type-erased so the
+// pool doesn't need the closure's type as a parameter.
+type JobHandler<J, R> = Arc<dyn Fn(J) -> Pin<Box<dyn Future<Output = R> + Send>> + Send + Sync>;
+
+// --- Pool Configuration ---
+
+// `WorkerPool::new` covers the common case; `with_config` takes the rest.
+#[derive(Debug, Clone)]
+struct PoolConfig {
+    num_workers: u32,
+    // How long a single job may run before the worker abandons it.
+    job_timeout: Option<Duration>,
+    // How many jobs can wait in the channel before producers feel
+    // backpressure: `submit` waits for room and `try_submit` fails fast.
+    queue_capacity: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig { num_workers: 4, job_timeout: None, queue_capacity: 100 }
+    }
+}
+
+// Errors the workers record while processing jobs.
+#[derive(Debug, Clone, thiserror::Error)]
+enum JobError {
+    #[error("job {job} timed out after {after:?}")]
+    JobTimedOut { job: String, after: Duration },
+}
+
+// Why a job couldn't be handed to the pool.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+enum SubmitError {
+    // Only from `try_submit`: every queue slot is taken.
+    #[error("the job queue is full")]
+    QueueFull,
+    // The receiving end of the queue is gone, so no worker will ever run it.
+    #[error("the pool has shut down")]
+    PoolShutdown,
+}
+
+impl<T> From<mpsc::error::SendError<T>> for SubmitError {
+    fn from(_: mpsc::error::SendError<T>) -> Self {
+        SubmitError::PoolShutdown
+    }
+}
+
+impl<T> From<mpsc::error::TrySendError<T>> for SubmitError {
+    fn from(err: mpsc::error::TrySendError<T>) -> Self {
+        match err {
+            mpsc::error::TrySendError::Full(_) => SubmitError::QueueFull,
+            mpsc::error::TrySendError::Closed(_) => SubmitError::PoolShutdown,
+        }
+    }
+}
+
+// Everything a worker needs, shared by all workers in the pool.
+struct WorkerContext<J, R> {
+    rx: Mutex<mpsc::Receiver<Envelope<J, R>>>,
+    handler: JobHandler<J, R>,
+    job_timeout: Option<Duration>,
+    errors: std::sync::Mutex<Vec<JobError>>,
+}
+
 // --- The Worker ---
 
 // The worker is a task that receives jobs from a channel and processes them.
-// We use an `Arc<Mutex<...>>` to allow multiple workers to share the same
-// receiver.
+// The receiver sits behind a `Mutex` in the shared context to allow multiple
+// workers to share it. `stop` is flipped to `true` when the pool shrinks; the
+// worker only checks it between jobs, so a job that is already running always
+// finishes.
 
-async fn worker(id: u32, rx: Arc<Mutex<mpsc::Receiver<Job>>>) {
+async fn worker<J: Debug, R>(id: u32, ctx: Arc<WorkerContext<J, R>>, mut stop: watch::Receiver<bool>) {
     loop {
-        let job = {
-            let mut lock = rx.lock().await;
-            lock.recv().await
+        let envelope = tokio::select! {
+            biased;
+            _ = stop.wait_for(|stop| *stop) => {
+                println!("Worker {} retiring.", id);
+                break;
+            }
+            envelope = async { ctx.rx.lock().await.recv().await } => envelope,
         };
 
-        if let Some(job) = job {
+        if let Some(Envelope { job, reply }) = envelope {
             println!("Worker {} processing job {:?}", id, job);
-            // Simulate some work
-            time::sleep(Duration::from_millis(500)).await;
+            let result = match ctx.job_timeout {
+                // On timeout the job's future is dropped, which cancels it at
+                // its next `.await`. The worker is free for the next job and
+                // the submitter sees the `oneshot` sender dropped.
+                Some(after) => {
+                    let label = format!("{:?}", job);
+                    match time::timeout(after, (ctx.handler)(job)).await {
+                        Ok(result) => Some(result),
+                        Err(_) => {
+                            let err = JobError::JobTimedOut { job: label, after };
+                            eprintln!("Worker {}: {}", id, err);
+                            ctx.errors.lock().unwrap().push(err);
+                            None
+                        }
+                    }
+                }
+                None => Some((ctx.handler)(job).await),
+            };
+            // The caller may have stopped waiting; that's fine.
+            if let (Some(reply), Some(result)) = (reply, result) {
+                let _ = reply.send(result);
+            }
         } else {
             // The channel has been closed, so we can exit.
             println!("Worker {} shutting down.", id);
@@ -42,45 +148,700 @@ async fn worker(id: u32, rx: Arc<Mutex<mpsc::Receiver<Job>>>) {
     }
 }
 
+// --- Supervising Workers ---
+
+// If a job panics, the task running `worker` dies with it and the pool would
+// quietly lose capacity. Each worker therefore runs under a small supervisor
+// task. The supervisor spawns the worker, and if the worker's `JoinHandle`
+// reports a panic, it logs it, bumps the restart counter, and spawns a fresh
+// worker in the same slot. When the worker exits normally (the channel was
+// closed) the supervisor exits too. The panicking job itself is lost; its
+// submitter sees the `oneshot` sender dropped.
+
+async fn supervise<J: Debug + Send + 'static, R: Send + 'static>(
+    id: u32,
+    ctx: Arc<WorkerContext<J, R>>,
+    stop: watch::Receiver<bool>,
+    restarts: Arc<AtomicUsize>,
+    active: Arc<AtomicUsize>,
+) {
+    loop {
+        let handle = tokio::spawn(worker(id, Arc::clone(&ctx), stop.clone()));
+        match handle.await {
+            Err(e) if e.is_panic() => {
+                eprintln!("Worker {} panicked; restarting it.", id);
+                restarts.fetch_add(1, Ordering::SeqCst);
+            }
+            _ => break,
+        }
+    }
+    active.fetch_sub(1, Ordering::SeqCst);
+}
+
 // --- The Worker Pool ---
 
 // The worker pool is responsible for creating the workers and providing a way
-// to send jobs to them.
+// to send jobs to them. It keeps the workers' `JoinHandle`s so it can wait
+// for them to finish when shutting down, along with what it needs to start
+// more workers when scaling up.
 
-struct WorkerPool {
-    sender: mpsc::Sender<Job>,
+// A running worker: its stop signal and its supervisor's handle.
+struct WorkerSlot {
+    stop: watch::Sender<bool>,
+    handle: JoinHandle<()>,
 }
 
-impl WorkerPool {
-    fn new(num_workers: u32) -> Self {
-        let (tx, rx) = mpsc::channel(100);
-        let rx = Arc::new(Mutex::new(rx));
+struct WorkerPool<J, R> {
+    sender: mpsc::Sender<Envelope<J, R>>,
+    queue_capacity: usize,
+    // Jobs turned away by `try_submit` because the queue was full.
+    rejected: AtomicUsize,
+    ctx: Arc<WorkerContext<J, R>>,
+    workers: Mutex<Vec<WorkerSlot>>,
+    next_id: AtomicU32,
+    restarts: Arc<AtomicUsize>,
+    active: Arc<AtomicUsize>,
+}
+
+impl<J: Debug + Send + 'static, R: Send + 'static> WorkerPool<J, R> {
+    fn new<F, Fut>(num_workers: u32, handler: F) -> Self
+    where
+        F: Fn(J) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = R> + Send + 'static,
+    {
+        Self::with_config(PoolConfig { num_workers, ..PoolConfig::default() }, handler)
+    }
+
+    fn with_config<F, Fut>(config: PoolConfig, handler: F) -> Self
+    where
+        F: Fn(J) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = R> + Send + 'static,
+    {
+        let queue_capacity = config.queue_capacity.max(1);
+        let (tx, rx) = mpsc::channel(queue_capacity);
+        let ctx = Arc::new(WorkerContext {
+            rx: Mutex::new(rx),
+            handler: Arc::new(move |job| Box::pin(handler(job))),
+            job_timeout: config.job_timeout,
+            errors: std::sync::Mutex::new(Vec::new()),
+        });
+
+        let mut pool = WorkerPool {
+            sender: tx,
+            queue_capacity,
+            rejected: AtomicUsize::new(0),
+            ctx,
+            workers: Mutex::new(Vec::new()),
+            next_id: AtomicU32::new(0),
+            restarts: Arc::new(AtomicUsize::new(0)),
+            active: Arc::new(AtomicUsize::new(0)),
+        };
+        let slots = (0..config.num_workers).map(|_| pool.spawn_worker()).collect();
+        *pool.workers.get_mut() = slots;
+        pool
+    }
+
+    fn spawn_worker(&self) -> WorkerSlot {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (stop, stop_rx) = watch::channel(false);
+        // Counted here rather than when the task first runs, so the count is
+        // already right when `new` or `scale_to` returns.
+        self.active.fetch_add(1, Ordering::SeqCst);
+        let handle = tokio::spawn(supervise(
+            id,
+            Arc::clone(&self.ctx),
+            stop_rx,
+            Arc::clone(&self.restarts),
+            Arc::clone(&self.active),
+        ));
+        WorkerSlot { stop, handle }
+    }
 
-        for i in 0..num_workers {
-            let rx_clone = Arc::clone(&rx);
-            tokio::spawn(worker(i, rx_clone));
+    // Number of workers currently running.
+    fn worker_count(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    // Grows or shrinks the pool to `n` workers. Surplus workers finish the
+    // job they are running before they stop; this waits for them, so the
+    // pool has exactly `n` workers when it returns.
+    async fn scale_to(&self, n: usize) -> Result<()> {
+        let retiring = {
+            let mut workers = self.workers.lock().await;
+            while workers.len() < n {
+                let slot = self.spawn_worker();
+                workers.push(slot);
+            }
+            let keep = n.min(workers.len());
+            workers.split_off(keep)
+        };
+        for slot in retiring {
+            let _ = slot.stop.send(true);
+            slot.handle.await?;
         }
+        Ok(())
+    }
+
+    // How many times a worker has been restarted after a panic.
+    fn restart_count(&self) -> usize {
+        self.restarts.load(Ordering::SeqCst)
+    }
+
+    // Errors recorded by the workers so far, such as timed-out jobs.
+    fn errors(&self) -> Vec<JobError> {
+        self.ctx.errors.lock().unwrap().clone()
+    }
+
+    // Jobs waiting in the queue, not counting the ones being processed.
+    // `Sender::capacity` is the number of free slots, so the rest are taken.
+    fn queue_len(&self) -> usize {
+        self.queue_capacity - self.sender.capacity()
+    }
+
+    // How many jobs `try_submit` has turned away.
+    fn rejected_count(&self) -> usize {
+        self.rejected.load(Ordering::SeqCst)
+    }
+
+    // Fire-and-forget: the job's result is discarded. Waits for room in the
+    // queue if it is full.
+    async fn send_job(&self, job: J) -> Result<(), SubmitError> {
+        self.sender.send(Envelope { job, reply: None }).await?;
+        Ok(())
+    }
+
+    // Queues the job and returns a receiver that resolves to its result.
+    // Waits for room in the queue if it is full.
+    async fn submit(&self, job: J) -> Result<oneshot::Receiver<R>, SubmitError> {
+        let (reply, result) = oneshot::channel();
+        self.sender.send(Envelope { job, reply: Some(reply) }).await?;
+        Ok(result)
+    }
+
+    // Like `submit`, but fails with `QueueFull` instead of waiting, so the
+    // producer can shed load, buffer elsewhere, or slow down.
+    fn try_submit(&self, job: J) -> Result<oneshot::Receiver<R>, SubmitError> {
+        let (reply, result) = oneshot::channel();
+        self.try_enqueue(Envelope { job, reply: Some(reply) })?;
+        Ok(result)
+    }
+
+    fn try_enqueue(&self, envelope: Envelope<J, R>) -> Result<(), SubmitError> {
+        let err = match self.sender.try_send(envelope) {
+            Ok(()) => return Ok(()),
+            Err(e) => SubmitError::from(e),
+        };
+        if err == SubmitError::QueueFull {
+            self.rejected.fetch_add(1, Ordering::SeqCst);
+        }
+        Err(err)
+    }
+
+    // Dropping the sender closes the channel. Workers keep going until the
+    // queue is empty, then `recv` returns `None` and they exit. Awaiting every
+    // handle means this returns exactly when the last job has finished.
+    async fn shutdown(self) -> Result<()> {
+        drop(self.sender);
+        for WorkerSlot { stop, handle } in self.workers.into_inner() {
+            handle.await?;
+            // Keep the stop sender alive until the worker has drained the
+            // queue; a dropped sender also tells the worker to stop.
+            drop(stop);
+        }
+        Ok(())
+    }
+}
+
+// --- Priority Worker Pool ---
+
+// `WorkerPool` is strictly FIFO because jobs travel through an `mpsc`
+// channel. `PriorityWorkerPool` keeps its queue in a shared `BinaryHeap`
+// instead, so workers always take the highest-priority job that is waiting.
+// A `Notify` wakes idle workers when a job arrives or the pool closes.
+
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use tokio::sync::Notify;
+
+struct QueuedJob<J, R> {
+    priority: u8,
+    // Submission order. Among equal priorities the lower `seq` wins, which
+    // keeps equal-priority jobs FIFO.
+    seq: u64,
+    job: J,
+    reply: oneshot::Sender<R>,
+}
+
+// `BinaryHeap` is a max-heap, so the "greatest" job is popped first: higher
+// priority, then earlier submission.
+impl<J, R> Ord for QueuedJob<J, R> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.priority.cmp(&other.priority).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl<J, R> PartialOrd for QueuedJob<J, R> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<J, R> PartialEq for QueuedJob<J, R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority && self.seq == other.seq
+    }
+}
+
+impl<J, R> Eq for QueuedJob<J, R> {}
+
+struct PriorityQueue<J, R> {
+    heap: std::sync::Mutex<BinaryHeap<QueuedJob<J, R>>>,
+    notify: Notify,
+    closed: AtomicBool,
+}
+
+async fn priority_worker<J: Debug, R>(id: u32, queue: Arc<PriorityQueue<J, R>>, handler: JobHandler<J, R>) {
+    loop {
+        // Register interest before looking at the heap, so a job pushed (or a
+        // close) between the check and the `.await` still wakes us.
+        let notified = queue.notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        let next = queue.heap.lock().unwrap().pop();
+        match next {
+            Some(QueuedJob { priority, job, reply, .. }) => {
+                println!("Worker {} processing job {:?} (priority {})", id, job, priority);
+                let _ = reply.send(handler(job).await);
+            }
+            // Closed and drained: the worker is done.
+            None if queue.closed.load(Ordering::SeqCst) => break,
+            None => notified.await,
+        }
+    }
+}
+
+struct PriorityWorkerPool<J, R> {
+    queue: Arc<PriorityQueue<J, R>>,
+    next_seq: AtomicU64,
+    workers: Vec<JoinHandle<()>>,
+}
 
-        WorkerPool { sender: tx }
+impl<J: Debug + Send + 'static, R: Send + 'static> PriorityWorkerPool<J, R> {
+    fn new<F, Fut>(num_workers: u32, handler: F) -> Self
+    where
+        F: Fn(J) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = R> + Send + 'static,
+    {
+        let queue = Arc::new(PriorityQueue {
+            heap: std::sync::Mutex::new(BinaryHeap::new()),
+            notify: Notify::new(),
+            closed: AtomicBool::new(false),
+        });
+        let handler: JobHandler<J, R> = Arc::new(move |job| Box::pin(handler(job)));
+
+        let workers = (0..num_workers)
+            .map(|i| tokio::spawn(priority_worker(i, Arc::clone(&queue), Arc::clone(&handler))))
+            .collect();
+
+        PriorityWorkerPool { queue, next_seq: AtomicU64::new(0), workers }
     }
 
-    async fn send_job(&self, job: Job) {
-        self.sender.send(job).await.unwrap();
+    // Queues the job and returns a receiver that resolves to its result.
+    // Higher `priority` values run first.
+    fn submit(&self, job: J, priority: u8) -> oneshot::Receiver<R> {
+        let (reply, result) = oneshot::channel();
+        let seq = self.next_seq.fetch_add(1, Ordering::SeqCst);
+        self.queue.heap.lock().unwrap().push(QueuedJob { priority, seq, job, reply });
+        self.queue.notify.notify_one();
+        result
+    }
+
+    // Workers finish every queued job, then exit.
+    async fn shutdown(self) -> Result<()> {
+        self.queue.closed.store(true, Ordering::SeqCst);
+        self.queue.notify.notify_waiters();
+        for handle in self.workers {
+            handle.await?;
+        }
+        Ok(())
     }
 }
 
 #[tokio::main]
-async fn main() {
-    let pool = WorkerPool::new(4);
+async fn main() -> Result<()> {
+    // Each job sums the numbers up to `id * 1000` and returns the total.
+    let pool = WorkerPool::new(4, |job: Job| async move {
+        // Simulate some work
+        time::sleep(Duration::from_millis(500)).await;
+        (0..=job.id as u64 * 1000).sum::<u64>()
+    });
 
+    let mut results = Vec::new();
     for i in 0..10 {
-        pool.send_job(Job { id: i }).await;
+        results.push((i, pool.submit(Job { id: i }).await?));
+    }
+    // Jobs whose results we don't need can still be fired and forgotten.
+    pool.send_job(Job { id: 99 }).await?;
+
+    for (id, result) in results {
+        println!("Job {} computed {}", id, result.await?);
+    }
+    println!("Workers restarted after panics: {}", pool.restart_count());
+
+    // To gracefully shut down the workers, we close the channel and wait for
+    // every worker to drain the queue and exit.
+    pool.shutdown().await?;
+    println!("All jobs finished; the pool has shut down.");
+
+    // The pool can be resized while it runs. With 50ms jobs, a batch of 10
+    // finishes faster the more workers there are.
+    let scalable = WorkerPool::new(2, |job: Job| async move {
+        time::sleep(Duration::from_millis(50)).await;
+        job.id
+    });
+    for size in [2, 5, 1] {
+        scalable.scale_to(size).await?;
+        let started = Instant::now();
+        let mut results = Vec::new();
+        for i in 0..10 {
+            results.push(scalable.submit(Job { id: i }).await?);
+        }
+        for result in results {
+            result.await?;
+        }
+        println!(
+            "{} workers ran 10 jobs in {:?}",
+            scalable.worker_count(),
+            started.elapsed()
+        );
+    }
+    scalable.shutdown().await?;
+
+    // With a job timeout, a stuck job is abandoned and its worker moves on.
+    // Job 0 sleeps far past the limit; the single worker still gets through
+    // the jobs queued behind it.
+    let config = PoolConfig {
+        num_workers: 1,
+        job_timeout: Some(Duration::from_millis(200)),
+        ..PoolConfig::default()
+    };
+    let timed = WorkerPool::with_config(config, |job: Job| async move {
+        let work = if job.id == 0 { Duration::from_secs(60) } else { Duration::from_millis(10) };
+        time::sleep(work).await;
+        job.id
+    });
+    let mut results = Vec::new();
+    for i in 0..4 {
+        results.push((i, timed.submit(Job { id: i }).await?));
+    }
+    for (id, result) in results {
+        match result.await {
+            Ok(_) => println!("Job {} completed", id),
+            Err(_) => println!("Job {} was abandoned", id),
+        }
+    }
+    for err in timed.errors() {
+        println!("Recorded error: {}", err);
+    }
+    timed.shutdown().await?;
+
+    // With a one-slot queue and one busy worker, the queue fills after a
+    // single job. `try_submit` then reports `QueueFull` straight away instead
+    // of blocking the producer.
+    let config = PoolConfig { num_workers: 1, queue_capacity: 1, ..PoolConfig::default() };
+    let bounded = WorkerPool::with_config(config, |job: Job| async move {
+        time::sleep(Duration::from_millis(100)).await;
+        job.id
+    });
+    let running = bounded.try_submit(Job { id: 0 })?;
+    time::sleep(Duration::from_millis(20)).await; // let the worker pick it up
+    let queued = bounded.try_submit(Job { id: 1 })?;
+    println!("Queue length with one job waiting: {}", bounded.queue_len());
+    match bounded.try_submit(Job { id: 2 }) {
+        Ok(_) => println!("Unexpectedly queued job 2."),
+        Err(e) => println!("Job 2 rejected: {} ({:?})", e, e),
     }
+    println!("Rejected so far: {}", bounded.rejected_count());
+    println!("Jobs 0 and 1 still finish: {} and {}", running.await?, queued.await?);
+    bounded.shutdown().await?;
 
-    // To gracefully shut down the workers, we can drop the sender. This will
-    // cause the `recv` calls in the workers to return `None`.
-    drop(pool.sender);
+    // A single worker with a priority queue. Job 0 keeps the worker busy
+    // while low (1) and high (9) priority jobs are queued alternately; the
+    // high-priority ones then run first, each group in submission order.
+    let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let seen = Arc::clone(&order);
+    let prioritized = PriorityWorkerPool::new(1, move |job: Job| {
+        let seen = Arc::clone(&seen);
+        async move {
+            let work = if job.id == 0 { 100 } else { 10 };
+            time::sleep(Duration::from_millis(work)).await;
+            seen.lock().unwrap().push(job.id);
+        }
+    });
+    prioritized.submit(Job { id: 0 }, 5);
+    // Let the worker pick up job 0 before the others arrive; otherwise the
+    // high-priority jobs could overtake it too.
+    time::sleep(Duration::from_millis(20)).await;
+    for i in 1..=6 {
+        let priority = if i % 2 == 0 { 9 } else { 1 };
+        prioritized.submit(Job { id: i }, priority);
+    }
+    prioritized.shutdown().await?;
+    let order = order.lock().unwrap().clone();
+    println!("Priority pool processed jobs in order {:?}", order);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn shutdown_returns_once_every_queued_job_has_finished() {
+        let completed = Arc::new(AtomicUsize::new(0));
+        let pool = WorkerPool::new(3, {
+            let completed = Arc::clone(&completed);
+            move |_job: Job| {
+                let completed = Arc::clone(&completed);
+                async move {
+                    time::sleep(Duration::from_millis(20)).await;
+                    completed.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+        for i in 0..20 {
+            pool.send_job(Job { id: i }).await.unwrap();
+        }
+
+        pool.shutdown().await.unwrap();
+        assert_eq!(completed.load(Ordering::SeqCst), 20);
+    }
+
+
+    fn summing_pool(num_workers: u32) -> WorkerPool<Job, u64> {
+        WorkerPool::new(num_workers, |job: Job| async move { (0..=job.id as u64 * 1000).sum::<u64>() })
+    }
+
+    #[tokio::test]
+    async fn each_submitted_job_returns_its_own_result() {
+        let pool = summing_pool(4);
+        let mut results = Vec::new();
+        for i in 0..10 {
+            results.push(pool.submit(Job { id: i }).await.unwrap());
+        }
+
+        for (i, result) in results.into_iter().enumerate() {
+            let n = i as u64 * 1000;
+            assert_eq!(result.await.unwrap(), n * (n + 1) / 2);
+        }
+        pool.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn fire_and_forget_jobs_still_run() {
+        let ran = Arc::new(AtomicUsize::new(0));
+        let pool = WorkerPool::new(2, {
+            let ran = Arc::clone(&ran);
+            move |_job: Job| {
+                let ran = Arc::clone(&ran);
+                async move { ran.fetch_add(1, Ordering::SeqCst) }
+            }
+        });
+        for i in 0..5 {
+            pool.send_job(Job { id: i }).await.unwrap();
+        }
+        pool.shutdown().await.unwrap();
+        assert_eq!(ran.load(Ordering::SeqCst), 5);
+    }
+
+
+    #[tokio::test]
+    async fn a_panicking_job_restarts_its_worker_and_later_jobs_still_run() {
+        let pool = WorkerPool::new(1, |job: Job| async move {
+            if job.id == 3 {
+                panic!("job 3 is poisoned");
+            }
+            job.id
+        });
+        let mut results = Vec::new();
+        for i in 0..6 {
+            results.push(pool.submit(Job { id: i }).await.unwrap());
+        }
 
-    // Wait for a bit to allow the workers to finish processing their current jobs.
-    time::sleep(Duration::from_secs(3)).await;
-}
\ No newline at end of file
+        let mut outcomes = Vec::new();
+        for result in results {
+            outcomes.push(result.await.ok());
+        }
+        // With a single worker, everything after job 3 ran on its replacement.
+        assert_eq!(outcomes, [Some(0), Some(1), Some(2), None, Some(4), Some(5)]);
+        assert_eq!(pool.restart_count(), 1);
+        assert_eq!(pool.worker_count(), 1);
+        pool.shutdown().await.unwrap();
+    }
+
+
+    // Runs `jobs` 50ms jobs through the pool and returns how long they took.
+    async fn time_batch(pool: &WorkerPool<Job, u32>, jobs: u32) -> Duration {
+        let started = Instant::now();
+        let mut results = Vec::new();
+        for i in 0..jobs {
+            results.push(pool.submit(Job { id: i }).await.unwrap());
+        }
+        for result in results {
+            result.await.unwrap();
+        }
+        started.elapsed()
+    }
+
+    #[tokio::test]
+    async fn scaling_changes_the_worker_count_and_the_throughput() {
+        let pool = WorkerPool::new(2, |job: Job| async move {
+            time::sleep(Duration::from_millis(50)).await;
+            job.id
+        });
+        assert_eq!(pool.worker_count(), 2);
+        let with_two = time_batch(&pool, 10).await;
+
+        pool.scale_to(5).await.unwrap();
+        assert_eq!(pool.worker_count(), 5);
+        let with_five = time_batch(&pool, 10).await;
+
+        pool.scale_to(1).await.unwrap();
+        assert_eq!(pool.worker_count(), 1);
+        let with_one = time_batch(&pool, 10).await;
+
+        // Ideally 250ms, 100ms and 500ms.
+        assert!(with_five < with_two && with_two < with_one, "{:?} {:?} {:?}", with_two, with_five, with_one);
+        assert!(with_one >= Duration::from_millis(500));
+        pool.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn shrinking_lets_running_jobs_finish() {
+        let pool = WorkerPool::new(3, |job: Job| async move {
+            time::sleep(Duration::from_millis(100)).await;
+            job.id
+        });
+        let mut results = Vec::new();
+        for i in 0..3 {
+            results.push(pool.submit(Job { id: i }).await.unwrap());
+        }
+        time::sleep(Duration::from_millis(20)).await; // let all three start
+
+        pool.scale_to(1).await.unwrap();
+        assert_eq!(pool.worker_count(), 1);
+        for (i, result) in results.into_iter().enumerate() {
+            assert_eq!(result.await.unwrap(), i as u32);
+        }
+        pool.shutdown().await.unwrap();
+    }
+
+
+    #[tokio::test]
+    async fn a_timed_out_job_is_abandoned_and_the_worker_moves_on() {
+        let config = PoolConfig {
+            num_workers: 1,
+            job_timeout: Some(Duration::from_millis(50)),
+            ..PoolConfig::default()
+        };
+        let pool = WorkerPool::with_config(config, |job: Job| async move {
+            let work = if job.id == 0 { Duration::from_secs(60) } else { Duration::from_millis(5) };
+            time::sleep(work).await;
+            job.id
+        });
+        let started = Instant::now();
+        let stuck = pool.submit(Job { id: 0 }).await.unwrap();
+        let mut later = Vec::new();
+        for i in 1..4 {
+            later.push(pool.submit(Job { id: i }).await.unwrap());
+        }
+
+        assert!(stuck.await.is_err(), "the stuck job produces no result");
+        for (i, result) in later.into_iter().enumerate() {
+            assert_eq!(result.await.unwrap(), i as u32 + 1);
+        }
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let errors = pool.errors();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], JobError::JobTimedOut { job, .. } if job == "Job { id: 0 }"));
+        pool.shutdown().await.unwrap();
+    }
+
+
+    #[tokio::test]
+    async fn higher_priority_jobs_run_first_and_ties_stay_fifo() {
+        let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let pool = PriorityWorkerPool::new(1, {
+            let order = Arc::clone(&order);
+            move |job: Job| {
+                let order = Arc::clone(&order);
+                async move {
+                    let work = if job.id == 0 { 50 } else { 1 };
+                    time::sleep(Duration::from_millis(work)).await;
+                    order.lock().unwrap().push(job.id);
+                }
+            }
+        });
+        // Job 0 occupies the worker while the rest queue up behind it.
+        pool.submit(Job { id: 0 }, 5);
+        time::sleep(Duration::from_millis(10)).await;
+        for i in 1..=6 {
+            let priority = if i % 2 == 0 { 9 } else { 1 };
+            pool.submit(Job { id: i }, priority);
+        }
+
+        pool.shutdown().await.unwrap();
+        assert_eq!(*order.lock().unwrap(), [0, 2, 4, 6, 1, 3, 5]);
+    }
+
+    #[tokio::test]
+    async fn submit_returns_the_jobs_result() {
+        let pool = PriorityWorkerPool::new(2, |job: Job| async move { job.id * 2 });
+        let result = pool.submit(Job { id: 21 }, 0);
+        assert_eq!(result.await.unwrap(), 42);
+        pool.shutdown().await.unwrap();
+    }
+
+
+    fn one_slot_pool(num_workers: u32) -> WorkerPool<Job, u32> {
+        let config = PoolConfig { num_workers, queue_capacity: 1, ..PoolConfig::default() };
+        WorkerPool::with_config(config, |job: Job| async move {
+            time::sleep(Duration::from_millis(100)).await;
+            job.id
+        })
+    }
+
+    #[tokio::test]
+    async fn try_submit_on_a_full_queue_fails_fast_with_queue_full() {
+        let pool = one_slot_pool(1);
+        let running = pool.try_submit(Job { id: 0 }).unwrap();
+        time::sleep(Duration::from_millis(20)).await; // let the worker pick it up
+        let queued = pool.try_submit(Job { id: 1 }).unwrap();
+        assert_eq!(pool.queue_len(), 1);
+
+        let started = Instant::now();
+        assert_eq!(pool.try_submit(Job { id: 2 }).unwrap_err(), SubmitError::QueueFull);
+        assert!(started.elapsed() < Duration::from_millis(50));
+        assert_eq!(pool.rejected_count(), 1);
+
+        assert_eq!((running.await.unwrap(), queued.await.unwrap()), (0, 1));
+        assert_eq!(pool.queue_len(), 0);
+        pool.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn submitting_after_the_workers_are_gone_is_pool_shutdown() {
+        // With no workers nobody holds the receiver, so it can be closed
+        // here, as if every worker had exited.
+        let pool = one_slot_pool(0);
+        pool.ctx.rx.lock().await.close();
+
+        assert_eq!(pool.submit(Job { id: 0 }).await.unwrap_err(), SubmitError::PoolShutdown);
+        assert_eq!(pool.send_job(Job { id: 1 }).await.unwrap_err(), SubmitError::PoolShutdown);
+        assert_eq!(pool.try_submit(Job { id: 2 }).unwrap_err(), SubmitError::PoolShutdown);
+        assert_eq!(pool.rejected_count(), 0);
+        pool.shutdown().await.unwrap();
+    }
+}
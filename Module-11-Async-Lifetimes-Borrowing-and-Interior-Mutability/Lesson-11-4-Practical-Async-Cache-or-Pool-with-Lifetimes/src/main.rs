@@ -4,59 +4,237 @@
 // asynchronous cache or connection pool. This demonstrates how these concepts
 // work together in a real-world scenario.
 
+use std::borrow::Borrow;
 use std::collections::HashMap;
+use std::fmt::Debug;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, OnceCell};
 use tokio::time::{self, Duration};
 
 // --- The Cache Entry ---
 
-// For simplicity, our cache will store strings.
-// In a real cache, this would be a more complex data structure.
+// The cache stores any cloneable value `V`. `get` hands out a clone, so cheap
+// clones (numbers, `Arc`s) make for a cheap cache.
 
 #[derive(Debug, Clone)]
-struct CacheEntry {
-    value: String,
-    // In a real cache, you might have a timestamp for expiration,
-    // or other metadata.
+struct CacheEntry<V> {
+    value: V,
+    // How much this entry counts against the cache's weight budget.
+    weight: usize,
+    // Logical timestamp of the last insert or read, used for LRU eviction.
+    last_access: u64,
+}
+
+// --- Weighing Values ---
+
+// Bounding a cache by entry count is crude when values vary wildly in size.
+// A `Weigher` reports how "heavy" a value is (usually an estimate of its
+// memory footprint in bytes), so the cache can be bounded by total weight.
+
+pub trait Weigher<V>: Send + Sync {
+    fn weigh(&self, value: &V) -> usize;
+}
+
+// Only the inline size of the value. Heap data it points to isn't counted, so
+// this is exact for plain values like numbers and an underestimate otherwise.
+// It is the weigher a new cache starts with.
+pub struct SizeOfWeigher;
+
+impl<V> Weigher<V> for SizeOfWeigher {
+    fn weigh(&self, value: &V) -> usize {
+        std::mem::size_of_val(value)
+    }
+}
+
+// The inline size of the value plus the heap buffer a `String` owns.
+pub struct StringWeigher;
+
+impl Weigher<String> for StringWeigher {
+    fn weigh(&self, value: &String) -> usize {
+        std::mem::size_of_val(value) + value.capacity()
+    }
 }
 
 // --- The Cache ---
 
 // Our cache will be shared across multiple async tasks, so we need `Arc<Mutex<...>>`.
+// Cloning an `AsyncCache` is cheap: every clone shares the same underlying data.
 
-struct AsyncCache {
+struct AsyncCache<K, V> {
     // The actual cache data. Protected by a Mutex for concurrent access.
-    data: Arc<Mutex<HashMap<String, CacheEntry>>>,
+    data: Arc<Mutex<HashMap<K, CacheEntry<V>>>>,
+    // Sum of the weights of all entries. Only updated while `data` is locked.
+    total_weight: Arc<AtomicUsize>,
+    // Monotonic counter handing out `last_access` timestamps.
+    clock: Arc<AtomicU64>,
+    weigher: Arc<dyn Weigher<V>>,
+    max_weight: Option<usize>,
+    max_entries: Option<usize>,
+    // Loads in progress for `get_or_try_insert_with`, one cell per key.
+    loading: Arc<Mutex<HashMap<K, Arc<OnceCell<V>>>>>,
 }
 
-impl AsyncCache {
+// A derived `Clone` would demand `K: Clone` and `V: Clone` even though only
+// the `Arc`s are cloned, so it is written out by hand.
+impl<K, V> Clone for AsyncCache<K, V> {
+    fn clone(&self) -> Self {
+        AsyncCache {
+            data: Arc::clone(&self.data),
+            total_weight: Arc::clone(&self.total_weight),
+            clock: Arc::clone(&self.clock),
+            weigher: Arc::clone(&self.weigher),
+            max_weight: self.max_weight,
+            max_entries: self.max_entries,
+            loading: Arc::clone(&self.loading),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone + Debug, V: Clone> AsyncCache<K, V> {
     fn new() -> Self {
         AsyncCache {
             data: Arc::new(Mutex::new(HashMap::new())),
+            total_weight: Arc::new(AtomicUsize::new(0)),
+            clock: Arc::new(AtomicU64::new(0)),
+            weigher: Arc::new(SizeOfWeigher),
+            max_weight: None,
+            max_entries: None,
+            loading: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
+    // Bounds the cache by total weight. Inserting past the budget evicts the
+    // least-recently-used entries first.
+    fn with_max_weight(mut self, max_weight: usize) -> Self {
+        self.max_weight = Some(max_weight);
+        self
+    }
+
+    // Bounds the cache by entry count, also evicting least-recently-used
+    // entries first. Can be combined with `with_max_weight`.
+    fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    fn with_weigher(mut self, weigher: impl Weigher<V> + 'static) -> Self {
+        self.weigher = Arc::new(weigher);
+        self
+    }
+
+    // Current total weight of all cached values.
+    fn total_weight(&self) -> usize {
+        self.total_weight.load(Ordering::SeqCst)
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::SeqCst)
+    }
+
     // Inserts a key-value pair into the cache.
-    async fn insert(&self, key: String, value: String) {
+    async fn insert(&self, key: K, value: V) {
+        let weight = self.weigher.weigh(&value);
         let mut data = self.data.lock().await;
-        data.insert(key, CacheEntry { value });
+
+        if let Some(old) = data.remove(&key) {
+            self.total_weight.fetch_sub(old.weight, Ordering::SeqCst);
+        }
+
+        if self.max_weight.is_some_and(|max_weight| weight > max_weight) || self.max_entries == Some(0) {
+            // Caching it would mean evicting everything else; skip it.
+            println!("Cache: Value for {:?} exceeds the cache's bounds, not cached.", key);
+            return;
+        }
+        loop {
+            let over_weight = self.max_weight.is_some_and(|max_weight| self.total_weight() + weight > max_weight);
+            let over_count = self.max_entries.is_some_and(|max_entries| data.len() >= max_entries);
+            if !over_weight && !over_count {
+                break;
+            }
+            let lru_key = match data.iter().min_by_key(|(_, e)| e.last_access) {
+                Some((k, _)) => k.clone(),
+                None => break,
+            };
+            if let Some(evicted) = data.remove(&lru_key) {
+                self.total_weight.fetch_sub(evicted.weight, Ordering::SeqCst);
+                let budget = if over_weight { "weight budget" } else { "entry limit" };
+                println!("Cache: Evicted {:?} to stay within {}.", lru_key, budget);
+            }
+        }
+
+        let last_access = self.tick();
+        data.insert(key, CacheEntry { value, weight, last_access });
+        self.total_weight.fetch_add(weight, Ordering::SeqCst);
         println!("Cache: Inserted key.");
     }
 
-    // Retrieves a value from the cache.
-    async fn get(&self, key: &str) -> Option<CacheEntry> {
-        let data = self.data.lock().await;
-        data.get(key).cloned()
+    // Retrieves a value from the cache. Reading an entry marks it as recently used.
+    async fn get<Q>(&self, key: &Q) -> Option<CacheEntry<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut data = self.data.lock().await;
+        let last_access = self.tick();
+        let entry = data.get_mut(key)?;
+        entry.last_access = last_access;
+        Some(entry.clone())
+    }
+
+    // Returns the cached value, or runs `loader` to produce and cache it.
+    // Concurrent callers for the same missing key share one `OnceCell`, so
+    // the loader runs once and everyone else waits for its result instead of
+    // stampeding the backing store. If the load fails, the error goes to the
+    // caller whose loader ran, and the next waiter tries its own loader.
+    //
+    // A caller can miss the cache just before another load of the key
+    // finishes and removes its cell. It then gets a fresh cell, so the cache
+    // is checked again under the cell before `loader` runs.
+    async fn get_or_try_insert_with<F, Fut, E>(&self, key: K, loader: F) -> Result<V, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+    {
+        if let Some(entry) = self.get(&key).await {
+            return Ok(entry.value);
+        }
+
+        let cell = Arc::clone(self.loading.lock().await.entry(key.clone()).or_default());
+        let result = cell
+            .get_or_try_init(|| async {
+                if let Some(entry) = self.get(&key).await {
+                    return Ok(entry.value);
+                }
+                let value = loader().await?;
+                self.insert(key.clone(), value.clone()).await;
+                Ok(value)
+            })
+            .await
+            .cloned();
+
+        // The value is in the cache now (or the load failed), so the cell
+        // can go. Only remove it if nobody has replaced it in the meantime.
+        let mut loading = self.loading.lock().await;
+        if loading.get(&key).is_some_and(|current| Arc::ptr_eq(current, &cell)) {
+            loading.remove(&key);
+        }
+        result
     }
 
     // Simulates a cleanup task that runs in the background.
-    async fn run_cleanup_task(&self) {
+    async fn run_cleanup_task(&self)
+    where
+        K: Send + 'static,
+        V: Send + 'static,
+    {
         let data_clone = Arc::clone(&self.data);
         tokio::spawn(async move {
             loop {
                 time::sleep(Duration::from_secs(5)).await;
-                let mut data = data_clone.lock().await;
+                let data = data_clone.lock().await;
                 // In a real cleanup, you'd remove expired items.
                 println!("Cache: Running cleanup. Current size: {}", data.len());
             }
@@ -66,7 +244,8 @@ impl AsyncCache {
 
 #[tokio::main]
 async fn main() {
-    let cache = AsyncCache::new();
+    // `StringWeigher` counts each `String`'s heap buffer, not just its header.
+    let cache = AsyncCache::new().with_weigher(StringWeigher);
 
     // Start the cleanup task in the background.
     cache.run_cleanup_task().await;
@@ -75,14 +254,13 @@ async fn main() {
     let mut handles = vec![];
 
     for i in 0..5 {
-        let cache_clone = cache.data.clone(); // Clone the Arc to share the Mutex
+        let cache_clone = cache.clone(); // Clones the inner Arcs, sharing the same data
         let key = format!("key{}", i);
         let value = format!("value{}", i);
 
         let handle = tokio::spawn(async move {
-            let cache_instance = AsyncCache { data: cache_clone }; // Reconstruct AsyncCache for the task
-            cache_instance.insert(key.clone(), value.clone()).await;
-            if let Some(entry) = cache_instance.get(&key).await {
+            cache_clone.insert(key.clone(), value.clone()).await;
+            if let Some(entry) = cache_clone.get(&key).await {
                 println!("Task {}: Retrieved {} for {}", i, entry.value, key);
             }
         });
@@ -98,4 +276,248 @@ async fn main() {
     time::sleep(Duration::from_secs(6)).await;
 
     println!("Main: Final cache state: {:?}", cache.data.lock().await);
+    println!("Main: Total weight: {} bytes", cache.total_weight());
+
+    // Values don't have to be strings. Here the cache holds a struct keyed by
+    // a number, and numbers keyed by `&str`.
+    #[derive(Debug, Clone)]
+    struct UserProfile {
+        name: String,
+        admin: bool,
+    }
+    let profiles: AsyncCache<u64, UserProfile> = AsyncCache::new();
+    profiles.insert(7, UserProfile { name: "Ada".to_string(), admin: true }).await;
+    if let Some(entry) = profiles.get(&7).await {
+        println!("Main: Profile 7 is {} (admin: {})", entry.value.name, entry.value.admin);
+    }
+    let counters: AsyncCache<&'static str, u64> = AsyncCache::new();
+    counters.insert("page_views", 1_024).await;
+    if let Some(entry) = counters.get("page_views").await {
+        println!("Main: page_views = {}", entry.value + 1);
+    }
+
+    // A cache bounded by weight rather than entry count. Each value below
+    // weighs its capacity plus the 24-byte `String` header.
+    let bounded = AsyncCache::new().with_weigher(StringWeigher).with_max_weight(300);
+    bounded.insert("small".to_string(), "a".repeat(16)).await;
+    bounded.insert("medium".to_string(), "b".repeat(100)).await;
+    // Touch "small" so "medium" becomes the least recently used entry.
+    bounded.get("small").await;
+    // This value needs room, so "medium" is evicted first.
+    bounded.insert("large".to_string(), "c".repeat(200)).await;
+    println!(
+        "Main: Bounded cache has small={}, medium={}, large={}, weight={}",
+        bounded.get("small").await.is_some(),
+        bounded.get("medium").await.is_some(),
+        bounded.get("large").await.is_some(),
+        bounded.total_weight()
+    );
+
+    // A cache bounded by entry count. After touching "a", "b" is the least
+    // recently used entry, so inserting a fourth key evicts it.
+    let lru: AsyncCache<&'static str, u32> = AsyncCache::new().with_max_entries(3);
+    lru.insert("a", 1).await;
+    lru.insert("b", 2).await;
+    lru.insert("c", 3).await;
+    lru.get("a").await;
+    lru.insert("d", 4).await;
+    let mut remaining: Vec<_> = lru.data.lock().await.keys().copied().collect();
+    remaining.sort_unstable();
+    println!("Main: LRU cache kept {:?}", remaining);
+
+    // On a miss, `get_or_try_insert_with` runs the loader and caches its
+    // result, so the second call below is served from the cache. Concurrent
+    // callers for one key share a single load (see the tests).
+    let config: AsyncCache<String, String> = AsyncCache::new();
+    for call in 1..=2 {
+        let value = config
+            .get_or_try_insert_with("config".to_string(), || async move {
+                println!("Main: Loading config for call {}", call);
+                Ok::<_, std::io::Error>("max_connections=10".to_string())
+            })
+            .await
+            .unwrap();
+        println!("Main: Call {} got {}", call, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A loader that counts its runs and takes a while, so concurrent callers
+    // overlap with it.
+    async fn slow_load(loads: Arc<AtomicUsize>, value: String) -> Result<String, std::io::Error> {
+        loads.fetch_add(1, Ordering::SeqCst);
+        time::sleep(Duration::from_millis(50)).await;
+        Ok(value)
+    }
+
+    #[tokio::test]
+    async fn concurrent_callers_for_a_cold_key_run_the_loader_once() {
+        let cache: AsyncCache<String, String> = AsyncCache::new();
+        let loads = Arc::new(AtomicUsize::new(0));
+        let mut handles = vec![];
+        for i in 0..10 {
+            let cache = cache.clone();
+            let loads = Arc::clone(&loads);
+            handles.push(tokio::spawn(async move {
+                cache
+                    .get_or_try_insert_with("config".to_string(), || slow_load(loads, format!("loaded by task {}", i)))
+                    .await
+                    .unwrap()
+            }));
+        }
+        let mut values = vec![];
+        for handle in handles {
+            values.push(handle.await.unwrap());
+        }
+
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        values.dedup();
+        assert_eq!(values.len(), 1, "every task should see the same value: {:?}", values);
+        assert_eq!(cache.get("config").await.unwrap().value, values[0]);
+        assert!(cache.loading.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_load_that_finished_after_the_miss_is_not_repeated() {
+        let cache: AsyncCache<String, String> = AsyncCache::new();
+        let loads = Arc::new(AtomicUsize::new(0));
+
+        // Hold the cell map so the caller stops between its cache miss and
+        // taking a cell, then let another load "finish" in that gap.
+        let loading = cache.loading.lock().await;
+        let caller = tokio::spawn({
+            let cache = cache.clone();
+            let loads = Arc::clone(&loads);
+            async move {
+                cache
+                    .get_or_try_insert_with("config".to_string(), || slow_load(loads, "loaded again".to_string()))
+                    .await
+                    .unwrap()
+            }
+        });
+        time::sleep(Duration::from_millis(20)).await;
+        cache.insert("config".to_string(), "loaded first".to_string()).await;
+        drop(loading);
+
+        assert_eq!(caller.await.unwrap(), "loaded first");
+        assert_eq!(loads.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn a_failed_load_is_not_cached_and_the_next_caller_retries() {
+        let cache: AsyncCache<&'static str, u32> = AsyncCache::new();
+        let failed = cache
+            .get_or_try_insert_with("answer", || async { Err::<u32, _>("backend down") })
+            .await;
+        assert_eq!(failed, Err("backend down"));
+        assert!(cache.get("answer").await.is_none());
+
+        let loaded = cache.get_or_try_insert_with("answer", || async { Ok::<_, &str>(42) }).await;
+        assert_eq!(loaded, Ok(42));
+        assert_eq!(cache.get("answer").await.unwrap().value, 42);
+    }
+
+
+    fn string_weight(len: usize) -> usize {
+        std::mem::size_of::<String>() + len
+    }
+
+    #[tokio::test]
+    async fn large_values_evict_the_least_recently_used_entries_to_fit_the_budget() {
+        let cache = AsyncCache::new().with_weigher(StringWeigher).with_max_weight(300);
+        cache.insert("small", "a".repeat(16)).await;
+        cache.insert("medium", "b".repeat(100)).await;
+        cache.get("small").await; // "medium" is now the least recently used
+        cache.insert("large", "c".repeat(200)).await;
+
+        assert!(cache.get("small").await.is_some());
+        assert!(cache.get("medium").await.is_none());
+        assert!(cache.get("large").await.is_some());
+        assert_eq!(cache.total_weight(), string_weight(16) + string_weight(200));
+    }
+
+    #[tokio::test]
+    async fn a_value_heavier_than_the_whole_budget_is_not_cached() {
+        let cache = AsyncCache::new().with_weigher(StringWeigher).with_max_weight(100);
+        cache.insert("small", "a".repeat(10)).await;
+        cache.insert("huge", "z".repeat(1_000)).await;
+
+        assert!(cache.get("huge").await.is_none());
+        assert!(cache.get("small").await.is_some(), "nothing is evicted for a value that can't fit");
+        assert_eq!(cache.total_weight(), string_weight(10));
+    }
+
+    #[tokio::test]
+    async fn replacing_a_value_replaces_its_weight() {
+        let cache = AsyncCache::new().with_weigher(StringWeigher);
+        cache.insert("key", "a".repeat(50)).await;
+        cache.insert("key", "b".repeat(5)).await;
+        assert_eq!(cache.total_weight(), string_weight(5));
+    }
+
+    #[test]
+    fn the_default_weigher_counts_a_strings_heap_buffer() {
+        let value = String::with_capacity(64);
+        assert_eq!(StringWeigher.weigh(&value), string_weight(64));
+        assert_eq!(Weigher::<String>::weigh(&SizeOfWeigher, &value), string_weight(0));
+        assert_eq!(SizeOfWeigher.weigh(&7u64), 8);
+    }
+
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct UserProfile {
+        name: String,
+        admin: bool,
+    }
+
+    #[tokio::test]
+    async fn the_cache_stores_struct_values() {
+        let cache: AsyncCache<u64, UserProfile> = AsyncCache::new();
+        let ada = UserProfile { name: "Ada".to_string(), admin: true };
+        cache.insert(7, ada.clone()).await;
+
+        assert_eq!(cache.get(&7).await.unwrap().value, ada);
+        assert!(cache.get(&8).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn the_cache_stores_numbers_under_borrowed_keys() {
+        let cache: AsyncCache<String, u64> = AsyncCache::new();
+        cache.insert("page_views".to_string(), 1_024).await;
+        // A `String` key can be looked up by `&str` through `Borrow`.
+        assert_eq!(cache.get("page_views").await.unwrap().value + 1, 1_025);
+    }
+
+
+    async fn keys(cache: &AsyncCache<&'static str, u32>) -> Vec<&'static str> {
+        let mut keys: Vec<_> = cache.data.lock().await.keys().copied().collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    #[tokio::test]
+    async fn the_least_recently_used_key_is_evicted_at_capacity() {
+        let cache: AsyncCache<&'static str, u32> = AsyncCache::new().with_max_entries(3);
+        cache.insert("a", 1).await;
+        cache.insert("b", 2).await;
+        cache.insert("c", 3).await;
+        cache.get("a").await; // "b" is now the least recently used
+        cache.insert("d", 4).await;
+
+        assert_eq!(keys(&cache).await, ["a", "c", "d"]);
+    }
+
+    #[tokio::test]
+    async fn overwriting_an_existing_key_evicts_nothing() {
+        let cache: AsyncCache<&'static str, u32> = AsyncCache::new().with_max_entries(2);
+        cache.insert("a", 1).await;
+        cache.insert("b", 2).await;
+        cache.insert("a", 10).await;
+
+        assert_eq!(keys(&cache).await, ["a", "b"]);
+        assert_eq!(cache.get("a").await.unwrap().value, 10);
+    }
 }
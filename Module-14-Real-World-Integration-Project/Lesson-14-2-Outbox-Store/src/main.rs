@@ -10,21 +10,188 @@
 // First, let's define the trait that our outbox store implementations will
 // adhere to. This trait will be part of our `outbox_core` crate.
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Event {
     pub id: String,
     pub payload: String,
     pub processed: bool,
+    // When the event was created. Stores return unprocessed events oldest
+    // first so downstream processing is FIFO.
+    pub created_at: DateTime<Utc>,
+    // How many delivery attempts have failed, and why the last one did. The
+    // relayer can move events past a retry limit to a dead-letter path.
+    pub retry_count: u32,
+    pub last_error: Option<String>,
 }
 
+impl Event {
+    // A new, unprocessed event created now.
+    pub fn new(id: &str, payload: &str) -> Self {
+        Event {
+            id: id.to_string(),
+            payload: payload.to_string(),
+            processed: false,
+            created_at: Utc::now(),
+            retry_count: 0,
+            last_error: None,
+        }
+    }
+
+    // Builder-style helper for constructing an already-processed event,
+    // e.g. `Event::new("1", "UserCreated").processed()`.
+    pub fn processed(mut self) -> Self {
+        self.processed = true;
+        self
+    }
+
+    pub fn record_failure(&mut self, err: &str) {
+        self.retry_count += 1;
+        self.last_error = Some(err.to_string());
+    }
+}
+
+// --- Store Errors ---
+
+// The trait methods return `anyhow::Result`, but failures a caller may want to
+// react to are returned as a typed `OutboxError` inside the `anyhow::Error`
+// (recover it with `err.downcast_ref::<OutboxError>()`).
+
+#[derive(Debug, thiserror::Error)]
+pub enum OutboxError {
+    #[error("payload is {size} bytes, which exceeds the {limit} byte limit")]
+    PayloadTooLarge { size: usize, limit: usize },
+    // Returned when acknowledging an id that was never stored, which usually
+    // means the relayer and the store disagree about what was sent.
+    #[error("event {id} not found")]
+    EventNotFound { id: String },
+    // A line of the outbox file that doesn't decode, or, with checksums on,
+    // whose checksum is missing or wrong. `line_number` is 1-based.
+    #[error("outbox line {line_number} is corrupt")]
+    CorruptEvent { line_number: usize },
+}
+
+impl OutboxError {
+    // None of these go away by trying again: the payload stays too large,
+    // the id stays unknown and the line stays corrupt until it is repaired.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            OutboxError::PayloadTooLarge { .. } => false,
+            OutboxError::EventNotFound { .. } => false,
+            OutboxError::CorruptEvent { .. } => false,
+        }
+    }
+}
+
+// A predicate over events, as taken by `get_unprocessed_matching`. It is a
+// trait object so `OutboxStore` stays usable as `dyn OutboxStore`.
+pub type EventFilter = dyn Fn(&Event) -> bool + Send + Sync;
+
 #[async_trait]
 pub trait OutboxStore: Send + Sync {
     async fn save_event(&self, event: Event) -> Result<()>;
+    // Saves a batch of events. The default just calls `save_event` for each
+    // one; stores that can write a batch in one go should override it.
+    async fn save_events(&self, events: Vec<Event>) -> Result<()> {
+        for event in events {
+            self.save_event(event).await?;
+        }
+        Ok(())
+    }
     async fn get_unprocessed_events(&self) -> Result<Vec<Event>>;
+    // Unprocessed events for which `predicate` returns true, oldest first.
+    // Lets a consumer route by event type, e.g. payloads prefixed `order:`.
+    // The default filters `get_unprocessed_events`; stores
+    // that can filter while scanning should override it.
+    async fn get_unprocessed_matching(&self, predicate: &EventFilter) -> Result<Vec<Event>> {
+        let mut events = self.get_unprocessed_events().await?;
+        events.retain(|e| predicate(e));
+        Ok(events)
+    }
+    // Fails with `OutboxError::EventNotFound` if no event has this id.
     async fn mark_event_processed(&self, event_id: &str) -> Result<()>;
+    // Marks a batch of events processed, e.g. after the relayer has sent them
+    // to the broker together. Returns how many unprocessed events were
+    // flipped; ids that are unknown or already processed are skipped.
+    async fn mark_events_processed(&self, ids: &[String]) -> Result<usize> {
+        let pending = self.get_unprocessed_events().await?;
+        let mut flipped = 0;
+        for id in ids {
+            if pending.iter().any(|e| &e.id == id) {
+                self.mark_event_processed(id).await?;
+                flipped += 1;
+            }
+        }
+        Ok(flipped)
+    }
+    // Deletes the given events outright. Returns how many were removed.
+    async fn remove_events(&self, ids: &[String]) -> Result<usize>;
+    // Whether an event with this id is stored, processed or not.
+    async fn contains_event(&self, id: &str) -> Result<bool>;
+    // Records a failed delivery attempt on the stored event, like
+    // `Event::record_failure`, and returns the new `retry_count`. Keeping the
+    // count in the store lets the relayer dead-letter an event by its total
+    // failures across passes and restarts. Fails with
+    // `OutboxError::EventNotFound` for an unknown id.
+    async fn record_failure(&self, id: &str, error: &str) -> Result<u32>;
+    // Flips a processed event back to unprocessed so the relayer sends it
+    // again, e.g. to replay events after a downstream bug. Returns false if
+    // it was already unprocessed. Fails with `OutboxError::EventNotFound`
+    // for an unknown id.
+    async fn reset_event(&self, id: &str) -> Result<bool>;
+    // Flips every processed event back to unprocessed. Returns how many
+    // changed.
+    async fn reset_all_processed(&self) -> Result<usize>;
+
+    // Moves the unprocessed events with the given ids into `dest`. Events are
+    // copied first and only then removed from `self`, so a failure never
+    // loses an event. If the removal fails, the copies are taken back out of
+    // `dest` so the event doesn't end up in both stores. An id `dest`
+    // already has is left where it is in both stores and not counted, so a
+    // rollback only ever removes copies this call made.
+    async fn transfer_to(&self, dest: &dyn OutboxStore, ids: &[String]) -> Result<usize> {
+        let mut to_move = Vec::new();
+        for event in self.get_unprocessed_events().await? {
+            if ids.contains(&event.id) && !dest.contains_event(&event.id).await? {
+                to_move.push(event);
+            }
+        }
+
+        let mut copied = Vec::new();
+        for event in &to_move {
+            if let Err(e) = dest.save_event(event.clone()).await {
+                return Err(roll_back_copies(dest, &copied, e).await);
+            }
+            copied.push(event.id.clone());
+        }
+
+        if let Err(e) = self.remove_events(&copied).await {
+            return Err(roll_back_copies(dest, &copied, e).await);
+        }
+        Ok(copied.len())
+    }
+}
+
+// Undoes the copies of a failed `transfer_to`. The caller needs the error
+// that stopped the transfer, so a failed rollback is attached to it as
+// context rather than returned in its place.
+async fn roll_back_copies(
+    dest: &dyn OutboxStore,
+    copied: &[String],
+    err: anyhow::Error,
+) -> anyhow::Error {
+    match dest.remove_events(copied).await {
+        Ok(_) => err,
+        Err(rollback) => err.context(format!(
+            "rolling back {} copied events also failed: {:#}",
+            copied.len(),
+            rollback
+        )),
+    }
 }
 
 // --- File-based Outbox Store Implementation ---
@@ -32,21 +199,115 @@ pub trait OutboxStore: Send + Sync {
 // This is a simple implementation for demonstration purposes. In a real
 // application, you would likely use a more robust storage solution.
 
+use futures::stream::{self, Stream, TryStreamExt};
 use tokio::fs::{self, OpenOptions};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::time::{self, Duration};
+use tracing::{warn, Instrument};
+
+// What happens to a row once its event is marked processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionPolicy {
+    // Keep the row with `processed = true` (the default). Good for auditing
+    // and replay, but the file grows until it is compacted.
+    KeepProcessed,
+    // Delete the row. With `after: Some(delay)` the row is first marked
+    // processed and only removed once `delay` has passed, leaving a window in
+    // which it can still be inspected or replayed.
+    DeleteOnProcess { after: Option<Duration> },
+}
 
 pub struct FileOutboxStore {
     file_path: String,
+    max_payload_bytes: Option<usize>,
+    retention: RetentionPolicy,
+    codec: Arc<dyn EventCodec>,
+    // When set, saving an event whose id is already in the file is a no-op.
+    idempotent: bool,
+    // When set, every line carries a CRC32 suffix that is checked on read.
+    checksums: bool,
+    // Every read-modify-write of the file holds this, so two tasks sharing
+    // the store can't interleave a read and a rewrite and lose an update.
+    write_lock: Arc<tokio::sync::Mutex<()>>,
+    // Set by `close`; checked by the debug-build `Drop` impl below.
+    closed: bool,
 }
 
 impl FileOutboxStore {
+    // Uses the pipe-delimited `PipeCodec` line format.
     pub fn new(file_path: &str) -> Self {
-        FileOutboxStore { file_path: file_path.to_string() }
+        Self::with_codec(file_path, PipeCodec)
+    }
+
+    pub fn with_codec(file_path: &str, codec: impl EventCodec + 'static) -> Self {
+        FileOutboxStore {
+            file_path: file_path.to_string(),
+            max_payload_bytes: None,
+            retention: RetentionPolicy::KeepProcessed,
+            codec: Arc::new(codec),
+            idempotent: false,
+            checksums: false,
+            write_lock: Arc::new(tokio::sync::Mutex::new(())),
+            closed: false,
+        }
+    }
+
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    // Makes saves idempotent: an event whose id already exists in the file
+    // (processed or not) is skipped instead of appended, so a producer that
+    // retries a save can't make the relayer send the event twice. Rows that
+    // retention has already deleted can't be detected and are saved again.
+    pub fn with_idempotent_saves(mut self) -> Self {
+        self.idempotent = true;
+        self
+    }
+
+    // Writes a CRC32 checksum at the end of every line and verifies it on
+    // read. A line that doesn't decode is always reported, but damage that
+    // still decodes (a changed byte in a payload, say) is only caught with a
+    // checksum. Every line must carry one, so lines written before this was
+    // turned on count as corrupt: only enable it for a new file.
+    pub fn with_checksums(mut self) -> Self {
+        self.checksums = true;
+        self
+    }
+
+    // Rust has no async `Drop`, so durability on shutdown has to be explicit.
+    // `close` forces everything written so far onto disk with `sync_all`
+    // (an fsync), so it survives a crash or power loss after this returns.
+    pub async fn close(mut self) -> Result<()> {
+        if fs::metadata(&self.file_path).await.is_ok() {
+            let file = OpenOptions::new().write(true).open(&self.file_path).await?;
+            file.sync_all().await?;
+        }
+        self.closed = true;
+        Ok(())
+    }
+
+    // Rejects events whose payload is larger than `limit` bytes, so oversized
+    // messages fail at the producer instead of at the broker.
+    pub fn with_max_payload_bytes(mut self, limit: usize) -> Self {
+        self.max_payload_bytes = Some(limit);
+        self
+    }
+
+    fn check_payload_size(&self, event: &Event) -> Result<()> {
+        if let Some(limit) = self.max_payload_bytes {
+            let size = event.payload.len();
+            if size > limit {
+                return Err(OutboxError::PayloadTooLarge { size, limit }.into());
+            }
+        }
+        Ok(())
     }
 
     async fn read_all_events(&self) -> Result<Vec<Event>> {
         let mut events = Vec::new();
-        if !fs::metadata(&self.file_path).await.is_ok() {
+        if fs::metadata(&self.file_path).await.is_err() {
             return Ok(events); // File doesn't exist yet
         }
 
@@ -54,137 +315,2412 @@ impl FileOutboxStore {
         let reader = BufReader::new(file);
         let mut lines = reader.lines();
 
+        let mut line_number = 0;
         while let Some(line) = lines.next_line().await? {
-            let parts: Vec<&str> = line.splitn(3, '|').collect();
-            if parts.len() == 3 {
-                events.push(Event {
-                    id: parts[0].to_string(),
-                    payload: parts[1].to_string(),
-                    processed: parts[2].parse().unwrap_or(false),
-                });
-            }
+            line_number += 1;
+            events.push(Self::decode_line(
+                self.codec.as_ref(),
+                self.checksums,
+                &line,
+                line_number,
+            )?);
         }
         Ok(events)
     }
 
+    // Decodes one line of the file. With checksums, the suffix is checked
+    // and stripped first. Any failure is a `CorruptEvent`, and the callers
+    // fail the read rather than skip the line: a skipped line would be gone
+    // for good after the next rewrite of the file. `repair` moves such lines
+    // out of the way.
+    fn decode_line(
+        codec: &dyn EventCodec,
+        checksums: bool,
+        line: &str,
+        line_number: usize,
+    ) -> std::result::Result<Event, OutboxError> {
+        let body = if checksums {
+            strip_checksum(line)
+        } else {
+            Some(line)
+        };
+        body.and_then(|body| codec.decode(body).ok())
+            .ok_or(OutboxError::CorruptEvent { line_number })
+    }
+
+    // Yields unprocessed events one line at a time instead of loading the
+    // whole file, so memory stays bounded however large the outbox gets.
+    // Events come back in file order; `get_unprocessed_events` collects this
+    // stream and sorts it by `created_at`.
+    pub fn unprocessed_stream(&self) -> impl Stream<Item = Result<Event>> {
+        let path = self.file_path.clone();
+        let codec = self.codec.clone();
+        let checksums = self.checksums;
+        // The state is the open file and the number of the last line read.
+        stream::try_unfold(
+            None,
+            move |state: Option<(Lines<BufReader<fs::File>>, usize)>| {
+                let path = path.clone();
+                let codec = codec.clone();
+                async move {
+                    let (mut lines, mut line_number) = match state {
+                        Some(state) => state,
+                        None => match fs::File::open(&path).await {
+                            Ok(file) => (BufReader::new(file).lines(), 0),
+                            // File doesn't exist yet
+                            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                            Err(e) => return Err(e.into()),
+                        },
+                    };
+                    while let Some(line) = lines.next_line().await? {
+                        line_number += 1;
+                        match Self::decode_line(codec.as_ref(), checksums, &line, line_number) {
+                            Ok(event) if !event.processed => {
+                                return Ok(Some((event, Some((lines, line_number)))))
+                            }
+                            Ok(_) => continue,
+                            Err(e) => return Err(e.into()),
+                        }
+                    }
+                    Ok(None)
+                }
+            },
+        )
+    }
+
+    // Marks every event in `ids` as processed in a single read-modify-write,
+    // honouring the retention policy. Returns how many of `ids` were found
+    // and how many unprocessed events were flipped.
+    async fn mark_processed(&self, ids: &[String]) -> Result<(usize, usize)> {
+        let _guard = self.write_lock.lock().await;
+        let mut events = self.read_all_events().await?;
+        let found = ids
+            .iter()
+            .filter(|id| events.iter().any(|e| &e.id == *id))
+            .count();
+        let flipped = events
+            .iter()
+            .filter(|e| !e.processed && ids.contains(&e.id))
+            .count();
+
+        if self.retention == (RetentionPolicy::DeleteOnProcess { after: None }) {
+            events.retain(|e| !ids.contains(&e.id));
+            self.write_all_events(&events).await?;
+            return Ok((found, flipped));
+        }
+
+        for event in &mut events {
+            if ids.contains(&event.id) {
+                event.processed = true;
+            }
+        }
+        self.write_all_events(&events).await?;
+
+        if let RetentionPolicy::DeleteOnProcess { after: Some(delay) } = self.retention {
+            let file_path = self.file_path.clone();
+            let codec = self.codec.clone();
+            let checksums = self.checksums;
+            let write_lock = self.write_lock.clone();
+            let ids = ids.to_vec();
+            let span = tracing::info_span!("delayed_delete", events = ids.len());
+            tokio::spawn(
+                async move {
+                    time::sleep(delay).await;
+                    let mut store = FileOutboxStore::new(&file_path);
+                    store.codec = codec;
+                    store.checksums = checksums;
+                    store.write_lock = write_lock;
+                    let guard = store.write_lock.clone().lock_owned().await;
+                    if let Ok(mut events) = store.read_all_events().await {
+                        // Only delete events nobody reset in the meantime.
+                        events.retain(|e| !(ids.contains(&e.id) && e.processed));
+                        if let Err(e) = store.write_all_events(&events).await {
+                            tracing::error!(?ids, error = %e, "failed to delete processed events");
+                        }
+                    }
+                    drop(guard);
+                    let _ = store.close().await;
+                }
+                .instrument(span),
+            );
+        }
+        Ok((found, flipped))
+    }
+
+    // Rewrites the file keeping only unprocessed events and returns how many
+    // processed rows were pruned. If nothing is processed the file is left
+    // untouched. The rewrite goes through `write_all_events`, so readers see
+    // either the old file or the compacted one.
+    pub async fn compact(&self) -> Result<usize> {
+        let _guard = self.write_lock.lock().await;
+        let events = self.read_all_events().await?;
+        let before = events.len();
+        let retained: Vec<Event> = events.into_iter().filter(|e| !e.processed).collect();
+        if retained.len() == before {
+            return Ok(0);
+        }
+        self.write_all_events(&retained).await?;
+        Ok(before - retained.len())
+    }
+
+    // Rewrites the file without processed events, passing every surviving
+    // event through `f` on the way. `f` can migrate or redact an event, or
+    // return `None` to drop it. Returns how many rows were removed.
+    pub async fn compact_with(&self, f: impl Fn(Event) -> Option<Event>) -> Result<usize> {
+        let _guard = self.write_lock.lock().await;
+        let events = self.read_all_events().await?;
+        let before = events.len();
+        let retained: Vec<Event> = events
+            .into_iter()
+            .filter(|e| !e.processed)
+            .filter_map(f)
+            .collect();
+        self.write_all_events(&retained).await?;
+        Ok(before - retained.len())
+    }
+
+    // Writes every event to a sibling `<path>.tmp` file and then renames it
+    // over the real file. A rename within one filesystem is atomic, so a crash
+    // mid-write leaves either the old file or the new one, never a truncated
+    // mix of both.
     async fn write_all_events(&self, events: &[Event]) -> Result<()> {
+        let tmp_path = format!("{}.tmp", self.file_path);
         let mut file = OpenOptions::new()
             .write(true)
             .truncate(true)
             .create(true)
-            .open(&self.file_path)
+            .open(&tmp_path)
             .await?;
 
         for event in events {
-            file.write_all(format!("{}|{}|{}
-", event.id, event.payload, event.processed).as_bytes()).await?;
+            let mut line = self.codec.encode(event);
+            if self.checksums {
+                append_checksum(&mut line);
+            }
+            line.push('\n');
+            file.write_all(line.as_bytes()).await?;
         }
+        // `tokio::fs::File` finishes writes in the background; flushing makes
+        // sure they have completed (and surfaces any error) before the rename.
+        file.flush().await?;
+        drop(file);
+        fs::rename(&tmp_path, &self.file_path).await?;
         Ok(())
     }
+
+    // Moves every corrupt line into `<path>.corrupt` and rewrites the file
+    // with the rest, so one damaged line no longer blocks every read. Lines
+    // are appended to the quarantine file, which is flushed before the
+    // rewrite, so a crash between the two leaves a copy rather than losing
+    // the line. Returns the quarantined line numbers.
+    pub async fn repair(&self) -> Result<Vec<usize>> {
+        let _guard = self.write_lock.lock().await;
+        if fs::metadata(&self.file_path).await.is_err() {
+            return Ok(Vec::new());
+        }
+        let file = fs::File::open(&self.file_path).await?;
+        let mut lines = BufReader::new(file).lines();
+
+        let mut events = Vec::new();
+        let mut corrupt = Vec::new();
+        let mut quarantined = String::new();
+        let mut line_number = 0;
+        while let Some(line) = lines.next_line().await? {
+            line_number += 1;
+            match Self::decode_line(self.codec.as_ref(), self.checksums, &line, line_number) {
+                Ok(event) => events.push(event),
+                Err(_) => {
+                    corrupt.push(line_number);
+                    quarantined.push_str(&line);
+                    quarantined.push('\n');
+                }
+            }
+        }
+        if corrupt.is_empty() {
+            return Ok(corrupt);
+        }
+
+        let mut quarantine = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(format!("{}.corrupt", self.file_path))
+            .await?;
+        quarantine.write_all(quarantined.as_bytes()).await?;
+        quarantine.sync_all().await?;
+        self.write_all_events(&events).await?;
+        Ok(corrupt)
+    }
+}
+
+// --- Event Codecs ---
+
+// An `EventCodec` turns an event into a single line of the outbox file and
+// back. `encode` must never produce a newline, since lines are the record
+// separator.
+
+pub trait EventCodec: Send + Sync {
+    fn encode(&self, event: &Event) -> String;
+    fn decode(&self, line: &str) -> Result<Event>;
+}
+
+// The pipe-delimited format. Lines are written as v2:
+// `v2|id|payload|processed|created_at|retry_count|last_error`, with an empty
+// `last_error` meaning none. Backslashes, `|` and newlines inside a field are
+// escaped (`\\`, `\|`, `\n`) so any payload survives a round-trip without
+// breaking the line or the field boundaries.
+//
+// Lines without a version marker are v1, the format used before versioning:
+// `id|payload|processed`, optionally followed by the later fields. `decode`
+// picks the parser per line, so one file can mix both.
+pub struct PipeCodec;
+
+pub const PIPE_FORMAT_VERSION: u32 = 2;
+
+// The number of fields in a v2 line, including the version marker.
+const PIPE_V2_FIELDS: usize = 7;
+
+impl PipeCodec {
+    // The version of a line, or `None` for an unversioned (v1) line. A v1
+    // line has at most six fields, so a v1 event whose id happens to be
+    // `v2` is never mistaken for a versioned one. Versions newer than
+    // `PIPE_FORMAT_VERSION` are accepted: they may only append fields.
+    fn line_version(parts: &[String]) -> Option<u32> {
+        if parts.len() < PIPE_V2_FIELDS {
+            return None;
+        }
+        parts[0]
+            .strip_prefix('v')?
+            .parse()
+            .ok()
+            .filter(|version| *version >= 2)
+    }
+
+    fn decode_v1(parts: &[String]) -> Result<Event> {
+        if parts.len() < 3 {
+            return Err(anyhow!("expected at least 3 fields, found {}", parts.len()));
+        }
+        Ok(Event {
+            id: parts[0].clone(),
+            payload: parts[1].clone(),
+            processed: parts[2].parse().unwrap_or(false),
+            // Lines written before `created_at` existed only have three
+            // fields; treat them as created now.
+            created_at: parts
+                .get(3)
+                .and_then(|ts| DateTime::parse_from_rfc3339(ts).ok())
+                .map(|ts| ts.with_timezone(&Utc))
+                .unwrap_or_else(Utc::now),
+            retry_count: parts.get(4).and_then(|n| n.parse().ok()).unwrap_or(0),
+            last_error: parts.get(5).filter(|err| !err.is_empty()).cloned(),
+        })
+    }
+
+    // Every v2 field is required, so a damaged line is rejected instead of
+    // silently defaulted. Fields past the v2 set (from newer writers) are
+    // ignored.
+    fn decode_v2(parts: &[String]) -> Result<Event> {
+        Ok(Event {
+            id: parts[1].clone(),
+            payload: parts[2].clone(),
+            processed: parts[3].parse().context("invalid processed flag")?,
+            created_at: DateTime::parse_from_rfc3339(&parts[4])
+                .context("invalid created_at")?
+                .with_timezone(&Utc),
+            retry_count: parts[5].parse().context("invalid retry_count")?,
+            last_error: Some(parts[6].clone()).filter(|err| !err.is_empty()),
+        })
+    }
+}
+
+impl EventCodec for PipeCodec {
+    fn encode(&self, event: &Event) -> String {
+        format!(
+            "v{}|{}|{}|{}|{}|{}|{}",
+            PIPE_FORMAT_VERSION,
+            escape_field(&event.id),
+            escape_field(&event.payload),
+            event.processed,
+            event.created_at.to_rfc3339(),
+            event.retry_count,
+            escape_field(event.last_error.as_deref().unwrap_or(""))
+        )
+    }
+
+    fn decode(&self, line: &str) -> Result<Event> {
+        let parts = split_escaped_fields(line);
+        match Self::line_version(&parts) {
+            None => Self::decode_v1(&parts),
+            Some(_) => Self::decode_v2(&parts),
+        }
+    }
+}
+
+// One JSON object per line. `serde_json` escapes newlines and quotes inside
+// strings, so the line structure can't be broken by a payload.
+pub struct JsonCodec;
+
+impl EventCodec for JsonCodec {
+    fn encode(&self, event: &Event) -> String {
+        serde_json::to_string(event).expect("Event always serializes to JSON")
+    }
+
+    fn decode(&self, line: &str) -> Result<Event> {
+        Ok(serde_json::from_str(line)?)
+    }
+}
+
+fn escape_field(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '|' => escaped.push_str("\\|"),
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Checksummed lines end in `#` and the CRC32 of the rest of the line as
+// eight hex digits. The suffix is split off from the right, so a `#` in the
+// encoded event itself is harmless.
+fn append_checksum(line: &mut String) {
+    let crc = crc32fast::hash(line.as_bytes());
+    line.push_str(&format!("#{:08x}", crc));
+}
+
+// The line without its checksum, or `None` if the checksum is missing or
+// doesn't match.
+fn strip_checksum(line: &str) -> Option<&str> {
+    let (body, crc) = line.rsplit_once('#')?;
+    if crc.len() != 8 {
+        return None;
+    }
+    let crc = u32::from_str_radix(crc, 16).ok()?;
+    (crc32fast::hash(body.as_bytes()) == crc).then_some(body)
+}
+
+// Splits a line on unescaped `|` and unescapes each field.
+fn split_escaped_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().unwrap();
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => field.push('\n'),
+                Some(other) => field.push(other),
+                None => field.push('\\'),
+            },
+            '|' => fields.push(String::new()),
+            _ => field.push(c),
+        }
+    }
+    fields
+}
+
+// In debug builds, warn about stores that were dropped without `close`, since
+// their last writes were never synced to disk.
+#[cfg(debug_assertions)]
+impl Drop for FileOutboxStore {
+    fn drop(&mut self) {
+        if !self.closed {
+            warn!(path = %self.file_path, "FileOutboxStore dropped without calling close(); recent writes may not be durable");
+        }
+    }
 }
 
 #[async_trait]
 impl OutboxStore for FileOutboxStore {
     async fn save_event(&self, event: Event) -> Result<()> {
+        self.save_events(vec![event]).await
+    }
+
+    // One read and one rewrite for the whole batch instead of one per event.
+    // Sizes are checked up front so an oversized event rejects the batch
+    // before anything is written. With idempotent saves, the duplicate check
+    // and the rewrite happen under the write lock, so no other save can slip
+    // the same id in between them.
+    async fn save_events(&self, new_events: Vec<Event>) -> Result<()> {
+        for event in &new_events {
+            self.check_payload_size(event)?;
+        }
+        let _guard = self.write_lock.lock().await;
         let mut events = self.read_all_events().await?;
-        events.push(event);
-        self.write_all_events(&events).await?;
+        let before = events.len();
+        for event in new_events {
+            if self.idempotent && events.iter().any(|e| e.id == event.id) {
+                continue;
+            }
+            events.push(event);
+        }
+        if events.len() > before {
+            self.write_all_events(&events).await?;
+        }
         Ok(())
     }
 
     async fn get_unprocessed_events(&self) -> Result<Vec<Event>> {
-        let events = self.read_all_events().await?;
-        Ok(events.into_iter().filter(|e| !e.processed).collect())
+        let mut unprocessed: Vec<Event> = self.unprocessed_stream().try_collect().await?;
+        unprocessed.sort_by_key(|e| e.created_at);
+        Ok(unprocessed)
+    }
+
+    // Filters during the single streaming pass, so events that don't match
+    // are dropped as soon as they are decoded instead of being collected.
+    async fn get_unprocessed_matching(&self, predicate: &EventFilter) -> Result<Vec<Event>> {
+        let mut matching: Vec<Event> = self
+            .unprocessed_stream()
+            .try_filter(|e| futures::future::ready(predicate(e)))
+            .try_collect()
+            .await?;
+        matching.sort_by_key(|e| e.created_at);
+        Ok(matching)
     }
 
     async fn mark_event_processed(&self, event_id: &str) -> Result<()> {
-        let mut events = self.read_all_events().await?;
-        for event in &mut events {
-            if event.id == event_id {
-                event.processed = true;
-                break;
+        let (found, _) = self.mark_processed(&[event_id.to_string()]).await?;
+        if found == 0 {
+            return Err(OutboxError::EventNotFound {
+                id: event_id.to_string(),
             }
+            .into());
         }
+        Ok(())
+    }
+
+    // Acknowledges the whole batch with one read and one rewrite.
+    async fn mark_events_processed(&self, ids: &[String]) -> Result<usize> {
+        let (_, flipped) = self.mark_processed(ids).await?;
+        Ok(flipped)
+    }
+
+    async fn remove_events(&self, ids: &[String]) -> Result<usize> {
+        let _guard = self.write_lock.lock().await;
+        let events = self.read_all_events().await?;
+        let before = events.len();
+        let kept: Vec<Event> = events
+            .into_iter()
+            .filter(|e| !ids.contains(&e.id))
+            .collect();
+        self.write_all_events(&kept).await?;
+        Ok(before - kept.len())
+    }
+
+    async fn contains_event(&self, id: &str) -> Result<bool> {
+        Ok(self.read_all_events().await?.iter().any(|e| e.id == id))
+    }
+
+    async fn record_failure(&self, id: &str, error: &str) -> Result<u32> {
+        let _guard = self.write_lock.lock().await;
+        let mut events = self.read_all_events().await?;
+        let Some(event) = events.iter_mut().find(|e| e.id == id) else {
+            return Err(OutboxError::EventNotFound { id: id.to_string() }.into());
+        };
+        event.record_failure(error);
+        let retry_count = event.retry_count;
+        self.write_all_events(&events).await?;
+        Ok(retry_count)
+    }
+
+    // Both resets are one locked read-modify-write. Rows that a
+    // `DeleteOnProcess` retention has removed are gone, so their ids are
+    // unknown here.
+    async fn reset_event(&self, id: &str) -> Result<bool> {
+        let _guard = self.write_lock.lock().await;
+        let mut events = self.read_all_events().await?;
+        let Some(event) = events.iter_mut().find(|e| e.id == id) else {
+            return Err(OutboxError::EventNotFound { id: id.to_string() }.into());
+        };
+        if !event.processed {
+            return Ok(false);
+        }
+        event.processed = false;
         self.write_all_events(&events).await?;
+        Ok(true)
+    }
+
+    async fn reset_all_processed(&self) -> Result<usize> {
+        let _guard = self.write_lock.lock().await;
+        let mut events = self.read_all_events().await?;
+        let mut reset = 0;
+        for event in events.iter_mut().filter(|e| e.processed) {
+            event.processed = false;
+            reset += 1;
+        }
+        if reset > 0 {
+            self.write_all_events(&events).await?;
+        }
+        Ok(reset)
+    }
+}
+
+// --- In-memory Outbox Store ---
+
+// Behaves like `FileOutboxStore` (with `KeepProcessed` retention) but keeps
+// events in a shared `Vec`. Useful for examples and tests that shouldn't
+// touch the filesystem. Clones share the same events, so a copy can be
+// handed to another task and both see each other's writes.
+
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Default)]
+pub struct InMemoryOutboxStore {
+    events: Arc<Mutex<Vec<Event>>>,
+}
+
+impl InMemoryOutboxStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl OutboxStore for InMemoryOutboxStore {
+    async fn save_event(&self, event: Event) -> Result<()> {
+        self.events.lock().unwrap().push(event);
         Ok(())
     }
+
+    async fn get_unprocessed_events(&self) -> Result<Vec<Event>> {
+        let events = self.events.lock().unwrap();
+        let mut unprocessed: Vec<Event> = events.iter().filter(|e| !e.processed).cloned().collect();
+        unprocessed.sort_by_key(|e| e.created_at);
+        Ok(unprocessed)
+    }
+
+    async fn mark_event_processed(&self, event_id: &str) -> Result<()> {
+        let mut events = self.events.lock().unwrap();
+        match events.iter_mut().find(|e| e.id == event_id) {
+            Some(event) => {
+                event.processed = true;
+                Ok(())
+            }
+            None => Err(OutboxError::EventNotFound {
+                id: event_id.to_string(),
+            }
+            .into()),
+        }
+    }
+
+    async fn remove_events(&self, ids: &[String]) -> Result<usize> {
+        let mut events = self.events.lock().unwrap();
+        let before = events.len();
+        events.retain(|e| !ids.contains(&e.id));
+        Ok(before - events.len())
+    }
+
+    async fn contains_event(&self, id: &str) -> Result<bool> {
+        Ok(self.events.lock().unwrap().iter().any(|e| e.id == id))
+    }
+
+    async fn record_failure(&self, id: &str, error: &str) -> Result<u32> {
+        let mut events = self.events.lock().unwrap();
+        match events.iter_mut().find(|e| e.id == id) {
+            Some(event) => {
+                event.record_failure(error);
+                Ok(event.retry_count)
+            }
+            None => Err(OutboxError::EventNotFound { id: id.to_string() }.into()),
+        }
+    }
+
+    async fn reset_event(&self, id: &str) -> Result<bool> {
+        let mut events = self.events.lock().unwrap();
+        match events.iter_mut().find(|e| e.id == id) {
+            Some(event) => Ok(std::mem::replace(&mut event.processed, false)),
+            None => Err(OutboxError::EventNotFound { id: id.to_string() }.into()),
+        }
+    }
+
+    async fn reset_all_processed(&self) -> Result<usize> {
+        let mut events = self.events.lock().unwrap();
+        let mut reset = 0;
+        for event in events.iter_mut().filter(|e| e.processed) {
+            event.processed = false;
+            reset += 1;
+        }
+        Ok(reset)
+    }
 }
 
-// --- Conceptual Database-backed Outbox Store (using sqlx) ---
+// --- Database-backed Outbox Store (using sqlx) ---
 
-// In a real application, you would use a database like PostgreSQL.
-// The `sqlx` crate provides an asynchronous, compile-time checked ORM.
+// In a real application, you would use a database like PostgreSQL. This store
+// is only compiled with `--features sqlx`. It uses the runtime-checked
+// `sqlx::query` API rather than `query!`, so building it doesn't need a live
+// database for compile-time checks.
 
-// pub struct SqlxOutboxStore {
-//     pool: sqlx::PgPool,
-// }
-//
-// impl SqlxOutboxStore {
-//     pub fn new(pool: sqlx::PgPool) -> Self {
-//         SqlxOutboxStore { pool }
-//     }
-// }
-//
-// #[async_trait]
-// impl OutboxStore for SqlxOutboxStore {
-//     async fn save_event(&self, event: Event) -> Result<()> {
-//         sqlx::query!(
-//             "INSERT INTO outbox (id, payload, processed) VALUES ($1, $2, $3)",
-//             event.id,
-//             event.payload,
-//             event.processed
-//         )
-//         .execute(&self.pool)
-//         .await?;
-//         Ok(())
-//     }
-//
-//     async fn get_unprocessed_events(&self) -> Result<Vec<Event>> {
-//         let records = sqlx::query_as!(Event,
-//             "SELECT id, payload, processed FROM outbox WHERE processed = FALSE"
-//         )
-//         .fetch_all(&self.pool)
-//         .await?;
-//         Ok(records)
-//     }
-//
-//     async fn mark_event_processed(&self, event_id: &str) -> Result<()> {
-//         sqlx::query!(
-//             "UPDATE outbox SET processed = TRUE WHERE id = $1",
-//             event_id
-//         )
-//         .execute(&self.pool)
-//         .await?;
-//         Ok(())
-//     }
-// }
+#[cfg(feature = "sqlx")]
+use sqlx::Row;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let file_store = FileOutboxStore::new("outbox_events.txt");
+#[cfg(feature = "sqlx")]
+pub struct SqlxOutboxStore {
+    pool: sqlx::PgPool,
+}
+
+#[cfg(feature = "sqlx")]
+impl SqlxOutboxStore {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        SqlxOutboxStore { pool }
+    }
+
+    // Creates the outbox table if it doesn't exist. Unprocessed events are
+    // returned by `created_at`, with `seq` (insertion order) breaking ties.
+    pub async fn migrate(&self) -> Result<()> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS outbox (
+                seq BIGSERIAL,
+                id TEXT PRIMARY KEY,
+                payload TEXT NOT NULL,
+                processed BOOLEAN NOT NULL DEFAULT FALSE,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                retry_count INTEGER NOT NULL DEFAULT 0,
+                last_error TEXT
+            )",
+        )
+        .execute(&self.pool)
+        .await?;
+        // Tables created by older versions get the newer columns added.
+        sqlx::query(
+            "ALTER TABLE outbox
+                ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                ADD COLUMN IF NOT EXISTS retry_count INTEGER NOT NULL DEFAULT 0,
+                ADD COLUMN IF NOT EXISTS last_error TEXT",
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
 
-    // Clean up previous run
-    let _ = fs::remove_file("outbox_events.txt").await;
+#[cfg(feature = "sqlx")]
+#[async_trait]
+impl OutboxStore for SqlxOutboxStore {
+    async fn save_event(&self, event: Event) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO outbox (id, payload, processed, created_at, retry_count, last_error)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&event.id)
+        .bind(&event.payload)
+        .bind(event.processed)
+        .bind(event.created_at)
+        // Postgres has no unsigned integers.
+        .bind(event.retry_count as i32)
+        .bind(&event.last_error)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
 
-    // Save some events
-    file_store.save_event(Event::new("1", "UserCreated")).await?;
-    file_store.save_event(Event::new("2", "OrderPlaced")).await?;
-    file_store.save_event(Event::new("3", "ProductUpdated")).await?;
+    async fn get_unprocessed_events(&self) -> Result<Vec<Event>> {
+        let rows = sqlx::query(
+            "SELECT id, payload, processed, created_at, retry_count, last_error
+             FROM outbox WHERE processed = FALSE ORDER BY created_at, seq",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let mut events = Vec::with_capacity(rows.len());
+        for row in rows {
+            events.push(Event {
+                id: row.try_get("id")?,
+                payload: row.try_get("payload")?,
+                processed: row.try_get("processed")?,
+                created_at: row.try_get("created_at")?,
+                retry_count: row.try_get::<i32, _>("retry_count")? as u32,
+                last_error: row.try_get("last_error")?,
+            });
+        }
+        Ok(events)
+    }
 
-    println!("Saved events.");
+    async fn mark_event_processed(&self, event_id: &str) -> Result<()> {
+        let result = sqlx::query("UPDATE outbox SET processed = TRUE WHERE id = $1")
+            .bind(event_id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(OutboxError::EventNotFound {
+                id: event_id.to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
 
-    // Get unprocessed events
-    let unprocessed = file_store.get_unprocessed_events().await?;
-    println!("Unprocessed events: {:?}", unprocessed);
+    async fn mark_events_processed(&self, ids: &[String]) -> Result<usize> {
+        let result = sqlx::query(
+            "UPDATE outbox SET processed = TRUE WHERE id = ANY($1) AND processed = FALSE",
+        )
+        .bind(ids)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() as usize)
+    }
 
-    // Mark an event as processed
-    file_store.mark_event_processed("2").await?;
+    async fn remove_events(&self, ids: &[String]) -> Result<usize> {
+        let result = sqlx::query("DELETE FROM outbox WHERE id = ANY($1)")
+            .bind(ids)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() as usize)
+    }
 
-    println!("Marked event 2 as processed.");
+    async fn contains_event(&self, id: &str) -> Result<bool> {
+        let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM outbox WHERE id = $1)")
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(exists)
+    }
 
-    // Get unprocessed events again
-    let unprocessed_after_mark = file_store.get_unprocessed_events().await?;
-    println!("Unprocessed events after marking: {:?}", unprocessed_after_mark);
+    async fn record_failure(&self, id: &str, error: &str) -> Result<u32> {
+        let retry_count: Option<i32> = sqlx::query_scalar(
+            "UPDATE outbox SET retry_count = retry_count + 1, last_error = $2 WHERE id = $1 RETURNING retry_count",
+        )
+        .bind(id)
+        .bind(error)
+        .fetch_optional(&self.pool)
+        .await?;
+        match retry_count {
+            Some(retry_count) => Ok(retry_count as u32),
+            None => Err(OutboxError::EventNotFound { id: id.to_string() }.into()),
+        }
+    }
 
-    Ok(())
+    async fn reset_event(&self, id: &str) -> Result<bool> {
+        let result =
+            sqlx::query("UPDATE outbox SET processed = FALSE WHERE id = $1 AND processed = TRUE")
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+        if result.rows_affected() > 0 {
+            return Ok(true);
+        }
+        // Nothing changed: either already unprocessed, or not there at all.
+        let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM outbox WHERE id = $1)")
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await?;
+        if !exists {
+            return Err(OutboxError::EventNotFound { id: id.to_string() }.into());
+        }
+        Ok(false)
+    }
+
+    async fn reset_all_processed(&self) -> Result<usize> {
+        let result = sqlx::query("UPDATE outbox SET processed = FALSE WHERE processed = TRUE")
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() as usize)
+    }
+}
+
+// --- Redis-backed Outbox Store ---
+
+// A file can't be shared safely between processes, but Redis can. Compiled
+// with `--features redis`, this store keeps each event as a hash under
+// `<prefix>:event:<id>` and the ids of unprocessed events in a sorted set,
+// `<prefix>:unprocessed`, scored by creation time. Several relay instances
+// can then drain one outbox.
+
+#[cfg(feature = "redis")]
+use redis::AsyncCommands;
+
+#[cfg(feature = "redis")]
+pub struct RedisOutboxStore {
+    conn: redis::aio::MultiplexedConnection,
+    prefix: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisOutboxStore {
+    pub async fn connect(url: &str, prefix: &str) -> Result<Self> {
+        let client = redis::Client::open(url)?;
+        let conn = client.get_multiplexed_async_connection().await?;
+        Ok(RedisOutboxStore {
+            conn,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    fn event_key(&self, id: &str) -> String {
+        format!("{}:event:{}", self.prefix, id)
+    }
+
+    fn unprocessed_key(&self) -> String {
+        format!("{}:unprocessed", self.prefix)
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl OutboxStore for RedisOutboxStore {
+    async fn save_event(&self, event: Event) -> Result<()> {
+        let mut conn = self.conn.clone();
+        // MULTI/EXEC so the hash and the set entry appear together.
+        let mut pipe = redis::pipe();
+        pipe.atomic().hset_multiple(
+            self.event_key(&event.id),
+            &[
+                ("payload", event.payload.clone()),
+                ("processed", event.processed.to_string()),
+                ("created_at", event.created_at.to_rfc3339()),
+                ("retry_count", event.retry_count.to_string()),
+                ("last_error", event.last_error.clone().unwrap_or_default()),
+            ],
+        );
+        if !event.processed {
+            pipe.zadd(
+                self.unprocessed_key(),
+                &event.id,
+                event.created_at.timestamp_millis(),
+            );
+        }
+        pipe.query_async::<()>(&mut conn).await?;
+        Ok(())
+    }
+
+    async fn get_unprocessed_events(&self) -> Result<Vec<Event>> {
+        let mut conn = self.conn.clone();
+        let ids: Vec<String> = conn.zrange(self.unprocessed_key(), 0, -1).await?;
+        let mut events = Vec::with_capacity(ids.len());
+        for id in ids {
+            let fields: std::collections::HashMap<String, String> =
+                conn.hgetall(self.event_key(&id)).await?;
+            // The hash can disappear between the two reads if another
+            // instance removed the event.
+            if fields.is_empty() {
+                continue;
+            }
+            events.push(Event {
+                payload: fields.get("payload").cloned().unwrap_or_default(),
+                processed: fields.get("processed").is_some_and(|p| p == "true"),
+                created_at: fields
+                    .get("created_at")
+                    .and_then(|ts| DateTime::parse_from_rfc3339(ts).ok())
+                    .map(|ts| ts.with_timezone(&Utc))
+                    .unwrap_or_else(Utc::now),
+                retry_count: fields
+                    .get("retry_count")
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or(0),
+                last_error: fields
+                    .get("last_error")
+                    .filter(|err| !err.is_empty())
+                    .cloned(),
+                id,
+            });
+        }
+        Ok(events)
+    }
+
+    async fn mark_event_processed(&self, event_id: &str) -> Result<()> {
+        let mut conn = self.conn.clone();
+        let key = self.event_key(event_id);
+        let exists: bool = conn.exists(&key).await?;
+        if !exists {
+            return Err(OutboxError::EventNotFound {
+                id: event_id.to_string(),
+            }
+            .into());
+        }
+        redis::pipe()
+            .atomic()
+            .zrem(self.unprocessed_key(), event_id)
+            .hset(&key, "processed", "true")
+            .query_async::<()>(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_events(&self, ids: &[String]) -> Result<usize> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let mut conn = self.conn.clone();
+        let keys: Vec<String> = ids.iter().map(|id| self.event_key(id)).collect();
+        let (removed, _): (usize, usize) = redis::pipe()
+            .atomic()
+            .del(keys)
+            .zrem(self.unprocessed_key(), ids)
+            .query_async(&mut conn)
+            .await?;
+        Ok(removed)
+    }
+
+    async fn contains_event(&self, id: &str) -> Result<bool> {
+        let mut conn = self.conn.clone();
+        Ok(conn.exists(self.event_key(id)).await?)
+    }
+
+    async fn record_failure(&self, id: &str, error: &str) -> Result<u32> {
+        let mut conn = self.conn.clone();
+        let key = self.event_key(id);
+        let exists: bool = conn.exists(&key).await?;
+        if !exists {
+            return Err(OutboxError::EventNotFound { id: id.to_string() }.into());
+        }
+        let (retry_count,): (u32,) = redis::pipe()
+            .atomic()
+            .hincr(&key, "retry_count", 1)
+            .hset(&key, "last_error", error)
+            .ignore()
+            .query_async(&mut conn)
+            .await?;
+        Ok(retry_count)
+    }
+
+    async fn reset_event(&self, id: &str) -> Result<bool> {
+        let mut conn = self.conn.clone();
+        let key = self.event_key(id);
+        let (processed, created_at): (Option<String>, Option<String>) =
+            conn.hget(&key, &["processed", "created_at"]).await?;
+        let Some(processed) = processed else {
+            return Err(OutboxError::EventNotFound { id: id.to_string() }.into());
+        };
+        if processed != "true" {
+            return Ok(false);
+        }
+        // Back into the sorted set at its original creation time, so it
+        // keeps its place in FIFO order.
+        let score = created_at
+            .and_then(|ts| DateTime::parse_from_rfc3339(&ts).ok())
+            .map(|ts| ts.timestamp_millis())
+            .unwrap_or_else(|| Utc::now().timestamp_millis());
+        redis::pipe()
+            .atomic()
+            .hset(&key, "processed", "false")
+            .zadd(self.unprocessed_key(), id, score)
+            .query_async::<()>(&mut conn)
+            .await?;
+        Ok(true)
+    }
+
+    // Walks every event hash with SCAN, which doesn't block Redis the way
+    // KEYS would on a large outbox.
+    async fn reset_all_processed(&self) -> Result<usize> {
+        let keys: Vec<String> = {
+            let mut conn = self.conn.clone();
+            let mut iter = conn.scan_match::<_, String>(self.event_key("*")).await?;
+            let mut keys = Vec::new();
+            while let Some(key) = iter.next_item().await {
+                keys.push(key);
+            }
+            keys
+        };
+        let id_prefix = self.event_key("");
+        let mut reset = 0;
+        for key in keys {
+            if let Some(id) = key.strip_prefix(&id_prefix) {
+                if self.reset_event(id).await? {
+                    reset += 1;
+                }
+            }
+        }
+        Ok(reset)
+    }
+}
+
+// Installs a `tracing` subscriber that prints to stdout. The level comes from
+// `RUST_LOG` (e.g. `RUST_LOG=debug`), defaulting to `info`.
+fn init_tracing() {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    init_tracing();
+
+    // Every file the demo writes goes into its own temporary directory, which
+    // is removed afterwards, so a run leaves nothing in the working directory.
+    let dir = std::env::temp_dir().join(format!("outbox-demo-{}", std::process::id()));
+    fs::create_dir_all(&dir).await?;
+    let demo = run_demo(&dir).await;
+    fs::remove_dir_all(&dir).await?;
+    demo
+}
+
+// A short tour of the stores. The tests check each behaviour in detail; this
+// only shows the pieces working together. Lesson 14.3 relays what they hold.
+async fn run_demo(dir: &std::path::Path) -> Result<()> {
+    let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+    let ids = |events: Vec<Event>| events.into_iter().map(|e| e.id).collect::<Vec<_>>();
+
+    // The file store: batch and single saves, a payload limit, idempotent
+    // saves, and events read back oldest first.
+    let store = FileOutboxStore::new(&path("outbox.txt"))
+        .with_max_payload_bytes(1024)
+        .with_idempotent_saves();
+    store
+        .save_events(vec![
+            Event::new("1", "UserCreated"),
+            Event::new("2", "order:placed|a\nb"),
+            Event::new("3", "AlreadySent").processed(),
+        ])
+        .await?;
+    store.save_event(Event::new("1", "UserCreated")).await?;
+    if let Err(e) = store.save_event(Event::new("big", &"x".repeat(2048))).await {
+        let retryable = e
+            .downcast_ref::<OutboxError>()
+            .is_some_and(OutboxError::is_retryable);
+        println!("Rejected: {} (retryable: {})", e, retryable);
+    }
+    println!(
+        "Unprocessed: {:?}",
+        ids(store.get_unprocessed_events().await?)
+    );
+    let orders = store
+        .get_unprocessed_matching(&|e: &Event| e.payload.starts_with("order:"))
+        .await?;
+    println!("Order events: {:?}", ids(orders));
+
+    // Acknowledging, replaying and compacting.
+    store.mark_event_processed("1").await?;
+    if let Err(e) = store.mark_event_processed("42").await {
+        println!("Cannot acknowledge: {}", e);
+    }
+    println!(
+        "Acknowledged {} in a batch.",
+        store.mark_events_processed(&["2".to_string()]).await?
+    );
+    println!(
+        "Requeued 1: {}; reset {} more.",
+        store.reset_event("1").await?,
+        store.reset_all_processed().await?
+    );
+    store.record_failure("1", "broker timeout").await?;
+    let mut pending = Box::pin(store.unprocessed_stream());
+    while let Some(event) = pending.try_next().await? {
+        println!(
+            "Streamed {} (retries: {}, last error: {:?})",
+            event.id, event.retry_count, event.last_error
+        );
+    }
+    store
+        .mark_events_processed(&["2".to_string(), "3".to_string()])
+        .await?;
+    println!("Compaction removed {} event(s).", store.compact().await?);
+    let redacted = store
+        .compact_with(|e| {
+            Some(Event {
+                payload: e.payload.replace("User", "<redacted>"),
+                ..e
+            })
+        })
+        .await?;
+    println!(
+        "compact_with removed {}; left {:?}",
+        redacted,
+        store.get_unprocessed_events().await?
+    );
+    let memory = InMemoryOutboxStore::new();
+    println!(
+        "Moved {} event(s) to an in-memory store.",
+        store.transfer_to(&memory, &["1".to_string()]).await?
+    );
+    store.close().await?;
+
+    // Other line formats and policies: JSON lines, checksums with repair,
+    // and deleting rows as soon as they are processed.
+    let json = FileOutboxStore::with_codec(&path("outbox.jsonl"), JsonCodec);
+    json.save_event(Event::new("j1", "a|b\nc \"quoted\""))
+        .await?;
+    println!(
+        "JSON lines: {}",
+        fs::read_to_string(path("outbox.jsonl")).await?.trim_end()
+    );
+    json.close().await?;
+    let checked = FileOutboxStore::new(&path("checked.txt")).with_checksums();
+    checked
+        .save_events(vec![
+            Event::new("c1", "OrderPlaced"),
+            Event::new("c2", "OrderPaid"),
+        ])
+        .await?;
+    let contents = fs::read_to_string(path("checked.txt")).await?;
+    fs::write(
+        path("checked.txt"),
+        contents.replacen("OrderPaid", "OrderPayd", 1),
+    )
+    .await?;
+    if let Err(e) = checked.get_unprocessed_events().await {
+        println!(
+            "Checksum caught damage: {}; repair quarantined lines {:?}",
+            e,
+            checked.repair().await?
+        );
+    }
+    checked.close().await?;
+    let deleting = FileOutboxStore::new(&path("deleting.txt"))
+        .with_retention(RetentionPolicy::DeleteOnProcess { after: None });
+    deleting.save_event(Event::new("d1", "Deleted")).await?;
+    deleting.mark_event_processed("d1").await?;
+    println!(
+        "Rows left under DeleteOnProcess: {}",
+        deleting.read_all_events().await?.len()
+    );
+    deleting.close().await?;
+
+    // With `--features sqlx`/`redis` and `DATABASE_URL`/`REDIS_URL` set, the
+    // same steps run against real services.
+    #[cfg(feature = "sqlx")]
+    if let Ok(url) = std::env::var("DATABASE_URL") {
+        let sql_store = SqlxOutboxStore::new(sqlx::PgPool::connect(&url).await?);
+        sql_store.migrate().await?;
+        sql_store.remove_events(&["sql1".to_string()]).await?;
+        sql_store
+            .save_event(Event::new("sql1", "StoredInPostgres"))
+            .await?;
+        println!(
+            "Postgres unprocessed events: {:?}",
+            ids(sql_store.get_unprocessed_events().await?)
+        );
+    }
+    #[cfg(feature = "redis")]
+    if let Ok(url) = std::env::var("REDIS_URL") {
+        let redis_store = RedisOutboxStore::connect(&url, "outbox_demo").await?;
+        redis_store.remove_events(&["r1".to_string()]).await?;
+        redis_store
+            .save_event(Event::new("r1", "StoredInRedis"))
+            .await?;
+        println!(
+            "Redis unprocessed events: {:?}",
+            ids(redis_store.get_unprocessed_events().await?)
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A path for `name` inside a fresh temporary directory. The directory
+    // and everything in it are deleted when the returned guard is dropped.
+    fn scratch_path(name: &str) -> (tempfile::TempDir, String) {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let path = dir.path().join(name).to_string_lossy().into_owned();
+        (dir, path)
+    }
+
+    fn ids(events: &[Event]) -> Vec<String> {
+        events.iter().map(|e| e.id.clone()).collect()
+    }
+
+    fn corrupt_line_number(err: &anyhow::Error) -> Option<usize> {
+        match err.downcast_ref::<OutboxError>() {
+            Some(OutboxError::CorruptEvent { line_number }) => Some(*line_number),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_corrupted_checksummed_line_is_reported_and_can_be_repaired() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path).with_checksums();
+        for (id, payload) in [
+            ("1", "OrderPlaced"),
+            ("2", "OrderPaid"),
+            ("3", "OrderShipped"),
+        ] {
+            store.save_event(Event::new(id, payload)).await.unwrap();
+        }
+        // Still decodes, so only the checksum can notice.
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, contents.replacen("OrderPaid", "OrderPayd", 1)).unwrap();
+
+        let err = store.get_unprocessed_events().await.unwrap_err();
+        assert_eq!(corrupt_line_number(&err), Some(2));
+        let err = store.read_all_events().await.unwrap_err();
+        assert_eq!(corrupt_line_number(&err), Some(2));
+
+        assert_eq!(store.repair().await.unwrap(), [2]);
+        assert_eq!(
+            ids(&store.get_unprocessed_events().await.unwrap()),
+            ["1", "3"]
+        );
+        let quarantined = std::fs::read_to_string(format!("{}.corrupt", path)).unwrap();
+        assert!(quarantined.contains("OrderPayd"));
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn an_undecodable_line_is_never_dropped_by_a_rewrite() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path);
+        store
+            .save_event(Event::new("1", "OrderPlaced"))
+            .await
+            .unwrap();
+        let mut contents = std::fs::read_to_string(&path).unwrap();
+        contents.push_str("garbage\n");
+        std::fs::write(&path, &contents).unwrap();
+
+        let err = store.get_unprocessed_events().await.unwrap_err();
+        assert_eq!(corrupt_line_number(&err), Some(2));
+        let err = store
+            .save_event(Event::new("2", "OrderPaid"))
+            .await
+            .unwrap_err();
+        assert_eq!(corrupt_line_number(&err), Some(2));
+        assert!(store.mark_event_processed("1").await.is_err());
+        assert!(store.compact().await.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), contents);
+
+        assert_eq!(store.repair().await.unwrap(), [2]);
+        assert_eq!(ids(&store.get_unprocessed_events().await.unwrap()), ["1"]);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn record_failure_on_an_unknown_id_is_event_not_found() {
+        let store = InMemoryOutboxStore::new();
+        let err = store.record_failure("missing", "boom").await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<OutboxError>(),
+            Some(OutboxError::EventNotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn events_saved_before_close_are_read_back_by_a_new_store() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path);
+        store
+            .save_events(vec![
+                Event::new("1", "UserCreated"),
+                Event::new("2", "OrderPlaced"),
+            ])
+            .await
+            .unwrap();
+        store.close().await.unwrap();
+
+        let reopened = FileOutboxStore::new(&path);
+        assert_eq!(
+            ids(&reopened.get_unprocessed_events().await.unwrap()),
+            ["1", "2"]
+        );
+        reopened.close().await.unwrap();
+    }
+
+    // Collects everything a tracing subscriber writes, for asserting on logs.
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl CapturedLogs {
+        fn with_subscriber(&self, f: impl FnOnce()) -> String {
+            let logs = self.clone();
+            let subscriber = tracing_subscriber::fmt()
+                .with_ansi(false)
+                .with_writer(move || logs.clone())
+                .finish();
+            tracing::subscriber::with_default(subscriber, f);
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn dropping_a_store_without_close_logs_a_warning() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let logs = CapturedLogs::default().with_subscriber(|| drop(FileOutboxStore::new(&path)));
+        assert!(
+            logs.contains("WARN") && logs.contains("dropped without calling close()"),
+            "{}",
+            logs
+        );
+    }
+
+    #[test]
+    fn a_closed_store_logs_nothing_when_dropped() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let logs = CapturedLogs::default().with_subscriber(|| {
+            runtime.block_on(async {
+                let store = FileOutboxStore::new(&path);
+                store
+                    .save_event(Event::new("1", "UserCreated"))
+                    .await
+                    .unwrap();
+                store.close().await.unwrap();
+            })
+        });
+        assert!(logs.is_empty(), "{}", logs);
+    }
+
+    #[test]
+    fn outbox_errors_display_a_message_per_variant() {
+        let cases = [
+            (
+                OutboxError::PayloadTooLarge {
+                    size: 70,
+                    limit: 64,
+                },
+                "payload is 70 bytes, which exceeds the 64 byte limit",
+            ),
+            (
+                OutboxError::EventNotFound {
+                    id: "evt-7".to_string(),
+                },
+                "event evt-7 not found",
+            ),
+            (
+                OutboxError::CorruptEvent { line_number: 3 },
+                "outbox line 3 is corrupt",
+            ),
+        ];
+        for (err, message) in cases {
+            assert_eq!(err.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn outbox_errors_convert_into_boxed_and_anyhow_errors() {
+        fn find(id: &str) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            Err(OutboxError::EventNotFound { id: id.to_string() })?
+        }
+        let boxed = find("evt-7").unwrap_err();
+        assert_eq!(boxed.to_string(), "event evt-7 not found");
+        assert!(boxed.downcast_ref::<OutboxError>().is_some());
+
+        let err = anyhow::Error::from(OutboxError::EventNotFound {
+            id: "evt-7".to_string(),
+        });
+        assert!(matches!(
+            err.downcast_ref::<OutboxError>(),
+            Some(OutboxError::EventNotFound { .. })
+        ));
+        assert!(!err.downcast_ref::<OutboxError>().unwrap().is_retryable());
+    }
+
+    #[tokio::test]
+    async fn a_payload_over_the_limit_is_rejected_before_anything_is_written() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path).with_max_payload_bytes(1024);
+
+        let err = store
+            .save_event(Event::new("big", &"x".repeat(2048)))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<OutboxError>(),
+            Some(OutboxError::PayloadTooLarge {
+                size: 2048,
+                limit: 1024
+            })
+        ));
+        store
+            .save_event(Event::new("small", &"x".repeat(500)))
+            .await
+            .unwrap();
+        assert_eq!(
+            ids(&store.get_unprocessed_events().await.unwrap()),
+            ["small"]
+        );
+
+        // One oversized event rejects the whole batch.
+        let batch = vec![Event::new("a", "fits"), Event::new("b", &"x".repeat(1025))];
+        assert!(store.save_events(batch).await.is_err());
+        assert_eq!(
+            ids(&store.get_unprocessed_events().await.unwrap()),
+            ["small"]
+        );
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn compact_with_redacts_and_drops_while_pruning_processed_rows() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path);
+        store
+            .save_events(vec![
+                Event::new("1", "User alice signed up"),
+                Event::new("2", "User bob signed up").processed(),
+                Event::new("3", "debug: cache warmed"),
+                Event::new("4", "User carol signed up"),
+            ])
+            .await
+            .unwrap();
+
+        let removed = store
+            .compact_with(|event| {
+                if event.payload.starts_with("debug:") {
+                    return None;
+                }
+                Some(Event {
+                    payload: event.payload.replace("User", "<redacted>"),
+                    ..event
+                })
+            })
+            .await
+            .unwrap();
+        assert_eq!(removed, 2);
+
+        let reopened = FileOutboxStore::new(&path);
+        let events = reopened.read_all_events().await.unwrap();
+        assert_eq!(ids(&events), ["1", "4"]);
+        assert!(events.iter().all(|e| e.payload.starts_with("<redacted> ")));
+        store.close().await.unwrap();
+        reopened.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn transfer_to_moves_only_the_requested_events() {
+        let source = InMemoryOutboxStore::new();
+        let dest = InMemoryOutboxStore::new();
+        for i in 1..=5 {
+            source
+                .save_event(Event::new(&format!("s{}", i), "ShardEvent"))
+                .await
+                .unwrap();
+        }
+
+        let wanted = ["s1", "s3", "s5", "unknown"].map(String::from);
+        assert_eq!(source.transfer_to(&dest, &wanted).await.unwrap(), 3);
+        assert_eq!(
+            ids(&source.get_unprocessed_events().await.unwrap()),
+            ["s2", "s4"]
+        );
+        assert_eq!(
+            ids(&dest.get_unprocessed_events().await.unwrap()),
+            ["s1", "s3", "s5"]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_failed_transfer_leaves_the_source_untouched() {
+        let (_dir, path) = scratch_path("dest.txt");
+        let source = InMemoryOutboxStore::new();
+        source.save_event(Event::new("ok", "Fits")).await.unwrap();
+        source
+            .save_event(Event::new("big", &"x".repeat(64)))
+            .await
+            .unwrap();
+        let dest = FileOutboxStore::new(&path).with_max_payload_bytes(32);
+
+        let all = ["ok", "big"].map(String::from);
+        assert!(source.transfer_to(&dest, &all).await.is_err());
+        assert_eq!(
+            ids(&source.get_unprocessed_events().await.unwrap()),
+            ["ok", "big"]
+        );
+        assert!(dest.get_unprocessed_events().await.unwrap().is_empty());
+        dest.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_transfer_leaves_ids_the_destination_already_has_alone() {
+        let (_dir, path) = scratch_path("dest.txt");
+        let source = InMemoryOutboxStore::new();
+        source
+            .save_event(Event::new("dup", "FromSource"))
+            .await
+            .unwrap();
+        source.save_event(Event::new("ok", "Fits")).await.unwrap();
+        let dest = FileOutboxStore::new(&path).with_max_payload_bytes(32);
+        dest.save_event(Event::new("dup", "AlreadyThere").processed())
+            .await
+            .unwrap();
+
+        let wanted = ["dup", "ok"].map(String::from);
+        assert_eq!(source.transfer_to(&dest, &wanted).await.unwrap(), 1);
+        assert_eq!(
+            ids(&source.get_unprocessed_events().await.unwrap()),
+            ["dup"]
+        );
+        let kept = dest.read_all_events().await.unwrap();
+        assert_eq!(ids(&kept), ["dup", "ok"]);
+        assert_eq!(
+            (kept[0].payload.as_str(), kept[0].processed),
+            ("AlreadyThere", true)
+        );
+
+        // A failed transfer rolls back its own copy but not the colliding id,
+        // and reports why it failed.
+        source.save_event(Event::new("new", "Fits")).await.unwrap();
+        source
+            .save_event(Event::new("big", &"x".repeat(64)))
+            .await
+            .unwrap();
+        let all = ["dup", "new", "big"].map(String::from);
+        let err = source.transfer_to(&dest, &all).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<OutboxError>(),
+            Some(OutboxError::PayloadTooLarge { .. })
+        ));
+        assert_eq!(
+            ids(&source.get_unprocessed_events().await.unwrap()),
+            ["dup", "new", "big"]
+        );
+        assert_eq!(ids(&dest.read_all_events().await.unwrap()), ["dup", "ok"]);
+        dest.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn keep_processed_retains_rows_and_delete_on_process_removes_them() {
+        let (_dir, path) = scratch_path("kept.txt");
+        let kept = FileOutboxStore::new(&path).with_retention(RetentionPolicy::KeepProcessed);
+        kept.save_events(vec![Event::new("1", "a"), Event::new("2", "b")])
+            .await
+            .unwrap();
+        kept.mark_event_processed("1").await.unwrap();
+        let rows = kept.read_all_events().await.unwrap();
+        assert_eq!(ids(&rows), ["1", "2"]);
+        assert!(rows[0].processed);
+        kept.close().await.unwrap();
+
+        let (_dir, path) = scratch_path("deleted.txt");
+        let deleting = FileOutboxStore::new(&path)
+            .with_retention(RetentionPolicy::DeleteOnProcess { after: None });
+        deleting
+            .save_events(vec![Event::new("1", "a"), Event::new("2", "b")])
+            .await
+            .unwrap();
+        deleting.mark_event_processed("1").await.unwrap();
+        assert_eq!(ids(&deleting.read_all_events().await.unwrap()), ["2"]);
+        deleting.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn delete_on_process_with_a_delay_keeps_the_row_until_it_passes() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path).with_retention(RetentionPolicy::DeleteOnProcess {
+            after: Some(Duration::from_millis(50)),
+        });
+        store
+            .save_events(vec![Event::new("1", "a"), Event::new("2", "b")])
+            .await
+            .unwrap();
+        store.mark_event_processed("1").await.unwrap();
+        assert_eq!(ids(&store.read_all_events().await.unwrap()), ["1", "2"]);
+
+        time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(ids(&store.read_all_events().await.unwrap()), ["2"]);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_write_that_fails_before_the_rename_leaves_the_file_intact() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path);
+        let events: Vec<Event> = (0..1_000)
+            .map(|i| Event::new(&i.to_string(), "Original"))
+            .collect();
+        store.save_events(events).await.unwrap();
+        assert!(
+            fs::metadata(format!("{}.tmp", path)).await.is_err(),
+            "the temp file is renamed away"
+        );
+
+        // A directory where the temp file should go makes the next rewrite
+        // fail before it gets to the rename.
+        fs::create_dir(format!("{}.tmp", path)).await.unwrap();
+        assert!(store.mark_event_processed("0").await.is_err());
+
+        let events = FileOutboxStore::new(&path).read_all_events().await.unwrap();
+        assert_eq!(events.len(), 1_000);
+        assert!(events
+            .iter()
+            .all(|e| !e.processed && e.payload == "Original"));
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_half_written_temp_file_from_a_crash_is_ignored_and_replaced() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path);
+        store.save_event(Event::new("1", "Original")).await.unwrap();
+        fs::write(format!("{}.tmp", path), "v2|2|half-writ")
+            .await
+            .unwrap();
+
+        assert_eq!(ids(&store.get_unprocessed_events().await.unwrap()), ["1"]);
+        store.save_event(Event::new("2", "Next")).await.unwrap();
+        assert_eq!(
+            ids(&store.get_unprocessed_events().await.unwrap()),
+            ["1", "2"]
+        );
+        store.close().await.unwrap();
+    }
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Counts the lines a store encodes, i.e. how much it rewrites.
+    struct CountingCodec(Arc<AtomicUsize>);
+
+    impl EventCodec for CountingCodec {
+        fn encode(&self, event: &Event) -> String {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            PipeCodec.encode(event)
+        }
+
+        fn decode(&self, line: &str) -> Result<Event> {
+            PipeCodec.decode(line)
+        }
+    }
+
+    #[tokio::test]
+    async fn save_events_writes_the_file_once_for_the_whole_batch() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let encoded = Arc::new(AtomicUsize::new(0));
+        let store = FileOutboxStore::with_codec(&path, CountingCodec(encoded.clone()));
+        let batch: Vec<Event> = (0..100)
+            .map(|i| Event::new(&i.to_string(), "Batched"))
+            .collect();
+
+        store.save_events(batch).await.unwrap();
+        // One rewrite of 100 lines. Saving one at a time would rewrite the
+        // growing file 100 times: 1 + 2 + ... + 100 = 5050 lines.
+        assert_eq!(encoded.load(Ordering::SeqCst), 100);
+        assert_eq!(store.get_unprocessed_events().await.unwrap().len(), 100);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_default_save_events_saves_each_event() {
+        let store = InMemoryOutboxStore::new();
+        store
+            .save_events(vec![Event::new("1", "a"), Event::new("2", "b")])
+            .await
+            .unwrap();
+        assert_eq!(
+            ids(&store.get_unprocessed_events().await.unwrap()),
+            ["1", "2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn delimiters_newlines_and_backslashes_survive_a_round_trip() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path);
+        let payloads = ["a|b|c\nd", "trailing\\", "\\|\\n", "", "||"];
+        for (i, payload) in payloads.iter().enumerate() {
+            store
+                .save_event(Event::new(&format!("id|{}", i), payload))
+                .await
+                .unwrap();
+        }
+        let mut failed = Event::new("failed", "x");
+        failed.record_failure("line one\nline|two");
+        store.save_event(failed).await.unwrap();
+
+        let events = FileOutboxStore::new(&path)
+            .get_unprocessed_events()
+            .await
+            .unwrap();
+        for (i, payload) in payloads.iter().enumerate() {
+            assert_eq!(events[i].id, format!("id|{}", i));
+            assert_eq!(events[i].payload, *payload);
+        }
+        assert_eq!(events[5].last_error.as_deref(), Some("line one\nline|two"));
+        let contents = fs::read_to_string(&path).await.unwrap();
+        assert_eq!(contents.lines().count(), 6, "one line per event");
+        store.close().await.unwrap();
+    }
+
+    // Runs the same steps against any store, so the file and in-memory
+    // stores can be checked for the same behaviour.
+    async fn save_read_and_acknowledge(
+        store: &dyn OutboxStore,
+    ) -> (Vec<String>, Vec<String>, bool) {
+        store
+            .save_event(Event::new("1", "UserCreated"))
+            .await
+            .unwrap();
+        store
+            .save_event(Event::new("2", "OrderPlaced"))
+            .await
+            .unwrap();
+        store
+            .save_event(Event::new("3", "AlreadySent").processed())
+            .await
+            .unwrap();
+        let before = ids(&store.get_unprocessed_events().await.unwrap());
+        store.mark_event_processed("1").await.unwrap();
+        let after = ids(&store.get_unprocessed_events().await.unwrap());
+        let unknown = store.mark_event_processed("42").await.is_err();
+        (before, after, unknown)
+    }
+
+    #[tokio::test]
+    async fn the_in_memory_store_behaves_like_the_file_store() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let file = FileOutboxStore::new(&path);
+        let memory = InMemoryOutboxStore::new();
+
+        let expected = (
+            vec!["1".to_string(), "2".to_string()],
+            vec!["2".to_string()],
+            true,
+        );
+        assert_eq!(save_read_and_acknowledge(&file).await, expected);
+        assert_eq!(save_read_and_acknowledge(&memory).await, expected);
+        file.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn clones_of_the_in_memory_store_share_state_across_tasks() {
+        let store = InMemoryOutboxStore::new();
+        let tasks: Vec<_> = (0..4)
+            .map(|i| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    store
+                        .save_event(Event::new(&i.to_string(), "FromTask"))
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        assert_eq!(store.get_unprocessed_events().await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn unprocessed_events_come_back_oldest_first() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let file = FileOutboxStore::new(&path);
+        let memory = InMemoryOutboxStore::new();
+        let now = Utc::now();
+        let stores: [&dyn OutboxStore; 2] = [&file, &memory];
+        for store in stores {
+            for (id, age) in [("newest", 0), ("oldest", 60), ("middle", 30)] {
+                let created_at = now - chrono::Duration::seconds(age);
+                store
+                    .save_event(Event {
+                        created_at,
+                        ..Event::new(id, "Saved")
+                    })
+                    .await
+                    .unwrap();
+            }
+            assert_eq!(
+                ids(&store.get_unprocessed_events().await.unwrap()),
+                ["oldest", "middle", "newest"]
+            );
+        }
+        let reread = FileOutboxStore::new(&path).read_all_events().await.unwrap();
+        assert_eq!(reread[1].created_at, now - chrono::Duration::seconds(60));
+        file.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn mark_events_processed_flips_exactly_the_given_ids() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let file = FileOutboxStore::new(&path);
+        let memory = InMemoryOutboxStore::new();
+        let stores: [&dyn OutboxStore; 2] = [&file, &memory];
+        for store in stores {
+            store
+                .save_events(
+                    (1..=5)
+                        .map(|i| Event::new(&i.to_string(), "Sent"))
+                        .collect(),
+                )
+                .await
+                .unwrap();
+            store.mark_event_processed("4").await.unwrap();
+
+            let acked = ["1", "3", "4", "5", "missing"].map(String::from);
+            // "4" was already processed and "missing" doesn't exist.
+            assert_eq!(store.mark_events_processed(&acked).await.unwrap(), 3);
+            assert_eq!(ids(&store.get_unprocessed_events().await.unwrap()), ["2"]);
+        }
+        file.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn marking_a_stored_event_succeeds_and_an_unknown_one_fails() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let file = FileOutboxStore::new(&path);
+        let memory = InMemoryOutboxStore::new();
+        let stores: [&dyn OutboxStore; 2] = [&file, &memory];
+        for store in stores {
+            store
+                .save_event(Event::new("1", "UserCreated"))
+                .await
+                .unwrap();
+            store.mark_event_processed("1").await.unwrap();
+            assert!(store.get_unprocessed_events().await.unwrap().is_empty());
+
+            let err = store.mark_event_processed("42").await.unwrap_err();
+            assert_eq!(err.to_string(), "event 42 not found");
+            assert!(
+                matches!(err.downcast_ref::<OutboxError>(), Some(OutboxError::EventNotFound { id }) if id == "42")
+            );
+        }
+        file.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn compact_keeps_exactly_the_unprocessed_events() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path);
+        store
+            .save_events(
+                (0..10)
+                    .map(|i| Event::new(&i.to_string(), "Event"))
+                    .collect(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(store.compact().await.unwrap(), 0, "nothing to prune yet");
+
+        let processed: Vec<String> = (0..10).step_by(2).map(|i| i.to_string()).collect();
+        store.mark_events_processed(&processed).await.unwrap();
+        assert_eq!(store.compact().await.unwrap(), 5);
+
+        let contents = fs::read_to_string(&path).await.unwrap();
+        assert_eq!(contents.lines().count(), 5);
+        assert_eq!(
+            ids(&store.read_all_events().await.unwrap()),
+            ["1", "3", "5", "7", "9"]
+        );
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn recorded_failures_round_trip_through_the_file() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path);
+        let mut flaky = Event::new("f1", "FlakyDelivery");
+        flaky.record_failure("broker timeout");
+        flaky.record_failure("connection refused");
+        store.save_event(flaky).await.unwrap();
+        store.save_event(Event::new("ok", "Fine")).await.unwrap();
+
+        let events = FileOutboxStore::new(&path)
+            .get_unprocessed_events()
+            .await
+            .unwrap();
+        assert_eq!(events[0].retry_count, 2);
+        assert_eq!(events[0].last_error.as_deref(), Some("connection refused"));
+        assert_eq!(
+            (events[1].retry_count, events[1].last_error.as_deref()),
+            (0, None)
+        );
+
+        let max_retries = 1;
+        let dead: Vec<&Event> = events
+            .iter()
+            .filter(|e| e.retry_count > max_retries)
+            .collect();
+        assert_eq!(dead.len(), 1);
+        store.close().await.unwrap();
+    }
+
+    #[test]
+    fn a_new_event_is_unprocessed_with_no_failures() {
+        let before = Utc::now();
+        let event = Event::new("1", "UserCreated");
+        assert_eq!(
+            (event.id.as_str(), event.payload.as_str()),
+            ("1", "UserCreated")
+        );
+        assert!(!event.processed);
+        assert_eq!((event.retry_count, event.last_error), (0, None));
+        assert!(event.created_at >= before && event.created_at <= Utc::now());
+        assert!(Event::new("2", "AlreadySent").processed().processed);
+    }
+
+    #[tokio::test]
+    async fn the_stream_yields_only_unprocessed_events_in_file_order() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path);
+        let events: Vec<Event> = (0..10_000)
+            .map(|i| {
+                let event = Event::new(&i.to_string(), "Event");
+                if i % 2 == 0 {
+                    event.processed()
+                } else {
+                    event
+                }
+            })
+            .collect();
+        store.save_events(events).await.unwrap();
+
+        let mut stream = Box::pin(store.unprocessed_stream());
+        let mut count = 0;
+        let mut expected = 1;
+        while let Some(event) = stream.try_next().await.unwrap() {
+            assert_eq!(event.id, expected.to_string());
+            expected += 2;
+            count += 1;
+        }
+        assert_eq!(count, 5_000);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_stream_of_a_missing_file_is_empty() {
+        let (_dir, path) = scratch_path("missing.txt");
+        let store = FileOutboxStore::new(&path);
+        assert!(Box::pin(store.unprocessed_stream())
+            .try_next()
+            .await
+            .unwrap()
+            .is_none());
+        store.close().await.unwrap();
+    }
+
+    fn tricky_event() -> Event {
+        let mut event = Event::new("j|1", "a|b\nc \"quoted\" \\ {}");
+        event.record_failure("timed out\nretrying");
+        event
+    }
+
+    fn assert_same_event(decoded: &Event, original: &Event) {
+        assert_eq!(
+            (decoded.id.as_str(), decoded.payload.as_str()),
+            (original.id.as_str(), original.payload.as_str())
+        );
+        assert_eq!(
+            (decoded.retry_count, &decoded.last_error),
+            (original.retry_count, &original.last_error)
+        );
+        assert_eq!(decoded.created_at, original.created_at);
+    }
+
+    #[test]
+    fn both_codecs_round_trip_special_characters_on_one_line() {
+        let event = tricky_event();
+        let codecs: [&dyn EventCodec; 2] = [&PipeCodec, &JsonCodec];
+        for codec in codecs {
+            let line = codec.encode(&event);
+            assert!(!line.contains('\n'), "{}", line);
+            assert_same_event(&codec.decode(&line).unwrap(), &event);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_json_store_reads_back_what_it_wrote() {
+        let (_dir, path) = scratch_path("outbox.jsonl");
+        let store = FileOutboxStore::with_codec(&path, JsonCodec);
+        let event = tricky_event();
+        store.save_event(event.clone()).await.unwrap();
+
+        assert!(fs::read_to_string(&path).await.unwrap().starts_with('{'));
+        assert_same_event(&store.get_unprocessed_events().await.unwrap()[0], &event);
+        store.close().await.unwrap();
+    }
+
+    // These run against the Postgres in `DATABASE_URL` and pass without doing
+    // anything when it isn't set. The table may be shared, so each test uses
+    // ids under its own prefix and deletes its rows at the end.
+    #[cfg(feature = "sqlx")]
+    mod sqlx_store {
+        use super::*;
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        async fn connect() -> Option<SqlxOutboxStore> {
+            let Ok(url) = std::env::var("DATABASE_URL") else {
+                eprintln!("DATABASE_URL not set; skipping");
+                return None;
+            };
+            let store = SqlxOutboxStore::new(
+                sqlx::PgPool::connect(&url)
+                    .await
+                    .expect("connect to DATABASE_URL"),
+            );
+            store.migrate().await.unwrap();
+            Some(store)
+        }
+
+        fn unique_prefix() -> String {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos();
+            format!("test-{}-{}-", std::process::id(), nanos)
+        }
+
+        #[tokio::test]
+        async fn unprocessed_events_come_back_in_insertion_order_until_marked() {
+            let Some(store) = connect().await else { return };
+            let prefix = unique_prefix();
+            let ids: Vec<String> = (1..=3).map(|n| format!("{}{}", prefix, n)).collect();
+            let created_at = Utc::now();
+            for id in &ids {
+                let mut event = Event::new(id, "OrderPlaced");
+                event.created_at = created_at;
+                store.save_event(event).await.unwrap();
+            }
+            let mine = {
+                let prefix = prefix.clone();
+                move |e: &Event| e.id.starts_with(&prefix)
+            };
+
+            assert_eq!(
+                super::ids(&store.get_unprocessed_matching(&mine).await.unwrap()),
+                ids
+            );
+            store.mark_event_processed(&ids[1]).await.unwrap();
+            let unprocessed = store.get_unprocessed_matching(&mine).await.unwrap();
+            assert_eq!(super::ids(&unprocessed), [ids[0].clone(), ids[2].clone()]);
+
+            let err = store
+                .mark_event_processed(&format!("{}missing", prefix))
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<OutboxError>(),
+                Some(OutboxError::EventNotFound { .. })
+            ));
+            assert_eq!(store.remove_events(&ids).await.unwrap(), 3);
+        }
+
+        #[tokio::test]
+        async fn failures_and_resets_are_stored() {
+            let Some(store) = connect().await else { return };
+            let id = format!("{}1", unique_prefix());
+            store
+                .save_event(Event::new(&id, "OrderPlaced"))
+                .await
+                .unwrap();
+
+            assert_eq!(store.record_failure(&id, "boom").await.unwrap(), 1);
+            store.mark_event_processed(&id).await.unwrap();
+            assert!(store.reset_event(&id).await.unwrap());
+            assert!(!store.reset_event(&id).await.unwrap());
+
+            let wanted = id.clone();
+            let event = store
+                .get_unprocessed_matching(&move |e: &Event| e.id == wanted)
+                .await
+                .unwrap()
+                .remove(0);
+            assert_eq!(
+                (event.retry_count, event.last_error.as_deref()),
+                (1, Some("boom"))
+            );
+            store.remove_events(&[id]).await.unwrap();
+        }
+    }
+
+    // These run against the Redis in `REDIS_URL` and pass without doing
+    // anything when it isn't set. Each test gets its own key prefix and
+    // deletes its events at the end.
+    #[cfg(feature = "redis")]
+    mod redis_store {
+        use super::*;
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        async fn connect() -> Option<RedisOutboxStore> {
+            let Ok(url) = std::env::var("REDIS_URL") else {
+                eprintln!("REDIS_URL not set; skipping");
+                return None;
+            };
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos();
+            let prefix = format!("outbox-test-{}-{}", std::process::id(), nanos);
+            Some(
+                RedisOutboxStore::connect(&url, &prefix)
+                    .await
+                    .expect("connect to REDIS_URL"),
+            )
+        }
+
+        #[tokio::test]
+        async fn saved_events_are_unprocessed_until_marked() {
+            let Some(store) = connect().await else { return };
+            store
+                .save_events(vec![
+                    Event::new("1", "UserCreated"),
+                    Event::new("2", "OrderPlaced"),
+                ])
+                .await
+                .unwrap();
+            assert_eq!(
+                ids(&store.get_unprocessed_events().await.unwrap()),
+                ["1", "2"]
+            );
+
+            store.mark_event_processed("1").await.unwrap();
+            assert_eq!(ids(&store.get_unprocessed_events().await.unwrap()), ["2"]);
+            let err = store.mark_event_processed("missing").await.unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<OutboxError>(),
+                Some(OutboxError::EventNotFound { .. })
+            ));
+
+            assert!(store.reset_event("1").await.unwrap());
+            assert_eq!(
+                ids(&store.get_unprocessed_events().await.unwrap()),
+                ["1", "2"]
+            );
+            store
+                .remove_events(&["1".to_string(), "2".to_string()])
+                .await
+                .unwrap();
+        }
+
+        #[tokio::test]
+        async fn two_connections_drain_one_outbox() {
+            let Some(producer) = connect().await else {
+                return;
+            };
+            let url = std::env::var("REDIS_URL").unwrap();
+            let consumer = RedisOutboxStore::connect(&url, &producer.prefix)
+                .await
+                .unwrap();
+            producer
+                .save_event(Event::new("1", "OrderPlaced"))
+                .await
+                .unwrap();
+
+            let pending = consumer.get_unprocessed_events().await.unwrap();
+            assert_eq!(ids(&pending), ["1"]);
+            consumer.mark_event_processed("1").await.unwrap();
+            assert!(producer.get_unprocessed_events().await.unwrap().is_empty());
+            producer.remove_events(&["1".to_string()]).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn an_idempotent_store_keeps_one_copy_of_a_duplicate_id() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path).with_idempotent_saves();
+        store
+            .save_event(Event::new("1", "OrderPlaced"))
+            .await
+            .unwrap();
+        store
+            .save_event(Event::new("1", "OrderPlacedAgain"))
+            .await
+            .unwrap();
+
+        let events = store.read_all_events().await.unwrap();
+        assert_eq!(ids(&events), ["1"]);
+        assert_eq!(events[0].payload, "OrderPlaced");
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_saves_of_one_id_keep_one_copy() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = Arc::new(FileOutboxStore::new(&path).with_idempotent_saves());
+        let saves: Vec<_> = (0..8)
+            .map(|n| {
+                let store = Arc::clone(&store);
+                tokio::spawn(async move {
+                    store
+                        .save_event(Event::new("dup", &format!("attempt {}", n)))
+                        .await
+                })
+            })
+            .collect();
+        for save in saves {
+            save.await.unwrap().unwrap();
+        }
+        assert_eq!(ids(&store.read_all_events().await.unwrap()), ["dup"]);
+        Arc::into_inner(store).unwrap().close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn without_idempotent_saves_a_duplicate_is_appended() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path);
+        store
+            .save_event(Event::new("1", "OrderPlaced"))
+            .await
+            .unwrap();
+        store
+            .save_event(Event::new("1", "OrderPlaced"))
+            .await
+            .unwrap();
+        assert_eq!(ids(&store.read_all_events().await.unwrap()), ["1", "1"]);
+        store.close().await.unwrap();
+    }
+
+    fn has_prefix(prefix: &'static str) -> impl Fn(&Event) -> bool + Send + Sync {
+        move |event| event.payload.starts_with(prefix)
+    }
+
+    #[tokio::test]
+    async fn the_file_store_returns_only_matching_unprocessed_events() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path);
+        store
+            .save_events(vec![
+                Event::new("1", "order:placed"),
+                Event::new("2", "user:created"),
+                Event::new("3", "order:shipped"),
+                Event::new("4", "order:cancelled").processed(),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            ids(&store
+                .get_unprocessed_matching(&has_prefix("order:"))
+                .await
+                .unwrap()),
+            ["1", "3"]
+        );
+        assert_eq!(
+            ids(&store
+                .get_unprocessed_matching(&has_prefix("user:"))
+                .await
+                .unwrap()),
+            ["2"]
+        );
+        assert!(store
+            .get_unprocessed_matching(&has_prefix("invoice:"))
+            .await
+            .unwrap()
+            .is_empty());
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_default_implementation_filters_the_unprocessed_events() {
+        let store = InMemoryOutboxStore::new();
+        store
+            .save_events(vec![
+                Event::new("1", "order:placed"),
+                Event::new("2", "user:created"),
+                Event::new("3", "order:shipped").processed(),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(
+            ids(&store
+                .get_unprocessed_matching(&has_prefix("order:"))
+                .await
+                .unwrap()),
+            ["1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn v1_v2_and_newer_lines_load_from_one_file() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let lines = [
+            "1|OrderPlaced|false",
+            "2|OrderShipped|false|2024-01-01T00:00:00+00:00|2|timed out",
+            "v2|3|UserCreated|true|2024-01-02T00:00:00+00:00|1|",
+            "v3|4|UserDeleted|false|2024-01-03T00:00:00+00:00|0||a field from the future",
+        ];
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+        let store = FileOutboxStore::new(&path);
+
+        let events = store.read_all_events().await.unwrap();
+        assert_eq!(ids(&events), ["1", "2", "3", "4"]);
+        // A three-field v1 line gets defaults for everything it lacks.
+        assert!((Utc::now() - events[0].created_at).num_seconds() < 60);
+        assert_eq!((events[0].retry_count, &events[0].last_error), (0, &None));
+        assert_eq!(
+            (events[1].retry_count, events[1].last_error.as_deref()),
+            (2, Some("timed out"))
+        );
+        assert_eq!(
+            events[1].created_at.to_rfc3339(),
+            "2024-01-01T00:00:00+00:00"
+        );
+        assert_eq!(
+            (
+                events[2].processed,
+                events[2].retry_count,
+                &events[2].last_error
+            ),
+            (true, 1, &None)
+        );
+        assert_eq!(events[3].payload, "UserDeleted");
+
+        store.mark_event_processed("1").await.unwrap();
+        let rewritten = std::fs::read_to_string(&path).unwrap();
+        assert!(
+            rewritten.lines().all(|line| line.starts_with("v2|")),
+            "{}",
+            rewritten
+        );
+        assert_eq!(
+            ids(&store.read_all_events().await.unwrap()),
+            ["1", "2", "3", "4"]
+        );
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_damaged_v2_line_is_corrupt_rather_than_defaulted() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        std::fs::write(
+            &path,
+            "1|OrderPlaced|false\nv2|2|OrderShipped|false|not a date|0|\n",
+        )
+        .unwrap();
+        let store = FileOutboxStore::new(&path);
+        let err = store.read_all_events().await.unwrap_err();
+        assert_eq!(corrupt_line_number(&err), Some(2));
+        store.close().await.unwrap();
+    }
+
+    #[test]
+    fn a_v1_event_whose_id_is_v2_is_not_read_as_versioned() {
+        let event = PipeCodec.decode("v2|OrderPlaced|false").unwrap();
+        assert_eq!(
+            (event.id.as_str(), event.payload.as_str()),
+            ("v2", "OrderPlaced")
+        );
+    }
+
+    async fn replay_after_processing(store: &dyn OutboxStore) {
+        let events = vec![
+            Event::new("1", "UserCreated"),
+            Event::new("2", "OrderPlaced"),
+            Event::new("3", "OrderShipped"),
+        ];
+        let all = ids(&events);
+        store.save_events(events).await.unwrap();
+        store.mark_events_processed(&all).await.unwrap();
+
+        assert!(store.reset_event("2").await.unwrap());
+        assert!(
+            !store.reset_event("2").await.unwrap(),
+            "already unprocessed"
+        );
+        assert_eq!(ids(&store.get_unprocessed_events().await.unwrap()), ["2"]);
+        let err = store.reset_event("missing").await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<OutboxError>(),
+            Some(OutboxError::EventNotFound { .. })
+        ));
+
+        assert_eq!(store.reset_all_processed().await.unwrap(), 2);
+        assert_eq!(
+            ids(&store.get_unprocessed_events().await.unwrap()),
+            ["1", "2", "3"]
+        );
+        assert_eq!(store.reset_all_processed().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn processed_events_can_be_reset_one_at_a_time_or_all_at_once() {
+        replay_after_processing(&InMemoryOutboxStore::new()).await;
+
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path);
+        replay_after_processing(&store).await;
+        store.close().await.unwrap();
+    }
 }
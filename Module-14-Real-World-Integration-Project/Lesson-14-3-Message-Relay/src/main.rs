@@ -9,19 +9,87 @@
 // First, let's define the trait that our message relay implementations will
 // adhere to. This trait will be part of our `outbox_core` crate.
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+// The event as Lesson 14.2's outbox stores it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Event {
     pub id: String,
     pub payload: String,
     pub processed: bool,
+    // When the event was created. Stores return unprocessed events oldest
+    // first so downstream processing is FIFO.
+    pub created_at: DateTime<Utc>,
+    // How many delivery attempts have failed, and why the last one did. The
+    // relayer can move events past a retry limit to a dead-letter path.
+    pub retry_count: u32,
+    pub last_error: Option<String>,
+}
+
+impl Event {
+    // A new, unprocessed event created now.
+    pub fn new(id: &str, payload: &str) -> Self {
+        Event {
+            id: id.to_string(),
+            payload: payload.to_string(),
+            processed: false,
+            created_at: Utc::now(),
+            retry_count: 0,
+            last_error: None,
+        }
+    }
+
+    // Builder-style helper for constructing an already-processed event,
+    // e.g. `Event::new("1", "UserCreated").processed()`.
+    pub fn processed(mut self) -> Self {
+        self.processed = true;
+        self
+    }
+
+    pub fn record_failure(&mut self, err: &str) {
+        self.retry_count += 1;
+        self.last_error = Some(err.to_string());
+    }
+}
+
+// Describes a configured relay target so operators can see where events go.
+#[derive(Debug, Clone)]
+pub struct RelayDescriptor {
+    pub name: String,
+    // Where the relay delivers to (a URL, subject, exchange...), if known.
+    pub target: Option<String>,
 }
 
 #[async_trait]
 pub trait MessageRelay: Send + Sync {
     async fn publish_event(&self, event: &Event) -> Result<()>;
+
+    // By default a relay describes itself by its type name. Relays that wrap
+    // other relays should return the descriptors of everything they wrap.
+    fn describe(&self) -> Vec<RelayDescriptor> {
+        let full_name = std::any::type_name::<Self>();
+        let name = full_name.rsplit("::").next().unwrap_or(full_name);
+        vec![RelayDescriptor {
+            name: name.to_string(),
+            target: None,
+        }]
+    }
+}
+
+// Lets a relay chosen at runtime, e.g. from `BridgeConfig::broker_url`, go
+// wherever a concrete one can.
+#[async_trait]
+impl MessageRelay for Box<dyn MessageRelay> {
+    async fn publish_event(&self, event: &Event) -> Result<()> {
+        (**self).publish_event(event).await
+    }
+
+    fn describe(&self) -> Vec<RelayDescriptor> {
+        (**self).describe()
+    }
 }
 
 // --- Conceptual RabbitMQ Implementation ---
@@ -80,6 +148,163 @@ pub trait MessageRelay: Send + Sync {
 //     }
 // }
 
+// --- Relay Errors ---
+
+// `publish_event` returns `anyhow::Result`, but a relayer still needs to know
+// *why* a publish failed so it can decide whether to try again. Concrete
+// relays return a `RelayError` inside the `anyhow::Error`, which callers can
+// recover with `err.downcast_ref::<RelayError>()`.
+
+use tokio::time::{self, Duration};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RelayError {
+    // Returned by `CircuitBreaker` without calling the relay it wraps.
+    #[error("circuit breaker is open; retry in {retry_in:?}")]
+    CircuitOpen { retry_in: Duration },
+    // The broker refused this particular event; sending it again won't help.
+    #[error("broker rejected the event: {reason}")]
+    Rejected { reason: String },
+    // The broker didn't confirm delivery in time. The event may or may not
+    // have arrived, so it is sent again (consumers must tolerate duplicates).
+    #[error("broker did not confirm delivery within {after:?}")]
+    DeliveryTimedOut { after: Duration },
+    // An HTTP endpoint answered with a non-2xx status.
+    #[error("delivery failed with status {status}")]
+    DeliveryFailed { status: u16 },
+    #[error("connection error: {0}")]
+    Connection(String),
+}
+
+impl RelayError {
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            RelayError::CircuitOpen { .. } => true,
+            RelayError::Rejected { .. } => false,
+            RelayError::DeliveryTimedOut { .. } => true,
+            // A 4xx may be permanent, but webhook receivers often return them
+            // for transient reasons (rate limits, deploys), so all are retried.
+            RelayError::DeliveryFailed { .. } => true,
+            RelayError::Connection(_) => true,
+        }
+    }
+}
+
+// Classifies an error from a relay or store. Our own error types know
+// whether they are transient; anything else (network hiccups, timeouts, I/O)
+// is assumed to be worth another try.
+pub fn is_retryable(err: &anyhow::Error) -> bool {
+    if let Some(err) = err.downcast_ref::<RelayError>() {
+        return err.is_retryable();
+    }
+    if let Some(err) = err.downcast_ref::<OutboxError>() {
+        return err.is_retryable();
+    }
+    true
+}
+
+// --- HTTP Webhook Implementation ---
+
+// Not every destination is a broker. Often the "broker" is just an HTTP
+// endpoint owned by another team. `WebhookRelay` POSTs the event payload to a
+// configured URL using `reqwest`, or with `with_json_body` the whole `Event`
+// as JSON. Any 2xx response counts as delivered; timeouts, connection errors
+// and other statuses come back as retryable `RelayError`s. It is behind the
+// `http-client` feature so the lesson builds without an HTTP stack:
+//
+// cargo run --features http-client
+
+#[cfg(feature = "http-client")]
+pub struct WebhookRelay {
+    client: reqwest::Client,
+    url: String,
+    headers: Vec<(String, String)>,
+    timeout: Duration,
+    json_body: bool,
+}
+
+#[cfg(feature = "http-client")]
+impl WebhookRelay {
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(WebhookRelay {
+            client: reqwest::Client::builder().build()?,
+            url: url.to_string(),
+            headers: Vec::new(),
+            timeout: Duration::from_secs(10),
+            json_body: false,
+        })
+    }
+
+    // Adds a static header (e.g. an auth token) sent with every request.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    // How long a single POST may take, including reading the response.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // Sends the serialized `Event`, with its id, timestamps and failure
+    // history, as an `application/json` body instead of the bare payload.
+    pub fn with_json_body(mut self) -> Self {
+        self.json_body = true;
+        self
+    }
+}
+
+#[cfg(feature = "http-client")]
+#[async_trait]
+impl MessageRelay for WebhookRelay {
+    #[instrument(name = "broker_send", skip_all, fields(broker = "webhook", url = %self.url))]
+    async fn publish_event(&self, event: &Event) -> Result<()> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .timeout(self.timeout)
+            .header("X-Outbox-Event-Id", &event.id);
+        request = if self.json_body {
+            request
+                .header("Content-Type", "application/json")
+                .body(serde_json::to_string(event)?)
+        } else {
+            request.body(event.payload.clone())
+        };
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+
+        let response = request.send().await.map_err(|e| {
+            if e.is_timeout() {
+                RelayError::DeliveryTimedOut {
+                    after: self.timeout,
+                }
+            } else {
+                RelayError::Connection(e.to_string())
+            }
+        })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(RelayError::DeliveryFailed {
+                status: status.as_u16(),
+            }
+            .into());
+        }
+        debug!(status = status.as_u16(), "webhook accepted event");
+        Ok(())
+    }
+
+    fn describe(&self) -> Vec<RelayDescriptor> {
+        vec![RelayDescriptor {
+            name: "WebhookRelay".to_string(),
+            target: Some(self.url.clone()),
+        }]
+    }
+}
+
 // --- Dummy Implementation for Demonstration ---
 
 pub struct DummyMessageRelay;
@@ -92,8 +317,3441 @@ impl MessageRelay for DummyMessageRelay {
     }
 }
 
-fn main() {
-    println!("This lesson focuses on the Message Relay component.");
-    println!("The code for this lesson is conceptual and demonstrates the trait");
-    println!("and dummy implementation. Real implementations would use crates like `lapin` or `async_nats`.");
+// --- The Outbox Side ---
+
+// The relayer reads from an outbox, so this lesson needs a store as well.
+// Lesson 14.2 builds the real ones (codecs, checksums, retention, Postgres,
+// Redis). Lessons are separate crates, so here is only the part of its
+// `OutboxStore` trait the relayer uses, with an in-memory store and a small
+// file store.
+
+use std::sync::{Arc, Mutex};
+use tokio::fs;
+
+#[derive(Debug, thiserror::Error)]
+pub enum OutboxError {
+    #[error("event {id} not found")]
+    EventNotFound { id: String },
+}
+
+impl OutboxError {
+    // Looking an unknown id up again won't make it appear.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            OutboxError::EventNotFound { .. } => false,
+        }
+    }
+}
+
+#[async_trait]
+pub trait OutboxStore: Send + Sync {
+    async fn save_event(&self, event: Event) -> Result<()>;
+    // Saves a batch of events. The default just calls `save_event` for each
+    // one; stores that can write a batch in one go should override it.
+    async fn save_events(&self, events: Vec<Event>) -> Result<()> {
+        for event in events {
+            self.save_event(event).await?;
+        }
+        Ok(())
+    }
+    // Oldest first.
+    async fn get_unprocessed_events(&self) -> Result<Vec<Event>>;
+    // Fails with `OutboxError::EventNotFound` if no event has this id.
+    async fn mark_event_processed(&self, event_id: &str) -> Result<()>;
+    // Deletes the given events outright. Returns how many were removed.
+    async fn remove_events(&self, ids: &[String]) -> Result<usize>;
+    // Records a failed delivery attempt on the stored event, like
+    // `Event::record_failure`, and returns the new `retry_count`. Fails with
+    // `OutboxError::EventNotFound` for an unknown id.
+    async fn record_failure(&self, id: &str, error: &str) -> Result<u32>;
+    // Flips a processed event back to unprocessed so the relayer sends it
+    // again. Returns false if it was already unprocessed. Fails with
+    // `OutboxError::EventNotFound` for an unknown id.
+    async fn reset_event(&self, id: &str) -> Result<bool>;
+}
+
+// Keeps events in a shared `Vec`. Clones share the same events, so a test
+// can keep one handle while the relayer owns another.
+#[derive(Clone, Default)]
+pub struct InMemoryOutboxStore {
+    events: Arc<Mutex<Vec<Event>>>,
+}
+
+impl InMemoryOutboxStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl OutboxStore for InMemoryOutboxStore {
+    async fn save_event(&self, event: Event) -> Result<()> {
+        self.events.lock().unwrap().push(event);
+        Ok(())
+    }
+
+    async fn get_unprocessed_events(&self) -> Result<Vec<Event>> {
+        let events = self.events.lock().unwrap();
+        let mut unprocessed: Vec<Event> = events.iter().filter(|e| !e.processed).cloned().collect();
+        unprocessed.sort_by_key(|e| e.created_at);
+        Ok(unprocessed)
+    }
+
+    async fn mark_event_processed(&self, event_id: &str) -> Result<()> {
+        update_event(&mut self.events.lock().unwrap(), event_id, |event| {
+            event.processed = true
+        })
+    }
+
+    async fn remove_events(&self, ids: &[String]) -> Result<usize> {
+        Ok(remove_ids(&mut self.events.lock().unwrap(), ids))
+    }
+
+    async fn record_failure(&self, id: &str, error: &str) -> Result<u32> {
+        update_event(&mut self.events.lock().unwrap(), id, |event| {
+            event.record_failure(error);
+            event.retry_count
+        })
+    }
+
+    async fn reset_event(&self, id: &str) -> Result<bool> {
+        update_event(&mut self.events.lock().unwrap(), id, |event| {
+            std::mem::replace(&mut event.processed, false)
+        })
+    }
+}
+
+// Applies `change` to the event with this id, or fails with
+// `OutboxError::EventNotFound`. Shared by both stores.
+fn update_event<T>(
+    events: &mut [Event],
+    id: &str,
+    change: impl FnOnce(&mut Event) -> T,
+) -> Result<T> {
+    match events.iter_mut().find(|e| e.id == id) {
+        Some(event) => Ok(change(event)),
+        None => Err(OutboxError::EventNotFound { id: id.to_string() }.into()),
+    }
+}
+
+fn remove_ids(events: &mut Vec<Event>, ids: &[String]) -> usize {
+    let before = events.len();
+    events.retain(|e| !ids.contains(&e.id));
+    before - events.len()
+}
+
+// One event per line as JSON, the format Lesson 14.2 writes with
+// `FileOutboxStore::with_codec(path, JsonCodec)`, so either lesson can read
+// the other's files. Every change reads the whole file and writes it back
+// through a temp file and a rename, so a crash never leaves a half-written
+// outbox. A lock stops two tasks from losing each other's changes.
+pub struct FileOutboxStore {
+    file_path: String,
+    write_lock: tokio::sync::Mutex<()>,
+}
+
+impl FileOutboxStore {
+    pub fn new(file_path: &str) -> Self {
+        FileOutboxStore {
+            file_path: file_path.to_string(),
+            write_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    // Every event in the file, processed or not, in file order. A missing
+    // file is an empty outbox.
+    pub async fn read_all_events(&self) -> Result<Vec<Event>> {
+        let contents = match fs::read_to_string(&self.file_path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", self.file_path)),
+        };
+        contents
+            .lines()
+            .enumerate()
+            .map(|(i, line)| {
+                serde_json::from_str(line)
+                    .with_context(|| format!("{} line {} is not an event", self.file_path, i + 1))
+            })
+            .collect()
+    }
+
+    // Reads every event, lets `change` edit them, and writes them all back.
+    async fn update<T: Send>(
+        &self,
+        change: impl FnOnce(&mut Vec<Event>) -> Result<T> + Send,
+    ) -> Result<T> {
+        let _guard = self.write_lock.lock().await;
+        let mut events = self.read_all_events().await?;
+        let result = change(&mut events)?;
+        let mut contents = String::new();
+        for event in &events {
+            contents += &serde_json::to_string(event)?;
+            contents.push('\n');
+        }
+        let tmp_path = format!("{}.tmp", self.file_path);
+        fs::write(&tmp_path, contents).await?;
+        fs::rename(&tmp_path, &self.file_path).await?;
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl OutboxStore for FileOutboxStore {
+    async fn save_event(&self, event: Event) -> Result<()> {
+        self.update(|events| {
+            events.push(event);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn save_events(&self, new_events: Vec<Event>) -> Result<()> {
+        self.update(|events| {
+            events.extend(new_events);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_unprocessed_events(&self) -> Result<Vec<Event>> {
+        let mut unprocessed: Vec<Event> = self
+            .read_all_events()
+            .await?
+            .into_iter()
+            .filter(|e| !e.processed)
+            .collect();
+        unprocessed.sort_by_key(|e| e.created_at);
+        Ok(unprocessed)
+    }
+
+    async fn mark_event_processed(&self, event_id: &str) -> Result<()> {
+        self.update(|events| update_event(events, event_id, |event| event.processed = true))
+            .await
+    }
+
+    async fn remove_events(&self, ids: &[String]) -> Result<usize> {
+        self.update(|events| Ok(remove_ids(events, ids))).await
+    }
+
+    async fn record_failure(&self, id: &str, error: &str) -> Result<u32> {
+        self.update(|events| {
+            update_event(events, id, |event| {
+                event.record_failure(error);
+                event.retry_count
+            })
+        })
+        .await
+    }
+
+    async fn reset_event(&self, id: &str) -> Result<bool> {
+        self.update(|events| {
+            update_event(events, id, |event| {
+                std::mem::replace(&mut event.processed, false)
+            })
+        })
+        .await
+    }
+}
+
+// --- Relaying Events from the Outbox ---
+
+use futures::stream::{self, StreamExt};
+use rand::Rng;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, info_span, instrument, warn, Instrument};
+
+// The relayer is the other half of the outbox pattern (see Lesson 14.1). It
+// reads unprocessed events from any `OutboxStore`, publishes each through a
+// `MessageRelay`, and marks it processed once the broker has accepted it.
+// Transient send failures are retried with capped exponential backoff.
+//
+// The relay path reports through `tracing` (Lesson 14.7) rather than
+// `println!`: every pass, event and send runs in a span, so each log line
+// carries the event id and attempt number it belongs to.
+
+// A relay that keeps what it receives in memory, for exercising relay logic
+// without Kafka or RabbitMQ. Clones share state, so a test can keep one
+// handle while the relayer owns another. `fail_next(n)` makes the next `n`
+// sends fail with a transient error; `with_max_payload_bytes` makes it reject
+// oversized events outright, like a real broker's message size limit.
+// `with_latency` makes every send take that long, like a network round-trip.
+#[derive(Clone, Default)]
+pub struct InMemoryRelay {
+    received: Arc<Mutex<Vec<Event>>>,
+    failures_left: Arc<AtomicUsize>,
+    max_payload_bytes: Option<usize>,
+    latency: Duration,
+}
+
+impl InMemoryRelay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_payload_bytes(mut self, limit: usize) -> Self {
+        self.max_payload_bytes = Some(limit);
+        self
+    }
+
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn fail_next(&self, n: usize) {
+        self.failures_left.store(n, Ordering::SeqCst);
+    }
+
+    pub fn received(&self) -> Vec<Event> {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl MessageRelay for InMemoryRelay {
+    #[instrument(name = "broker_send", skip_all, fields(broker = "in_memory"))]
+    async fn publish_event(&self, event: &Event) -> Result<()> {
+        if let Some(limit) = self.max_payload_bytes {
+            if event.payload.len() > limit {
+                let reason = format!(
+                    "payload of {} bytes exceeds the {} byte limit",
+                    event.payload.len(),
+                    limit
+                );
+                return Err(RelayError::Rejected { reason }.into());
+            }
+        }
+        let failing = self
+            .failures_left
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if failing {
+            return Err(anyhow!("InMemoryRelay: injected failure"));
+        }
+        if !self.latency.is_zero() {
+            time::sleep(self.latency).await;
+        }
+        self.received.lock().unwrap().push(event.clone());
+        debug!("broker accepted event");
+        Ok(())
+    }
+}
+
+// A relay that appends each event to a file as one line of JSON, so the
+// `relay` subcommand can run end to end without a broker
+// (`broker_url = "file:///var/spool/outbox.jsonl"`). A consumer can tail the
+// file. Sends are serialized so concurrent ones don't interleave lines.
+pub struct FileRelay {
+    file_path: String,
+    write_lock: tokio::sync::Mutex<()>,
+}
+
+impl FileRelay {
+    pub fn new(file_path: &str) -> Self {
+        FileRelay {
+            file_path: file_path.to_string(),
+            write_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl MessageRelay for FileRelay {
+    #[instrument(name = "broker_send", skip_all, fields(broker = "file", path = %self.file_path))]
+    async fn publish_event(&self, event: &Event) -> Result<()> {
+        let line = serde_json::to_string(event)? + "\n";
+        let _guard = self.write_lock.lock().await;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file_path)
+            .await
+            .with_context(|| format!("opening {}", self.file_path))?;
+        file.write_all(line.as_bytes()).await?;
+        // tokio's `File` may still hold the write; don't report delivery
+        // until it has reached the file.
+        file.flush().await?;
+        debug!("broker accepted event");
+        Ok(())
+    }
+
+    fn describe(&self) -> Vec<RelayDescriptor> {
+        vec![RelayDescriptor {
+            name: "FileRelay".to_string(),
+            target: Some(format!("file://{}", self.file_path)),
+        }]
+    }
+}
+
+// --- Kafka Relay (feature `kafka`) ---
+
+// Produces each event to a Kafka topic as JSON, keyed by event id so all
+// events with the same id land on the same partition. `send` waits for the
+// delivery report, so `Ok` means Kafka has acknowledged the write and the
+// relayer can safely mark the event processed. Needs librdkafka, which
+// `rdkafka` builds from source.
+
+#[cfg(feature = "kafka")]
+pub struct KafkaRelay {
+    producer: rdkafka::producer::FutureProducer,
+    topic: String,
+    delivery_timeout: Duration,
+}
+
+#[cfg(feature = "kafka")]
+impl KafkaRelay {
+    // `brokers` is a comma-separated `host:port` list. `delivery_timeout`
+    // bounds how long a send waits for Kafka to confirm the write.
+    pub fn new(brokers: &str, topic: &str, delivery_timeout: Duration) -> Result<Self> {
+        let producer = rdkafka::ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set(
+                "message.timeout.ms",
+                delivery_timeout.as_millis().to_string(),
+            )
+            .create()?;
+        Ok(KafkaRelay {
+            producer,
+            topic: topic.to_string(),
+            delivery_timeout,
+        })
+    }
+}
+
+#[cfg(feature = "kafka")]
+#[async_trait]
+impl MessageRelay for KafkaRelay {
+    #[instrument(name = "broker_send", skip_all, fields(broker = "kafka", topic = %self.topic))]
+    async fn publish_event(&self, event: &Event) -> Result<()> {
+        use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+        use rdkafka::producer::FutureRecord;
+
+        let payload = serde_json::to_string(event)?;
+        let record = FutureRecord::to(&self.topic)
+            .key(&event.id)
+            .payload(&payload);
+        match self.producer.send(record, self.delivery_timeout).await {
+            Ok((partition, offset)) => {
+                debug!(partition, offset, "kafka acknowledged event");
+                Ok(())
+            }
+            Err((KafkaError::MessageProduction(code), _)) => match code {
+                RDKafkaErrorCode::MessageTimedOut
+                | RDKafkaErrorCode::RequestTimedOut
+                | RDKafkaErrorCode::QueueFull => Err(RelayError::DeliveryTimedOut {
+                    after: self.delivery_timeout,
+                }
+                .into()),
+                RDKafkaErrorCode::MessageSizeTooLarge
+                | RDKafkaErrorCode::UnknownTopicOrPartition => Err(RelayError::Rejected {
+                    reason: code.to_string(),
+                }
+                .into()),
+                code => Err(anyhow!("kafka produce failed: {}", code)),
+            },
+            Err((e, _)) => Err(anyhow!("kafka produce failed: {}", e)),
+        }
+    }
+}
+
+// --- Circuit Breaker ---
+
+// When the broker is down, retrying every event just adds load to a service
+// that is already struggling. `CircuitBreaker` wraps a relay and, after
+// `failure_threshold` consecutive failures, "opens": sends fail immediately
+// with `RelayError::CircuitOpen` until `cooldown` has passed. It then goes
+// half-open and lets a single probe through. A successful probe closes the
+// circuit again; a failed one re-opens it for another cooldown. Only
+// retryable failures count: a `Rejected` event says something about that
+// event, not about the broker's health, so it neither opens nor closes it.
+
+fn circuit_open(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<RelayError>(),
+        Some(RelayError::CircuitOpen { .. })
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+struct BreakerState {
+    state: CircuitState,
+    consecutive_failures: u32,
+    opened_at: Option<time::Instant>,
+    // Set while the half-open probe is in flight, so only one gets through.
+    probing: bool,
+}
+
+pub struct CircuitBreaker<R: MessageRelay> {
+    inner: R,
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl<R: MessageRelay> CircuitBreaker<R> {
+    pub fn new(inner: R, failure_threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            inner,
+            failure_threshold,
+            cooldown,
+            state: Mutex::new(BreakerState {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                opened_at: None,
+                probing: false,
+            }),
+        }
+    }
+
+    // Current state, e.g. for a health monitor to report.
+    pub fn state(&self) -> CircuitState {
+        self.state.lock().unwrap().state
+    }
+
+    // Decides whether a send may go through, moving Open -> HalfOpen once the
+    // cooldown has passed. Returns `true` if this send is the half-open probe.
+    fn try_acquire(&self) -> Result<bool, RelayError> {
+        let mut s = self.state.lock().unwrap();
+        match s.state {
+            CircuitState::Closed => Ok(false),
+            CircuitState::Open => {
+                let elapsed = s.opened_at.map(|at| at.elapsed()).unwrap_or(self.cooldown);
+                if elapsed < self.cooldown {
+                    return Err(RelayError::CircuitOpen {
+                        retry_in: self.cooldown - elapsed,
+                    });
+                }
+                s.state = CircuitState::HalfOpen;
+                s.probing = true;
+                Ok(true)
+            }
+            CircuitState::HalfOpen if s.probing => Err(RelayError::CircuitOpen {
+                retry_in: Duration::ZERO,
+            }),
+            CircuitState::HalfOpen => {
+                s.probing = true;
+                Ok(true)
+            }
+        }
+    }
+
+    fn record(&self, success: bool) {
+        let mut s = self.state.lock().unwrap();
+        if success {
+            s.state = CircuitState::Closed;
+            s.consecutive_failures = 0;
+            s.opened_at = None;
+            return;
+        }
+        s.consecutive_failures += 1;
+        if s.state == CircuitState::HalfOpen || s.consecutive_failures >= self.failure_threshold {
+            s.state = CircuitState::Open;
+            s.opened_at = Some(time::Instant::now());
+        }
+    }
+}
+
+#[async_trait]
+impl<R: MessageRelay> MessageRelay for CircuitBreaker<R> {
+    #[instrument(name = "circuit_breaker_send", skip_all)]
+    async fn publish_event(&self, event: &Event) -> Result<()> {
+        let is_probe = match self.try_acquire() {
+            Ok(is_probe) => is_probe,
+            Err(e) => {
+                debug!(error = %e, "short-circuited");
+                return Err(e.into());
+            }
+        };
+        let _probe = is_probe.then(|| ProbeGuard(&self.state));
+        let result = self.inner.publish_event(event).await;
+        match &result {
+            Ok(()) => self.record(true),
+            Err(e) if is_retryable(e) => self.record(false),
+            Err(_) => {}
+        }
+        result
+    }
+
+    fn describe(&self) -> Vec<RelayDescriptor> {
+        self.inner.describe()
+    }
+}
+
+// Ends the half-open probe when dropped, however the send finished. A probe
+// whose future is dropped mid-send (by a caller's timeout, say) would
+// otherwise leave `probing` set and the breaker half-open for good.
+struct ProbeGuard<'a>(&'a Mutex<BreakerState>);
+
+impl Drop for ProbeGuard<'_> {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().probing = false;
+    }
+}
+
+// --- Deduplicating Relay ---
+
+// At-least-once delivery can send an event twice. `DedupRelay` remembers
+// the ids of the last `capacity` events it forwarded and answers `Ok` to a
+// repeat without calling the inner broker, which approximates exactly-once
+// for duplicates that arrive within that window. The window is evicted
+// least-recently-used first; seeing an id again makes it recent. An id is
+// claimed before the inner send, so two concurrent sends of one id can't
+// both get through, and released again if the send fails so it can be
+// retried. The window lives in memory and is lost on restart, so it
+// complements an idempotent consumer rather than replacing one.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+struct SeenIds {
+    ids: HashSet<String>,
+    // Least recently used at the front.
+    order: VecDeque<String>,
+}
+
+pub struct DedupRelay<R: MessageRelay> {
+    inner: R,
+    capacity: usize,
+    seen: Mutex<SeenIds>,
+    skipped: AtomicU64,
+}
+
+impl<R: MessageRelay> DedupRelay<R> {
+    pub fn new(inner: R, capacity: usize) -> Self {
+        DedupRelay {
+            inner,
+            capacity: capacity.max(1),
+            seen: Mutex::new(SeenIds {
+                ids: HashSet::new(),
+                order: VecDeque::new(),
+            }),
+            skipped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    // How many sends were answered from the window without a broker call.
+    pub fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    // Returns false if `id` is already in the window (refreshing it), and
+    // otherwise records it, evicting the least recently used id if full.
+    fn claim(&self, id: &str) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        if seen.ids.contains(id) {
+            if let Some(pos) = seen.order.iter().position(|seen_id| seen_id == id) {
+                let id = seen.order.remove(pos).unwrap();
+                seen.order.push_back(id);
+            }
+            return false;
+        }
+        if seen.order.len() >= self.capacity {
+            if let Some(oldest) = seen.order.pop_front() {
+                seen.ids.remove(&oldest);
+            }
+        }
+        seen.ids.insert(id.to_string());
+        seen.order.push_back(id.to_string());
+        true
+    }
+
+    fn release(&self, id: &str) {
+        let mut seen = self.seen.lock().unwrap();
+        if seen.ids.remove(id) {
+            seen.order.retain(|seen_id| seen_id != id);
+        }
+    }
+}
+
+#[async_trait]
+impl<R: MessageRelay> MessageRelay for DedupRelay<R> {
+    #[instrument(name = "dedup_send", skip_all, fields(event_id = %event.id))]
+    async fn publish_event(&self, event: &Event) -> Result<()> {
+        if !self.claim(&event.id) {
+            self.skipped.fetch_add(1, Ordering::Relaxed);
+            debug!("duplicate event skipped");
+            return Ok(());
+        }
+        let result = self.inner.publish_event(event).await;
+        if result.is_err() {
+            self.release(&event.id);
+        }
+        result
+    }
+
+    fn describe(&self) -> Vec<RelayDescriptor> {
+        self.inner.describe()
+    }
+}
+
+// What a single `run_once` pass did. `retried` counts extra attempts, so an
+// event that succeeds on its third try adds 1 to `sent` and 2 to `retried`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RelayStats {
+    pub sent: usize,
+    pub failed: usize,
+    pub retried: usize,
+    // Failed events moved to the dead-letter store (also counted in `failed`).
+    pub dead_lettered: usize,
+}
+
+impl std::ops::AddAssign for RelayStats {
+    fn add_assign(&mut self, other: RelayStats) {
+        self.sent += other.sent;
+        self.failed += other.failed;
+        self.retried += other.retried;
+        self.dead_lettered += other.dead_lettered;
+    }
+}
+
+// --- Metrics ---
+
+// `RelayStats` describes one pass; `Metrics` keeps running totals for the
+// life of the process, which is what dashboards and alerts want. The counters
+// are atomics, so one `Arc<Metrics>` can be shared by several relayers and
+// by worker code without a lock. Batch workers report completed jobs through
+// `process_batch_parallel_with_metrics`.
+
+use std::sync::atomic::AtomicU64;
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    events_relayed: AtomicU64,
+    events_failed: AtomicU64,
+    jobs_processed: AtomicU64,
+    retries: AtomicU64,
+}
+
+// A point-in-time copy of the counters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub events_relayed: u64,
+    pub events_failed: u64,
+    pub jobs_processed: u64,
+    pub retries: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_relayed(&self) {
+        self.events_relayed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_failed(&self) {
+        self.events_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_job(&self) {
+        self.jobs_processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Each counter is read on its own, so a snapshot taken while events are
+    // in flight may be off by one between counters. Fine for monitoring.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let read = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        MetricsSnapshot {
+            events_relayed: read(&self.events_relayed),
+            events_failed: read(&self.events_failed),
+            jobs_processed: read(&self.jobs_processed),
+            retries: read(&self.retries),
+        }
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl MetricsSnapshot {
+    // Renders the counters in the Prometheus text format, ready to be served
+    // from a `/metrics` endpoint.
+    pub fn to_prometheus(&self) -> String {
+        let counters = [
+            (
+                "outbox_events_relayed_total",
+                "Events delivered to the broker.",
+                self.events_relayed,
+            ),
+            (
+                "outbox_events_failed_total",
+                "Events that failed permanently or ran out of retries.",
+                self.events_failed,
+            ),
+            (
+                "outbox_jobs_processed_total",
+                "Jobs completed by workers.",
+                self.jobs_processed,
+            ),
+            (
+                "outbox_retries_total",
+                "Send attempts that were retried.",
+                self.retries,
+            ),
+        ];
+        let mut out = String::new();
+        for (name, help, value) in counters {
+            out.push_str(&format!(
+                "# HELP {} {}\n# TYPE {} counter\n{} {}\n",
+                name, help, name, name, value
+            ));
+        }
+        out
+    }
+}
+
+// --- Dead-letter Store ---
+
+// Events that keep failing ("poison" events) would otherwise be retried on
+// every pass forever. The relayer can park them in a `DeadLetterStore`
+// instead, with `retry_count` and `last_error` kept for whoever investigates.
+// Once the cause is fixed, `requeue` puts a dead letter back in the outbox.
+
+#[async_trait]
+pub trait DeadLetterStore: Send + Sync {
+    async fn park(&self, event: Event) -> Result<()>;
+    async fn list(&self) -> Result<Vec<Event>>;
+    // Moves the dead letter back into `outbox` with its failure history
+    // reset. Fails with `OutboxError::EventNotFound` for an unknown id.
+    async fn requeue(&self, id: &str, outbox: &dyn OutboxStore) -> Result<()>;
+}
+
+// Keeps dead letters in their own outbox-format file, written through a
+// `FileOutboxStore`.
+pub struct FileDeadLetterStore {
+    file_path: String,
+}
+
+impl FileDeadLetterStore {
+    pub fn new(file_path: &str) -> Self {
+        FileDeadLetterStore {
+            file_path: file_path.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl DeadLetterStore for FileDeadLetterStore {
+    async fn park(&self, event: Event) -> Result<()> {
+        FileOutboxStore::new(&self.file_path)
+            .save_event(event)
+            .await
+    }
+
+    async fn list(&self) -> Result<Vec<Event>> {
+        FileOutboxStore::new(&self.file_path)
+            .get_unprocessed_events()
+            .await
+    }
+
+    async fn requeue(&self, id: &str, outbox: &dyn OutboxStore) -> Result<()> {
+        let store = FileOutboxStore::new(&self.file_path);
+        let mut event = match store
+            .get_unprocessed_events()
+            .await?
+            .into_iter()
+            .find(|e| e.id == id)
+        {
+            Some(event) => event,
+            None => return Err(OutboxError::EventNotFound { id: id.to_string() }.into()),
+        };
+        event.retry_count = 0;
+        event.last_error = None;
+        // Save to the outbox before removing the dead letter, so a failure in
+        // between leaves a duplicate rather than losing the event.
+        outbox.save_event(event).await?;
+        store.remove_events(&[id.to_string()]).await?;
+        Ok(())
+    }
+}
+
+// Which of the two relay steps comes first, and so what a crash between
+// them costs (see Lesson 14.1). Exactly-once needs an idempotent consumer
+// on top of `AtLeastOnce`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeliveryMode {
+    // Send, then mark processed. A crash in between leaves the event
+    // unprocessed, so the next pass sends it again: no loss, but duplicates.
+    #[default]
+    AtLeastOnce,
+    // Mark processed, then send once, with no retries. A crash in between,
+    // or a failed send, means the event is never delivered: no duplicates,
+    // but losses. Failed sends still go to the dead-letter store.
+    AtMostOnce,
+}
+
+pub struct MessageRelayer<S: OutboxStore, R: MessageRelay> {
+    store: S,
+    relay: R,
+    delivery_mode: DeliveryMode,
+    max_retries: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    // Longest wait `run` backs off to while the outbox stays empty.
+    max_idle_interval: Duration,
+    dead_letters: Option<Box<dyn DeadLetterStore>>,
+    metrics: Arc<Metrics>,
+}
+
+impl<S: OutboxStore, R: MessageRelay> MessageRelayer<S, R> {
+    pub fn new(store: S, relay: R) -> Self {
+        MessageRelayer {
+            store,
+            relay,
+            delivery_mode: DeliveryMode::AtLeastOnce,
+            max_retries: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            max_idle_interval: Duration::from_secs(30),
+            dead_letters: None,
+            metrics: Arc::new(Metrics::new()),
+        }
+    }
+
+    pub fn with_delivery_mode(mut self, delivery_mode: DeliveryMode) -> Self {
+        self.delivery_mode = delivery_mode;
+        self
+    }
+
+    pub fn with_max_idle_interval(mut self, max_idle_interval: Duration) -> Self {
+        self.max_idle_interval = max_idle_interval;
+        self
+    }
+
+    // Reports into a shared `Metrics` instead of the relayer's own.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    // Events whose `retry_count` exceeds `max_retries` are moved out of the
+    // outbox into `store` instead of being left for the next pass.
+    pub fn with_dead_letter_store(mut self, store: impl DeadLetterStore + 'static) -> Self {
+        self.dead_letters = Some(Box::new(store));
+        self
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    // The delay before retry `n` is `base * 2^(n-1)`, never more than `max`.
+    pub fn with_backoff(mut self, base: Duration, max: Duration) -> Self {
+        self.base_delay = base;
+        self.max_delay = max;
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn relay(&self) -> &R {
+        &self.relay
+    }
+
+    fn backoff_delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    // Relays continuously: a `run_once` pass right away, then one every
+    // `interval` until `cancel` fires. Each wait gets up to 10% random jitter
+    // so several relayer instances started together don't poll in lockstep.
+    // While passes find nothing to do, the wait doubles (up to
+    // `max_idle_interval`) to save work when the system is quiet, and drops
+    // back to `interval` as soon as a pass finds events. A failed pass is
+    // logged and the loop carries on, since store errors are often transient.
+    pub async fn run(&self, interval: Duration, cancel: CancellationToken) {
+        let mut wait = interval;
+        while !cancel.is_cancelled() {
+            match self.run_once().await {
+                Ok(stats) if stats.sent + stats.failed == 0 => {
+                    wait = wait
+                        .saturating_mul(2)
+                        .min(self.max_idle_interval.max(interval));
+                }
+                Ok(_) => wait = interval,
+                Err(e) => error!(error = %e, "relay pass failed"),
+            }
+
+            let jitter_ms = rand::thread_rng().gen_range(0..=wait.as_millis() as u64 / 10);
+            let delay = wait + Duration::from_millis(jitter_ms);
+            debug!(?delay, "waiting for the next relay pass");
+            tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = time::sleep(delay) => {}
+            }
+        }
+        info!("relay loop stopped");
+    }
+
+    // Drains the unprocessed events once. Each event is marked processed as
+    // soon as the broker accepts it, so a crash mid-pass re-sends as little
+    // as possible (`DeliveryMode::AtMostOnce` marks it before sending).
+    // Events whose stored `retry_count` goes past `max_retries` are
+    // dead-lettered if a dead-letter store is configured, and otherwise stay
+    // unprocessed for a later pass. Errors that `is_retryable` calls fatal
+    // skip the retries and go straight to the dead-letter store. If the
+    // broker is a `CircuitBreaker` that is open, the pass stops there and
+    // the remaining events wait, untouched, for a later one.
+    #[instrument(name = "relay_pass", skip(self))]
+    pub async fn run_once(&self) -> Result<RelayStats> {
+        let mut stats = RelayStats::default();
+        for event in self.store.get_unprocessed_events().await? {
+            if self.relay_event(event, &mut stats).await?.is_break() {
+                break;
+            }
+        }
+        info!(
+            sent = stats.sent,
+            failed = stats.failed,
+            retried = stats.retried,
+            "relay pass finished"
+        );
+        Ok(stats)
+    }
+
+    // Like `run_once`, but relays up to `max_parallel` events at a time.
+    // Events are grouped by `key_of` (an aggregate id, say). Different keys
+    // go concurrently, and each key's events are sent strictly in outbox
+    // order, one at a time. If an event isn't delivered, the rest of its key
+    // is held back for a later pass, so nothing overtakes it. A store error
+    // ends only its own key's group; the first such error is returned once
+    // every group has stopped, so no send is abandoned halfway.
+    #[instrument(name = "relay_pass_concurrent", skip(self, key_of))]
+    pub async fn run_once_concurrent(
+        &self,
+        max_parallel: usize,
+        key_of: impl Fn(&Event) -> String,
+    ) -> Result<RelayStats> {
+        // Keys in order of their first event, each with its events in order.
+        let mut groups: Vec<Vec<Event>> = Vec::new();
+        let mut group_of_key: HashMap<String, usize> = HashMap::new();
+        for event in self.store.get_unprocessed_events().await? {
+            let index = *group_of_key.entry(key_of(&event)).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[index].push(event);
+        }
+
+        let results: Vec<Result<RelayStats>> = stream::iter(groups)
+            .map(|events| async move {
+                let mut stats = RelayStats::default();
+                for event in events {
+                    let sent_before = stats.sent;
+                    if self.relay_event(event, &mut stats).await?.is_break()
+                        || stats.sent == sent_before
+                    {
+                        break;
+                    }
+                }
+                Ok(stats)
+            })
+            .buffer_unordered(max_parallel.max(1))
+            .collect()
+            .await;
+
+        let mut stats = RelayStats::default();
+        for result in results {
+            stats += result?;
+        }
+        info!(
+            sent = stats.sent,
+            failed = stats.failed,
+            retried = stats.retried,
+            "relay pass finished"
+        );
+        Ok(stats)
+    }
+
+    // Sends one event, retrying as configured. Each broker call gets its own
+    // `attempt` span nested under the event's span. Every failed attempt is
+    // recorded in the store, so `retry_count` counts failures across passes:
+    // once it exceeds `max_retries` the event is dead-lettered, or without a
+    // dead-letter store, left for the next pass to try once more. An open
+    // circuit isn't a failure of this event, so it is neither recorded nor
+    // retried; `Break` tells the pass to stop.
+    #[instrument(name = "relay_event", skip(self, event, stats), fields(event_id = %event.id))]
+    async fn relay_event(
+        &self,
+        mut event: Event,
+        stats: &mut RelayStats,
+    ) -> Result<ControlFlow<()>> {
+        if self.delivery_mode == DeliveryMode::AtMostOnce {
+            return self.relay_at_most_once(event, stats).await;
+        }
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let sent = self
+                .relay
+                .publish_event(&event)
+                .instrument(info_span!("attempt", attempt))
+                .await;
+            let e = match sent {
+                Ok(()) => {
+                    self.store.mark_event_processed(&event.id).await?;
+                    info!(attempt, "event sent");
+                    stats.sent += 1;
+                    self.metrics.record_relayed();
+                    return Ok(ControlFlow::Continue(()));
+                }
+                Err(e) => e,
+            };
+            if circuit_open(&e) {
+                warn!(attempt, error = %e, "circuit open; ending the pass");
+                return Ok(ControlFlow::Break(()));
+            }
+
+            let error = e.to_string();
+            event.retry_count = self.store.record_failure(&event.id, &error).await?;
+            event.last_error = Some(error);
+            if !is_retryable(&e) {
+                error!(attempt, error = %e, "event failed permanently");
+                stats.failed += 1;
+                self.metrics.record_failed();
+                self.dead_letter(event, stats).await?;
+                return Ok(ControlFlow::Continue(()));
+            }
+            if event.retry_count > self.max_retries {
+                error!(attempt, retry_count = event.retry_count, error = %e, "giving up on event");
+                stats.failed += 1;
+                self.metrics.record_failed();
+                self.dead_letter(event, stats).await?;
+                return Ok(ControlFlow::Continue(()));
+            }
+            stats.retried += 1;
+            self.metrics.record_retry();
+            let delay = self.backoff_delay(event.retry_count);
+            warn!(attempt, retry_count = event.retry_count, error = %e, ?delay, "send failed; retrying");
+            time::sleep(delay).await;
+        }
+    }
+
+    // Once the event is marked processed no later pass will read it, so this
+    // single send is its only chance. A retry could duplicate it, since a
+    // failed send may still have reached the broker. An open circuit still
+    // costs this event, but ends the pass so the rest aren't marked too.
+    async fn relay_at_most_once(
+        &self,
+        mut event: Event,
+        stats: &mut RelayStats,
+    ) -> Result<ControlFlow<()>> {
+        self.store.mark_event_processed(&event.id).await?;
+        let sent = self
+            .relay
+            .publish_event(&event)
+            .instrument(info_span!("attempt", attempt = 1))
+            .await;
+        match sent {
+            Ok(()) => {
+                info!(attempt = 1, "event sent");
+                stats.sent += 1;
+                self.metrics.record_relayed();
+                Ok(ControlFlow::Continue(()))
+            }
+            Err(e) => {
+                event.record_failure(&e.to_string());
+                error!(attempt = 1, error = %e, "event dropped (at-most-once)");
+                stats.failed += 1;
+                self.metrics.record_failed();
+                self.dead_letter(event, stats).await?;
+                Ok(if circuit_open(&e) {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                })
+            }
+        }
+    }
+
+    // Moves the event from the outbox to the dead-letter store, if there is one.
+    async fn dead_letter(&self, event: Event, stats: &mut RelayStats) -> Result<()> {
+        if let Some(dead_letters) = &self.dead_letters {
+            let id = event.id.clone();
+            dead_letters.park(event).await?;
+            self.store.remove_events(&[id]).await?;
+            stats.dead_lettered += 1;
+            warn!("event moved to the dead-letter store");
+        }
+        Ok(())
+    }
+}
+
+// --- Leader Election ---
+
+// Running several relayer processes against the same `FileOutboxStore` would
+// send every event multiple times. A simple fix is to let only one instance,
+// the "leader", run the relay loop. Leadership is represented by a lock file
+// created with `create_new(true)`, which fails if the file already exists.
+// The leader periodically replaces the file with a fresh timestamp (a
+// heartbeat), writing a temp file and renaming it over the lock so readers
+// never see a half-written one. If the timestamp goes stale, the leader is
+// assumed dead and another instance may take the lock over. A leader that
+// was only slow finds someone else's id in the lock at its next heartbeat;
+// it then stops heartbeating and reports the loss through `lost()`.
+//
+// This is deliberately simple: two followers that notice a stale lock at the
+// same instant can race, and so can a takeover and a heartbeat. Real
+// deployments use a database advisory lock or a coordination service (etcd,
+// Consul) instead.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+
+pub struct LeaderGuard {
+    lock_path: String,
+    instance_id: String,
+    heartbeat: JoinHandle<()>,
+    // Cancelled by the heartbeat task once the lock no longer names us.
+    lost: CancellationToken,
+}
+
+fn unix_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+impl LeaderGuard {
+    // Waits until this instance becomes leader, polling every `stale_after / 2`.
+    pub async fn acquire(
+        lock_path: &str,
+        instance_id: &str,
+        stale_after: Duration,
+    ) -> Result<Self> {
+        loop {
+            if let Some(guard) = Self::try_acquire(lock_path, instance_id, stale_after).await? {
+                return Ok(guard);
+            }
+            time::sleep(stale_after / 2).await;
+        }
+    }
+
+    // Returns `Ok(None)` if another live instance currently holds the lock.
+    pub async fn try_acquire(
+        lock_path: &str,
+        instance_id: &str,
+        stale_after: Duration,
+    ) -> Result<Option<Self>> {
+        if !Self::create_lock_file(lock_path, instance_id).await? {
+            if !Self::is_stale(lock_path, stale_after).await? {
+                return Ok(None);
+            }
+            // The previous leader stopped heartbeating; take over.
+            let _ = fs::remove_file(lock_path).await;
+            if !Self::create_lock_file(lock_path, instance_id).await? {
+                return Ok(None);
+            }
+        }
+
+        let lost = CancellationToken::new();
+        let heartbeat = tokio::spawn(Self::heartbeat(
+            lock_path.to_string(),
+            instance_id.to_string(),
+            stale_after / 3,
+            lost.clone(),
+        ));
+
+        Ok(Some(LeaderGuard {
+            lock_path: lock_path.to_string(),
+            instance_id: instance_id.to_string(),
+            heartbeat,
+            lost,
+        }))
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    // False once another instance has taken the lock over.
+    pub fn is_leader(&self) -> bool {
+        !self.lost.is_cancelled()
+    }
+
+    // A token that is cancelled when this instance loses the lock. Pass it to
+    // `MessageRelayer::run` so the relay loop stops as soon as someone else
+    // may be running theirs.
+    pub fn lost(&self) -> CancellationToken {
+        self.lost.clone()
+    }
+
+    // Stops heartbeating and removes the lock file so a follower can take over
+    // immediately instead of waiting for the lock to go stale.
+    pub async fn release(self) -> Result<()> {
+        self.heartbeat.abort();
+        let contents = fs::read_to_string(&self.lock_path)
+            .await
+            .unwrap_or_default();
+        if contents.split('|').next() == Some(self.instance_id.as_str()) {
+            fs::remove_file(&self.lock_path).await?;
+        }
+        Ok(())
+    }
+
+    // Refreshes the lock every `every` for as long as it still names
+    // `instance_id`. Checking first means a leader that lost the lock while it
+    // was stalled stops instead of overwriting the new leader's heartbeat.
+    async fn heartbeat(
+        lock_path: String,
+        instance_id: String,
+        every: Duration,
+        lost: CancellationToken,
+    ) {
+        let mut interval = time::interval(every);
+        // The first tick is immediate, and the lock was only just written.
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let contents = fs::read_to_string(&lock_path).await.unwrap_or_default();
+            if contents.split('|').next() != Some(instance_id.as_str()) {
+                warn!(lock = %lock_path, instance = %instance_id, "lost the leader lock");
+                lost.cancel();
+                return;
+            }
+            if let Err(e) = Self::write_lock_file(&lock_path, &instance_id).await {
+                warn!(lock = %lock_path, error = %e, "failed to refresh the leader lock");
+            }
+        }
+    }
+
+    // Writes `<lock>.<id>.tmp` and renames it over the lock, which replaces
+    // the file in one step.
+    async fn write_lock_file(lock_path: &str, instance_id: &str) -> Result<()> {
+        let tmp_path = format!("{}.{}.tmp", lock_path, instance_id);
+        fs::write(&tmp_path, format!("{}|{}", instance_id, unix_millis())).await?;
+        fs::rename(&tmp_path, lock_path).await?;
+        Ok(())
+    }
+
+    async fn create_lock_file(lock_path: &str, instance_id: &str) -> Result<bool> {
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(lock_path)
+            .await
+        {
+            Ok(mut file) => {
+                file.write_all(format!("{}|{}", instance_id, unix_millis()).as_bytes())
+                    .await?;
+                // Wait for tokio's background write to land before returning.
+                file.flush().await?;
+                Ok(true)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // A lock that was just created can still be empty. Its timestamp can't be
+    // parsed then, so the file's modification time stands in for it: a
+    // lock caught mid-write is fresh, while one left empty by a crash still
+    // goes stale eventually.
+    async fn is_stale(lock_path: &str, stale_after: Duration) -> Result<bool> {
+        let not_found = |e: &std::io::Error| e.kind() == std::io::ErrorKind::NotFound;
+        let contents = match fs::read_to_string(lock_path).await {
+            Ok(contents) => contents,
+            // The leader released the lock between our checks.
+            Err(e) if not_found(&e) => return Ok(true),
+            Err(e) => return Err(e.into()),
+        };
+        let last_beat = match contents
+            .split('|')
+            .nth(1)
+            .and_then(|ts| ts.trim().parse::<u128>().ok())
+        {
+            Some(last_beat) => last_beat,
+            None => match fs::metadata(lock_path).await {
+                Ok(metadata) => metadata
+                    .modified()?
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis(),
+                Err(e) if not_found(&e) => return Ok(true),
+                Err(e) => return Err(e.into()),
+            },
+        };
+        Ok(unix_millis().saturating_sub(last_beat) > stale_after.as_millis())
+    }
+}
+
+impl Drop for LeaderGuard {
+    // If the guard is dropped without `release`, the heartbeat stops and the
+    // lock goes stale after `stale_after`, letting a follower take over.
+    fn drop(&mut self) {
+        self.heartbeat.abort();
+    }
+}
+
+// --- Parallel Batch Processing ---
+
+// For pure-CPU work on a drained batch (validation, enrichment, encoding...)
+// there's no reason to go one event at a time. `process_batch_parallel` uses
+// rayon's `par_iter` (Lesson 14.5) to spread the batch across all cores. The
+// handler may run in any order on any thread, so it shouldn't have side
+// effects whose order matters. Because `par_iter` is indexed, `collect` still
+// returns result `i` for event `i`, and one failing event doesn't stop the
+// others.
+
+use rayon::prelude::*;
+
+pub fn process_batch_parallel(
+    events: Vec<Event>,
+    handler: impl Fn(&Event) -> Result<()> + Sync,
+) -> Vec<Result<()>> {
+    events.par_iter().map(&handler).collect()
+}
+
+// The same, reporting each event the handler completes to `metrics` as a
+// processed job. Failed events aren't counted; their errors are in the results.
+pub fn process_batch_parallel_with_metrics(
+    events: Vec<Event>,
+    metrics: &Metrics,
+    handler: impl Fn(&Event) -> Result<()> + Sync,
+) -> Vec<Result<()>> {
+    process_batch_parallel(events, |event| {
+        handler(event)?;
+        metrics.record_job();
+        Ok(())
+    })
+}
+
+// --- Configuration ---
+
+// Settings for the whole bridge, read in layers with the `config` crate:
+// built-in defaults, then a TOML file if one exists, then `OUTBOX_*`
+// environment variables, each layer overriding the one before. For example
+// `OUTBOX_MAX_RETRIES=5` beats `max_retries = 2` in the file. Durations are
+// given in whole milliseconds (`poll_interval_ms`, `retry_base_delay_ms`).
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BridgeConfig {
+    pub outbox_path: String,
+    #[serde(rename = "poll_interval_ms", deserialize_with = "duration_from_millis")]
+    pub poll_interval: Duration,
+    pub max_retries: u32,
+    // The relayer's backoff: the first retry waits `retry_base_delay`, and
+    // each one after that twice as long, up to `retry_max_delay`.
+    #[serde(
+        rename = "retry_base_delay_ms",
+        deserialize_with = "duration_from_millis"
+    )]
+    pub retry_base_delay: Duration,
+    #[serde(
+        rename = "retry_max_delay_ms",
+        deserialize_with = "duration_from_millis"
+    )]
+    pub retry_max_delay: Duration,
+    // Where events go; see `relay()`. `memory://` makes the `relay`
+    // subcommand a dry run.
+    pub broker_url: String,
+    // Threads for CPU-bound batch work such as `process_batch_parallel`.
+    pub worker_count: usize,
+}
+
+fn duration_from_millis<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_millis)
+}
+
+impl BridgeConfig {
+    // A missing file is fine (defaults and environment still apply); a file
+    // that doesn't parse, or a value of the wrong type, is an error.
+    pub fn load(path: &str) -> Result<Self> {
+        Self::build(path, None)
+    }
+
+    // Like `load`, but the `OUTBOX_*` variables come from `env` instead of the
+    // process environment. Tests and demos use it to override settings:
+    // `std::env::set_var` would race with any other thread reading the
+    // environment.
+    pub fn load_with_env(path: &str, env: HashMap<String, String>) -> Result<Self> {
+        Self::build(path, Some(env))
+    }
+
+    fn build(path: &str, env: Option<HashMap<String, String>>) -> Result<Self> {
+        let settings = config::Config::builder()
+            .set_default("outbox_path", "outbox_events.txt")?
+            .set_default("poll_interval_ms", 500)?
+            .set_default("max_retries", 3)?
+            .set_default("retry_base_delay_ms", 100)?
+            .set_default("retry_max_delay_ms", 5000)?
+            .set_default("broker_url", "memory://")?
+            .set_default("worker_count", 4)?
+            .add_source(config::File::new(path, config::FileFormat::Toml).required(false))
+            .add_source(
+                config::Environment::with_prefix("OUTBOX")
+                    .try_parsing(true)
+                    .source(env),
+            )
+            .build()
+            .with_context(|| format!("loading bridge config from {}", path))?;
+        Ok(settings.try_deserialize()?)
+    }
+
+    // A rayon pool sized for `worker_count`; run batch work inside
+    // `pool.install(..)`.
+    pub fn worker_pool(&self) -> Result<rayon::ThreadPool> {
+        Ok(rayon::ThreadPoolBuilder::new()
+            .num_threads(self.worker_count.max(1))
+            .build()?)
+    }
+
+    // The relay `broker_url` names: `memory://` keeps events in an
+    // `InMemoryRelay`, `file://PATH` appends them to PATH with a `FileRelay`,
+    // and with the `http-client` feature an `http(s)://` URL posts them as
+    // JSON to that endpoint.
+    pub fn relay(&self) -> Result<Box<dyn MessageRelay>> {
+        let url = self.broker_url.as_str();
+        if url == "memory://" {
+            return Ok(Box::new(InMemoryRelay::new()));
+        }
+        if let Some(path) = url.strip_prefix("file://") {
+            return Ok(Box::new(FileRelay::new(path)));
+        }
+        #[cfg(feature = "http-client")]
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Ok(Box::new(WebhookRelay::new(url)?.with_json_body()));
+        }
+        Err(anyhow!("unsupported broker_url {}", url))
+    }
+
+    // A relayer for `store` with this config's retry limit and backoff.
+    // `relay` is usually `self.relay()?`; tests and demos pass their own.
+    pub fn relayer<S: OutboxStore, R: MessageRelay>(
+        &self,
+        store: S,
+        relay: R,
+    ) -> MessageRelayer<S, R> {
+        MessageRelayer::new(store, relay)
+            .with_max_retries(self.max_retries)
+            .with_backoff(self.retry_base_delay, self.retry_max_delay)
+    }
+}
+
+// --- Command-line Interface ---
+
+// With a subcommand the binary works as an operator tool on a
+// `FileOutboxStore` (at `--path`, or `outbox_path` from the bridge config);
+// without one it runs the lesson demo below. Each handler returns its output
+// as a string, so it can be called directly without spawning the process.
+
+use clap::{Parser, Subcommand};
+
+#[derive(Parser)]
+#[command(name = "outbox", about = "Run and inspect a file-based outbox")]
+struct Cli {
+    #[arg(
+        long,
+        global = true,
+        help = "Outbox file (defaults to outbox_path from the bridge config)"
+    )]
+    path: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(
+        about = "Relay events to broker_url until Ctrl-C; with memory:// only list what would be sent"
+    )]
+    Relay,
+    #[command(about = "Print the unprocessed events, oldest first")]
+    List,
+    #[command(about = "Count processed and unprocessed events")]
+    Stats,
+    #[command(about = "Mark a processed event unprocessed so it is sent again")]
+    Requeue { id: String },
+    #[command(about = "Check that every unprocessed payload is valid JSON")]
+    Check,
+}
+
+async fn run_command(command: Command, path: &str, bridge: &BridgeConfig) -> Result<String> {
+    if let Command::Relay = command {
+        let cancel = CancellationToken::new();
+        let ctrl_c = cancel.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                ctrl_c.cancel();
+            }
+        });
+        return relay_command(path, bridge, cancel).await;
+    }
+    let store = FileOutboxStore::new(path);
+    match command {
+        Command::List => list_command(&store).await,
+        Command::Stats => stats_command(&store).await,
+        Command::Requeue { id } => requeue_command(&store, &id).await,
+        Command::Check => check_command(&store, bridge).await,
+        Command::Relay => unreachable!("handled above"),
+    }
+}
+
+// Relays through the relay and relayer `bridge` describes, polling on
+// `bridge.poll_interval` until `cancel` fires. `memory://`, the default, is
+// a dry run: it lists what would be relayed and returns without marking
+// anything processed, so a `relay` with no broker configured can't drain the
+// outbox into a relay that throws the events away.
+async fn relay_command(
+    path: &str,
+    bridge: &BridgeConfig,
+    cancel: CancellationToken,
+) -> Result<String> {
+    let store = FileOutboxStore::new(path);
+    if bridge.broker_url == "memory://" {
+        let pending = list_command(&store).await?;
+        return Ok(format!(
+            "dry run (broker_url is memory://); would relay:\n{}",
+            pending
+        ));
+    }
+    let relayer = bridge.relayer(store, bridge.relay()?);
+    relayer.run(bridge.poll_interval, cancel).await;
+    let metrics = relayer.metrics().snapshot();
+    Ok(format!(
+        "relayed {} event(s), {} failed, {} retries\n",
+        metrics.events_relayed, metrics.events_failed, metrics.retries
+    ))
+}
+
+async fn list_command(store: &FileOutboxStore) -> Result<String> {
+    let mut output = String::new();
+    for event in store.get_unprocessed_events().await? {
+        output += &format!(
+            "{} {} {} retries={}\n",
+            event.created_at.to_rfc3339(),
+            event.id,
+            event.payload,
+            event.retry_count
+        );
+    }
+    Ok(output)
+}
+
+async fn stats_command(store: &FileOutboxStore) -> Result<String> {
+    let events = store.read_all_events().await?;
+    let processed = events.iter().filter(|e| e.processed).count();
+    Ok(format!(
+        "processed: {}\nunprocessed: {}\n",
+        processed,
+        events.len() - processed
+    ))
+}
+
+async fn requeue_command(store: &FileOutboxStore, id: &str) -> Result<String> {
+    if store.reset_event(id).await? {
+        Ok(format!("requeued {}\n", id))
+    } else {
+        Ok(format!("{} is already unprocessed\n", id))
+    }
+}
+
+// Parses the unprocessed payloads on the `worker_count` pool, since checking
+// a large outbox is CPU-bound, and lists the events that aren't JSON. The
+// pool runs on a blocking thread so it doesn't stall the async runtime.
+async fn check_command(store: &FileOutboxStore, bridge: &BridgeConfig) -> Result<String> {
+    let events = store.get_unprocessed_events().await?;
+    let ids: Vec<String> = events.iter().map(|e| e.id.clone()).collect();
+    let pool = bridge.worker_pool()?;
+    let results = tokio::task::spawn_blocking(move || {
+        pool.install(|| process_batch_parallel(events, check_json_payload))
+    })
+    .await?;
+    let mut output = String::new();
+    for (id, result) in ids.iter().zip(&results) {
+        if let Err(e) = result {
+            output += &format!("{} {}\n", id, e);
+        }
+    }
+    let invalid = results.iter().filter(|r| r.is_err()).count();
+    output += &format!("checked {} event(s), {} invalid\n", ids.len(), invalid);
+    Ok(output)
+}
+
+fn check_json_payload(event: &Event) -> Result<()> {
+    serde_json::from_str::<serde_json::Value>(&event.payload)?;
+    Ok(())
+}
+
+// Installs a `tracing` subscriber that prints to stdout. The level comes from
+// `RUST_LOG` (e.g. `RUST_LOG=debug`), defaulting to `info`.
+fn init_tracing() {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    init_tracing();
+
+    // Settings come from `outbox_bridge.toml` (if present) and `OUTBOX_*`
+    // environment variables, falling back to defaults.
+    let bridge = BridgeConfig::load("outbox_bridge.toml")?;
+    let cli = Cli::parse();
+    if let Some(command) = cli.command {
+        let path = cli.path.unwrap_or_else(|| bridge.outbox_path.clone());
+        print!("{}", run_command(command, &path, &bridge).await?);
+        return Ok(());
+    }
+    println!("Bridge config: {:?}", bridge);
+
+    // Every file the demo writes goes into its own temporary directory, which
+    // is removed afterwards, so a run leaves nothing in the working directory.
+    let dir = std::env::temp_dir().join(format!("outbox-demo-{}", std::process::id()));
+    fs::create_dir_all(&dir).await?;
+    let demo = run_demo(&bridge, &dir).await;
+    fs::remove_dir_all(&dir).await?;
+    demo
+}
+
+// A short tour of the relays and the relayer. The tests check each
+// behaviour in detail; this only shows the pieces working together.
+async fn run_demo(bridge: &BridgeConfig, dir: &std::path::Path) -> Result<()> {
+    let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+    let ids = |events: Vec<Event>| events.into_iter().map(|e| e.id).collect::<Vec<_>>();
+
+    // Every relay can say where it delivers to, including wrapped ones.
+    let relay = DedupRelay::new(
+        CircuitBreaker::new(DummyMessageRelay, 3, Duration::from_secs(1)),
+        16,
+    );
+    for descriptor in relay.describe() {
+        println!("Configured relay target: {:?}", descriptor);
+    }
+    relay.publish_event(&Event::new("0", "Hello")).await?;
+
+    // The relayer sends through a broker that fails twice, backing off and
+    // retrying with the config's backoff. Its counters go into a shared
+    // `Metrics`.
+    let metrics = Arc::new(Metrics::new());
+    let outbox = InMemoryOutboxStore::new();
+    outbox
+        .save_events(
+            (1..=3)
+                .map(|i| Event::new(&format!("relay-{}", i), "ReadyToSend"))
+                .collect(),
+        )
+        .await?;
+    let relay = InMemoryRelay::new();
+    relay.fail_next(2);
+    let relayer = bridge.relayer(outbox, relay).with_metrics(metrics.clone());
+    println!(
+        "Relay pass: {:?}; broker got {:?}",
+        relayer.run_once().await?,
+        ids(relayer.relay().received())
+    );
+
+    // Events that keep failing, or that the broker rejects outright, are
+    // parked in a dead-letter store and can be requeued later.
+    let outbox = InMemoryOutboxStore::new();
+    outbox
+        .save_event(Event::new("poison-1", &"x".repeat(64)))
+        .await?;
+    let relayer = MessageRelayer::new(outbox, InMemoryRelay::new().with_max_payload_bytes(32))
+        .with_metrics(metrics.clone())
+        .with_dead_letter_store(FileDeadLetterStore::new(&path("dead_letters.txt")));
+    println!(
+        "Relay pass with an oversized event: {:?}",
+        relayer.run_once().await?
+    );
+    let dead_letters = FileDeadLetterStore::new(&path("dead_letters.txt"));
+    for event in dead_letters.list().await? {
+        println!("Dead letter {}: {:?}", event.id, event.last_error);
+    }
+    dead_letters.requeue("poison-1", relayer.store()).await?;
+    println!(
+        "Requeued; unprocessed again: {}",
+        relayer.store().get_unprocessed_events().await?.len()
+    );
+
+    // At-most-once marks an event processed before sending it, so a failed
+    // send is given up on instead of retried.
+    let outbox = InMemoryOutboxStore::new();
+    outbox.save_event(Event::new("pay-1", "ChargeCard")).await?;
+    let relay = InMemoryRelay::new();
+    relay.fail_next(1);
+    let relayer = MessageRelayer::new(outbox, relay).with_delivery_mode(DeliveryMode::AtMostOnce);
+    println!(
+        "At-most-once pass with a failing send: {:?}",
+        relayer.run_once().await?
+    );
+
+    // Events for different orders go out in parallel, each order in sequence.
+    let outbox = InMemoryOutboxStore::new();
+    for step in ["Created", "Paid"] {
+        for order in ["order-a", "order-b"] {
+            outbox
+                .save_event(Event::new(&format!("{}:{}", order, step), step))
+                .await?;
+        }
+    }
+    let relayer = MessageRelayer::new(
+        outbox,
+        InMemoryRelay::new().with_latency(Duration::from_millis(20)),
+    );
+    let stats = relayer
+        .run_once_concurrent(2, |event| {
+            event.id.split(':').next().unwrap_or_default().to_string()
+        })
+        .await?;
+    println!(
+        "Concurrent pass: {:?}; broker got {:?}",
+        stats,
+        ids(relayer.relay().received())
+    );
+
+    // A long-running relayer, sending to the config's `broker_url`, polls
+    // until cancelled.
+    let outbox = InMemoryOutboxStore::new();
+    let relayer = bridge
+        .relayer(outbox.clone(), bridge.relay()?)
+        .with_max_idle_interval(Duration::from_millis(100));
+    let stop = CancellationToken::new();
+    let producer = async {
+        outbox.save_event(Event::new("loop-1", "Scheduled")).await?;
+        time::sleep(Duration::from_millis(120)).await;
+        stop.cancel();
+        Ok::<_, anyhow::Error>(())
+    };
+    let (produced, ()) = tokio::join!(
+        producer,
+        relayer.run(Duration::from_millis(50), stop.clone())
+    );
+    produced?;
+    println!(
+        "Relay loop sent {} event(s).",
+        relayer.metrics().snapshot().events_relayed
+    );
+
+    // Relay wrappers: a dedup window and a circuit breaker.
+    let dedup = DedupRelay::new(InMemoryRelay::new(), 2);
+    let dup = Event::new("dup-1", "ChargeCard");
+    dedup.publish_event(&dup).await?;
+    dedup.publish_event(&dup).await?;
+    println!(
+        "Dedup forwarded {} and skipped {}.",
+        dedup.inner().received().len(),
+        dedup.skipped()
+    );
+    let flaky = InMemoryRelay::new();
+    flaky.fail_next(2);
+    let breaker = CircuitBreaker::new(flaky, 2, Duration::from_millis(50));
+    for _ in 0..3 {
+        if let Err(e) = breaker.publish_event(&dup).await {
+            println!(
+                "Circuit {:?}: {} (retryable: {})",
+                breaker.state(),
+                e,
+                is_retryable(&e)
+            );
+        }
+    }
+
+    // CPU-bound batch work runs on the rayon pool and reports into `Metrics`.
+    let batch: Vec<Event> = (0..100)
+        .map(|i| Event::new(&format!("b{}", i), &format!("{{\"n\":{}}}", i)))
+        .collect();
+    let results = bridge
+        .worker_pool()?
+        .install(|| process_batch_parallel_with_metrics(batch, &metrics, check_json_payload));
+    println!("Validated {} events in parallel.", results.len());
+    println!("Metrics: {:?}", metrics.snapshot());
+    #[cfg(feature = "prometheus")]
+    print!("{}", metrics.snapshot().to_prometheus());
+
+    // Config layering: an `OUTBOX_` variable overrides the file.
+    fs::write(path("bridge.toml"), "max_retries = 2\n").await?;
+    let env = HashMap::from([("OUTBOX_MAX_RETRIES".to_string(), "9".to_string())]);
+    println!(
+        "max_retries with OUTBOX_MAX_RETRIES=9: {}",
+        BridgeConfig::load_with_env(&path("bridge.toml"), env)?.max_retries
+    );
+
+    // The CLI handlers, called directly. With the default `memory://` broker
+    // `relay` only lists what it would send.
+    let cli_path = path("cli.txt");
+    let cli_store = FileOutboxStore::new(&cli_path);
+    cli_store
+        .save_events(vec![
+            Event::new("cli-1", "Replayable").processed(),
+            Event::new("cli-2", "Pending"),
+        ])
+        .await?;
+    print!("outbox list:\n{}", list_command(&cli_store).await?);
+    print!("outbox stats:\n{}", stats_command(&cli_store).await?);
+    print!(
+        "outbox requeue cli-1:\n{}",
+        requeue_command(&cli_store, "cli-1").await?
+    );
+    print!(
+        "outbox check:\n{}",
+        check_command(&cli_store, bridge).await?
+    );
+    let dry_run = BridgeConfig {
+        broker_url: "memory://".to_string(),
+        ..bridge.clone()
+    };
+    print!(
+        "outbox relay:\n{}",
+        relay_command(&cli_path, &dry_run, CancellationToken::new()).await?
+    );
+    // A `file://` broker appends to a file, so `relay` runs for real until
+    // cancelled (Ctrl-C from the command line; a timer here).
+    let to_file = BridgeConfig {
+        broker_url: format!("file://{}", path("broker.jsonl")),
+        poll_interval: Duration::from_millis(20),
+        ..bridge.clone()
+    };
+    let stop = CancellationToken::new();
+    let timer = stop.clone();
+    tokio::spawn(async move {
+        time::sleep(Duration::from_millis(100)).await;
+        timer.cancel();
+    });
+    print!(
+        "outbox relay with {}:\n{}",
+        to_file.broker_url,
+        relay_command(&cli_path, &to_file, stop).await?
+    );
+    print!(
+        "broker file:\n{}",
+        fs::read_to_string(path("broker.jsonl")).await?
+    );
+
+    // Only one relayer instance may hold the leader lock at a time.
+    let lock_path = path("relayer.lock");
+    let leader = LeaderGuard::acquire(&lock_path, "relayer-1", Duration::from_secs(2)).await?;
+    let follower =
+        LeaderGuard::try_acquire(&lock_path, "relayer-2", Duration::from_secs(2)).await?;
+    println!(
+        "{} leads (still leader: {}); relayer-2 got the lock: {}",
+        leader.instance_id(),
+        leader.is_leader(),
+        follower.is_some()
+    );
+    leader.release().await?;
+
+    // With `--features kafka` and `KAFKA_BROKERS` set, a relay pass runs
+    // against a real cluster.
+    #[cfg(feature = "kafka")]
+    if let Ok(brokers) = std::env::var("KAFKA_BROKERS") {
+        let topic = std::env::var("KAFKA_TOPIC").unwrap_or_else(|_| "outbox-events".to_string());
+        let store = InMemoryOutboxStore::new();
+        store.save_event(Event::new("k1", "SentToKafka")).await?;
+        let relayer = MessageRelayer::new(
+            store,
+            KafkaRelay::new(&brokers, &topic, Duration::from_secs(5))?,
+        );
+        println!(
+            "Kafka relay pass to {}: {:?}",
+            topic,
+            relayer.run_once().await?
+        );
+    }
+    // With `--features http-client`, events are POSTed as JSON to an HTTP
+    // endpoint. Nothing listens on port 1, so this send fails with a
+    // retryable connection error. The tests run it against a mock server.
+    #[cfg(feature = "http-client")]
+    {
+        let webhook = WebhookRelay::new("http://127.0.0.1:1/events")?
+            .with_json_body()
+            .with_header("Authorization", "Bearer demo-token")
+            .with_timeout(Duration::from_secs(2));
+        if let Err(e) = webhook
+            .publish_event(&Event::new("w1", "PostedToWebhook"))
+            .await
+        {
+            println!(
+                "Unreachable webhook: {} (retryable: {})",
+                e,
+                is_retryable(&e)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_a_rejection_is_not_retryable() {
+        assert!(RelayError::CircuitOpen {
+            retry_in: Duration::ZERO
+        }
+        .is_retryable());
+        assert!(!RelayError::Rejected {
+            reason: "too big".to_string()
+        }
+        .is_retryable());
+        assert!(RelayError::DeliveryTimedOut {
+            after: Duration::from_secs(1)
+        }
+        .is_retryable());
+        assert!(RelayError::DeliveryFailed { status: 503 }.is_retryable());
+        assert!(RelayError::Connection("refused".to_string()).is_retryable());
+    }
+
+    #[test]
+    fn a_relay_describes_itself_by_its_type_name_by_default() {
+        let descriptors = DummyMessageRelay.describe();
+        assert_eq!(descriptors.len(), 1);
+        assert_eq!(descriptors[0].name, "DummyMessageRelay");
+        assert_eq!(descriptors[0].target, None);
+    }
+
+    // A wrapping relay, as the comment on `describe` asks of them.
+    struct FanOut(Vec<Box<dyn MessageRelay>>);
+
+    #[async_trait]
+    impl MessageRelay for FanOut {
+        async fn publish_event(&self, event: &Event) -> Result<()> {
+            for relay in &self.0 {
+                relay.publish_event(event).await?;
+            }
+            Ok(())
+        }
+
+        fn describe(&self) -> Vec<RelayDescriptor> {
+            self.0.iter().flat_map(|relay| relay.describe()).collect()
+        }
+    }
+
+    #[test]
+    fn a_wrapping_relay_lists_every_relay_it_wraps() {
+        let relay = FanOut(vec![
+            Box::new(DummyMessageRelay),
+            Box::new(FanOut(vec![Box::new(DummyMessageRelay)])),
+        ]);
+        let names: Vec<String> = relay.describe().into_iter().map(|d| d.name).collect();
+        assert_eq!(names, ["DummyMessageRelay", "DummyMessageRelay"]);
+    }
+
+    #[test]
+    fn the_breaker_and_dedup_wrappers_describe_the_relay_they_wrap() {
+        let relay = DedupRelay::new(
+            CircuitBreaker::new(DummyMessageRelay, 3, Duration::from_secs(1)),
+            16,
+        );
+        let names: Vec<String> = relay.describe().into_iter().map(|d| d.name).collect();
+        assert_eq!(names, ["DummyMessageRelay"]);
+    }
+
+    // A path for `name` inside a fresh temporary directory. The directory
+    // and everything in it are deleted when the returned guard is dropped.
+    fn scratch_path(name: &str) -> (tempfile::TempDir, String) {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let path = dir.path().join(name).to_string_lossy().into_owned();
+        (dir, path)
+    }
+
+    fn bridge_config(outbox_path: &str) -> BridgeConfig {
+        BridgeConfig {
+            outbox_path: outbox_path.to_string(),
+            poll_interval: Duration::from_millis(10),
+            max_retries: 0,
+            retry_base_delay: Duration::from_millis(1),
+            retry_max_delay: Duration::from_millis(1),
+            broker_url: "memory://".to_string(),
+            worker_count: 1,
+        }
+    }
+
+    fn ids(events: &[Event]) -> Vec<String> {
+        events.iter().map(|e| e.id.clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn relay_command_with_memory_broker_is_a_dry_run() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path);
+        store
+            .save_events(vec![
+                Event::new("1", "UserCreated"),
+                Event::new("2", "OrderPlaced"),
+            ])
+            .await
+            .unwrap();
+
+        let output = relay_command(&path, &bridge_config(&path), CancellationToken::new())
+            .await
+            .unwrap();
+        assert!(output.starts_with("dry run"), "{}", output);
+        assert!(
+            output.contains(" 1 UserCreated") && output.contains(" 2 OrderPlaced"),
+            "{}",
+            output
+        );
+        assert_eq!(
+            ids(&store.get_unprocessed_events().await.unwrap()),
+            ["1", "2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn relay_command_with_a_file_broker_relays_until_cancelled() {
+        let (dir, path) = scratch_path("outbox.txt");
+        let sink = dir
+            .path()
+            .join("broker.jsonl")
+            .to_string_lossy()
+            .into_owned();
+        let store = FileOutboxStore::new(&path);
+        store
+            .save_events(vec![
+                Event::new("1", "UserCreated"),
+                Event::new("2", "OrderPlaced"),
+            ])
+            .await
+            .unwrap();
+        let bridge = BridgeConfig {
+            broker_url: format!("file://{}", sink),
+            ..bridge_config(&path)
+        };
+        let cancel = CancellationToken::new();
+        let relay = tokio::spawn({
+            let (path, cancel) = (path.clone(), cancel.clone());
+            async move { relay_command(&path, &bridge, cancel).await }
+        });
+
+        time::timeout(Duration::from_secs(5), async {
+            while !store.get_unprocessed_events().await.unwrap().is_empty() {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("relay drains the outbox");
+        // Draining the outbox doesn't end the command; only cancelling does.
+        assert!(!relay.is_finished());
+        cancel.cancel();
+        let output = relay.await.unwrap().unwrap();
+        assert_eq!(output, "relayed 2 event(s), 0 failed, 0 retries\n");
+
+        let sent: Vec<Event> = std::fs::read_to_string(&sink)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(ids(&sent), ["1", "2"]);
+    }
+
+    #[tokio::test]
+    async fn relay_command_rejects_unknown_broker_urls() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let bridge = BridgeConfig {
+            broker_url: "amqp://localhost".to_string(),
+            ..bridge_config(&path)
+        };
+        let err = relay_command(&path, &bridge, CancellationToken::new())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("unsupported broker_url"));
+    }
+
+    #[tokio::test]
+    async fn only_one_leader_and_a_follower_takes_over_after_release() {
+        let (_dir, lock) = scratch_path("relayer.lock");
+        let stale_after = Duration::from_secs(2);
+        let (a, b) = tokio::join!(
+            LeaderGuard::try_acquire(&lock, "relayer-1", stale_after),
+            LeaderGuard::try_acquire(&lock, "relayer-2", stale_after),
+        );
+        let (leader, follower) = match (a.unwrap(), b.unwrap()) {
+            (Some(leader), None) => (leader, "relayer-2"),
+            (None, Some(leader)) => (leader, "relayer-1"),
+            _ => panic!("exactly one instance should become leader"),
+        };
+        assert!(LeaderGuard::try_acquire(&lock, follower, stale_after)
+            .await
+            .unwrap()
+            .is_none());
+
+        let waiting = tokio::spawn({
+            let lock = lock.clone();
+            async move { LeaderGuard::acquire(&lock, follower, stale_after).await }
+        });
+        leader.release().await.unwrap();
+        let new_leader = time::timeout(Duration::from_secs(5), waiting)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(new_leader.instance_id(), follower);
+        new_leader.release().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_lock_caught_mid_write_is_not_stale() {
+        let (_dir, lock) = scratch_path("relayer.lock");
+        // What a reader sees between `create_new` and the first write.
+        std::fs::write(&lock, "").unwrap();
+        let stale_after = Duration::from_millis(200);
+        assert!(LeaderGuard::try_acquire(&lock, "relayer-2", stale_after)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(std::fs::read_to_string(&lock).unwrap(), "");
+
+        // An empty lock left behind by a crash does expire.
+        time::sleep(Duration::from_millis(300)).await;
+        let guard = LeaderGuard::try_acquire(&lock, "relayer-2", stale_after)
+            .await
+            .unwrap()
+            .unwrap();
+        guard.release().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_leader_whose_lock_was_taken_over_reports_the_loss() {
+        let (_dir, lock) = scratch_path("relayer.lock");
+        let stale_after = Duration::from_millis(300);
+        let leader = LeaderGuard::try_acquire(&lock, "relayer-1", stale_after)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(leader.is_leader());
+
+        // Another instance decided relayer-1 was dead and took over.
+        let takeover = format!("relayer-2|{}", unix_millis());
+        std::fs::write(&lock, &takeover).unwrap();
+        time::timeout(Duration::from_secs(2), leader.lost().cancelled())
+            .await
+            .unwrap();
+        assert!(!leader.is_leader());
+
+        // The old leader stopped heartbeating instead of reclaiming the lock,
+        // and release leaves the new leader's lock alone.
+        time::sleep(stale_after).await;
+        assert_eq!(std::fs::read_to_string(&lock).unwrap(), takeover);
+        leader.release().await.unwrap();
+        assert!(std::path::Path::new(&lock).exists());
+    }
+
+    #[tokio::test]
+    async fn the_heartbeat_keeps_the_lock_fresh() {
+        let (_dir, lock) = scratch_path("relayer.lock");
+        let stale_after = Duration::from_millis(300);
+        let leader = LeaderGuard::try_acquire(&lock, "relayer-1", stale_after)
+            .await
+            .unwrap()
+            .unwrap();
+        time::sleep(stale_after * 2).await;
+        assert!(!LeaderGuard::is_stale(&lock, stale_after).await.unwrap());
+        assert!(LeaderGuard::try_acquire(&lock, "relayer-2", stale_after)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(leader.is_leader());
+        leader.release().await.unwrap();
+    }
+
+    #[test]
+    fn bridge_config_layers_defaults_file_and_env() {
+        let (_dir, fixture) = scratch_path("bridge.toml");
+        std::fs::write(
+            &fixture,
+            "outbox_path = \"fixture.txt\"\npoll_interval_ms = 250\nmax_retries = 2\n",
+        )
+        .unwrap();
+
+        let from_file = BridgeConfig::load_with_env(&fixture, HashMap::new()).unwrap();
+        assert_eq!(from_file.outbox_path, "fixture.txt");
+        assert_eq!(from_file.poll_interval, Duration::from_millis(250));
+        assert_eq!(from_file.max_retries, 2);
+        // Not in the file, so the default applies.
+        assert_eq!(from_file.broker_url, "memory://");
+        assert_eq!(from_file.worker_count, 4);
+        assert_eq!(from_file.retry_base_delay, Duration::from_millis(100));
+        assert_eq!(from_file.retry_max_delay, Duration::from_secs(5));
+
+        let env = HashMap::from([
+            ("OUTBOX_MAX_RETRIES".to_string(), "9".to_string()),
+            ("OUTBOX_WORKER_COUNT".to_string(), "8".to_string()),
+        ]);
+        let overridden = BridgeConfig::load_with_env(&fixture, env).unwrap();
+        assert_eq!(overridden.max_retries, 9);
+        assert_eq!(overridden.worker_count, 8);
+        assert_eq!(overridden.poll_interval, Duration::from_millis(250));
+    }
+
+    #[test]
+    fn the_relayer_takes_its_retry_limit_and_backoff_from_the_config() {
+        let bridge = BridgeConfig {
+            max_retries: 5,
+            retry_base_delay: Duration::from_millis(20),
+            retry_max_delay: Duration::from_millis(50),
+            ..bridge_config("unused")
+        };
+        let relayer = bridge.relayer(InMemoryOutboxStore::new(), bridge.relay().unwrap());
+        assert_eq!(relayer.relay().describe()[0].name, "InMemoryRelay");
+        assert_eq!(relayer.max_retries, 5);
+        let delays: Vec<_> = (1..=3).map(|retry| relayer.backoff_delay(retry)).collect();
+        assert_eq!(delays, [20, 40, 50].map(Duration::from_millis));
+    }
+
+    #[test]
+    fn bridge_config_rejects_a_wrongly_typed_value() {
+        let (_dir, fixture) = scratch_path("bridge.toml");
+        std::fs::write(&fixture, "max_retries = \"lots\"\n").unwrap();
+        assert!(BridgeConfig::load_with_env(&fixture, HashMap::new()).is_err());
+        let env = HashMap::from([("OUTBOX_MAX_RETRIES".to_string(), "-1".to_string())]);
+        assert!(BridgeConfig::load_with_env(&fixture.replace("bridge", "missing"), env).is_err());
+    }
+
+    #[tokio::test]
+    async fn a_flaky_send_is_retried_within_one_pass() {
+        let outbox = InMemoryOutboxStore::new();
+        outbox
+            .save_event(Event::new("1", "OrderPlaced"))
+            .await
+            .unwrap();
+        let relay = InMemoryRelay::new();
+        relay.fail_next(2);
+        let relayer = MessageRelayer::new(outbox, relay.clone())
+            .with_max_retries(3)
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(1));
+
+        let stats = relayer.run_once().await.unwrap();
+        assert_eq!(
+            stats,
+            RelayStats {
+                sent: 1,
+                retried: 2,
+                ..RelayStats::default()
+            }
+        );
+        assert_eq!(ids(&relay.received()), ["1"]);
+        assert!(relayer
+            .store()
+            .get_unprocessed_events()
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn failures_are_persisted_across_passes_without_a_dead_letter_store() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path);
+        store
+            .save_event(Event::new("1", "OrderPlaced"))
+            .await
+            .unwrap();
+        let relay = InMemoryRelay::new();
+        relay.fail_next(3);
+        let relayer = MessageRelayer::new(store, relay)
+            .with_max_retries(0)
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(1));
+
+        for pass in 1..=3 {
+            let stats = relayer.run_once().await.unwrap();
+            assert_eq!(stats.failed, 1);
+            let events = relayer.store().get_unprocessed_events().await.unwrap();
+            assert_eq!(events[0].retry_count, pass);
+            assert!(events[0].last_error.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn an_event_that_keeps_failing_is_dead_lettered_and_can_be_requeued() {
+        let (_dir, path) = scratch_path("dead_letters.txt");
+        let outbox = InMemoryOutboxStore::new();
+        outbox
+            .save_event(Event::new("poison", "AlwaysFails"))
+            .await
+            .unwrap();
+        let relay = InMemoryRelay::new();
+        relay.fail_next(2);
+        let relayer = MessageRelayer::new(outbox, relay.clone())
+            .with_max_retries(1)
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(1))
+            .with_dead_letter_store(FileDeadLetterStore::new(&path));
+
+        let stats = relayer.run_once().await.unwrap();
+        assert_eq!(
+            stats,
+            RelayStats {
+                failed: 1,
+                retried: 1,
+                dead_lettered: 1,
+                ..RelayStats::default()
+            }
+        );
+        assert!(relayer
+            .store()
+            .get_unprocessed_events()
+            .await
+            .unwrap()
+            .is_empty());
+
+        let dead_letters = FileDeadLetterStore::new(&path);
+        let parked = dead_letters.list().await.unwrap();
+        assert_eq!(ids(&parked), ["poison"]);
+        assert_eq!(parked[0].retry_count, 2);
+        assert!(parked[0].last_error.is_some());
+
+        dead_letters
+            .requeue("poison", relayer.store())
+            .await
+            .unwrap();
+        assert!(dead_letters.list().await.unwrap().is_empty());
+        assert_eq!(relayer.run_once().await.unwrap().sent, 1);
+        assert_eq!(ids(&relay.received()), ["poison"]);
+    }
+
+    #[tokio::test]
+    async fn relayers_and_batch_workers_share_one_set_of_metrics() {
+        let metrics = Arc::new(Metrics::new());
+
+        let outbox = InMemoryOutboxStore::new();
+        for id in ["1", "2", "3"] {
+            outbox
+                .save_event(Event::new(id, "OrderPlaced"))
+                .await
+                .unwrap();
+        }
+        let relay = InMemoryRelay::new();
+        relay.fail_next(1);
+        let relayer = MessageRelayer::new(outbox, relay)
+            .with_metrics(metrics.clone())
+            .with_max_retries(0)
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(1));
+        let stats = relayer.run_once().await.unwrap();
+        assert_eq!((stats.sent, stats.failed), (2, 1));
+
+        let jobs: Vec<Event> = ["a", "b", "bad", "c"]
+            .into_iter()
+            .map(|id| Event::new(id, "Transform"))
+            .collect();
+        let results = bridge_config("unused").worker_pool().unwrap().install(|| {
+            process_batch_parallel_with_metrics(jobs, &metrics, |event| {
+                anyhow::ensure!(event.id != "bad", "bad job");
+                Ok(())
+            })
+        });
+        assert_eq!(results.iter().filter(|r| r.is_err()).count(), 1);
+
+        let expected = MetricsSnapshot {
+            events_relayed: 2,
+            events_failed: 1,
+            jobs_processed: 3,
+            retries: 0,
+        };
+        assert_eq!(metrics.snapshot(), expected);
+        assert_eq!(relayer.metrics().snapshot(), expected);
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn the_prometheus_exporter_renders_every_counter() {
+        let snapshot = MetricsSnapshot {
+            events_relayed: 5,
+            events_failed: 2,
+            jobs_processed: 8,
+            retries: 3,
+        };
+        let text = snapshot.to_prometheus();
+        assert!(text.contains(
+            "# TYPE outbox_events_relayed_total counter\noutbox_events_relayed_total 5\n"
+        ));
+        assert!(text.contains("\noutbox_events_failed_total 2\n"));
+        assert!(text.contains("\noutbox_jobs_processed_total 8\n"));
+        assert!(text.contains("\noutbox_retries_total 3\n"));
+    }
+
+    // Collects everything a tracing subscriber writes, for asserting on logs.
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl CapturedLogs {
+        fn with_subscriber(&self, f: impl FnOnce()) -> String {
+            let logs = self.clone();
+            let subscriber = tracing_subscriber::fmt()
+                .with_ansi(false)
+                .with_writer(move || logs.clone())
+                .finish();
+            tracing::subscriber::with_default(subscriber, f);
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    use std::sync::atomic::AtomicBool;
+
+    // Stands in for a relayer process that dies between the two relay steps.
+    // The first send panics, either after the broker has the event (the crash
+    // comes after a successful send) or before (the send never happens). Later
+    // sends succeed. Clones share their state, like `InMemoryRelay`.
+    #[derive(Clone)]
+    struct CrashingRelay {
+        deliver_before_crash: bool,
+        crashed: Arc<AtomicBool>,
+        delivered: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl CrashingRelay {
+        fn new(deliver_before_crash: bool) -> Self {
+            CrashingRelay {
+                deliver_before_crash,
+                crashed: Arc::new(AtomicBool::new(false)),
+                delivered: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+
+        fn delivered(&self) -> Vec<String> {
+            self.delivered.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl MessageRelay for CrashingRelay {
+        async fn publish_event(&self, event: &Event) -> Result<()> {
+            let crash = !self.crashed.swap(true, Ordering::SeqCst);
+            if !crash || self.deliver_before_crash {
+                self.delivered.lock().unwrap().push(event.id.clone());
+            }
+            if crash {
+                panic!("simulated relayer crash");
+            }
+            Ok(())
+        }
+    }
+
+    // A crash between the two relay steps: the first relayer panics inside
+    // `send`, and a fresh relayer on the same outbox plays the restart.
+    async fn crash_and_restart(mode: DeliveryMode) -> (RelayStats, Vec<String>) {
+        use futures::FutureExt;
+        use std::panic::AssertUnwindSafe;
+
+        let outbox = InMemoryOutboxStore::new();
+        outbox
+            .save_event(Event::new("pay-1", "ChargeCard"))
+            .await
+            .unwrap();
+        // At-least-once sends first, so the crash lands after delivery;
+        // at-most-once marks first, so it lands before.
+        let relay = CrashingRelay::new(mode == DeliveryMode::AtLeastOnce);
+        let crashed = MessageRelayer::new(outbox.clone(), relay.clone()).with_delivery_mode(mode);
+        assert!(AssertUnwindSafe(crashed.run_once())
+            .catch_unwind()
+            .await
+            .is_err());
+        let restarted = MessageRelayer::new(outbox, relay.clone()).with_delivery_mode(mode);
+        (restarted.run_once().await.unwrap(), relay.delivered())
+    }
+
+    #[tokio::test]
+    async fn at_least_once_resends_after_a_crash() {
+        let (stats, delivered) = crash_and_restart(DeliveryMode::AtLeastOnce).await;
+        assert_eq!(stats.sent, 1);
+        assert_eq!(delivered, ["pay-1", "pay-1"]);
+    }
+
+    #[tokio::test]
+    async fn at_most_once_loses_the_event_after_a_crash() {
+        let (stats, delivered) = crash_and_restart(DeliveryMode::AtMostOnce).await;
+        assert_eq!(stats, RelayStats::default());
+        assert!(delivered.is_empty());
+    }
+
+    #[tokio::test]
+    async fn at_most_once_does_not_retry_a_failed_send() {
+        let outbox = InMemoryOutboxStore::new();
+        outbox
+            .save_event(Event::new("pay-1", "ChargeCard"))
+            .await
+            .unwrap();
+        let relay = InMemoryRelay::new();
+        relay.fail_next(1);
+        let relayer = MessageRelayer::new(outbox, relay.clone())
+            .with_delivery_mode(DeliveryMode::AtMostOnce)
+            .with_max_retries(3);
+        let stats = relayer.run_once().await.unwrap();
+        assert_eq!(
+            stats,
+            RelayStats {
+                failed: 1,
+                ..RelayStats::default()
+            }
+        );
+        assert!(relay.received().is_empty());
+        assert!(relayer
+            .store()
+            .get_unprocessed_events()
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    fn assert_same_event(decoded: &Event, original: &Event) {
+        assert_eq!(
+            (decoded.id.as_str(), decoded.payload.as_str()),
+            (original.id.as_str(), original.payload.as_str())
+        );
+        assert_eq!(
+            (decoded.retry_count, &decoded.last_error),
+            (original.retry_count, &original.last_error)
+        );
+        assert_eq!(decoded.created_at, original.created_at);
+    }
+
+    #[tokio::test]
+    async fn an_event_that_exhausts_its_retries_is_left_for_a_later_pass() {
+        let outbox = InMemoryOutboxStore::new();
+        outbox
+            .save_events(vec![
+                Event::new("1", "OrderPlaced"),
+                Event::new("2", "OrderShipped"),
+            ])
+            .await
+            .unwrap();
+        let relay = InMemoryRelay::new();
+        relay.fail_next(2);
+        let relayer = MessageRelayer::new(outbox, relay.clone())
+            .with_max_retries(1)
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(1));
+
+        let stats = relayer.run_once().await.unwrap();
+        assert_eq!(
+            stats,
+            RelayStats {
+                sent: 1,
+                failed: 1,
+                retried: 1,
+                ..RelayStats::default()
+            }
+        );
+        assert_eq!(ids(&relay.received()), ["2"]);
+        let left = relayer.store().get_unprocessed_events().await.unwrap();
+        assert_eq!(ids(&left), ["1"]);
+        assert_eq!(left[0].retry_count, 2);
+    }
+
+    #[test]
+    fn the_backoff_doubles_up_to_the_cap() {
+        let relayer = MessageRelayer::new(InMemoryOutboxStore::new(), InMemoryRelay::new())
+            .with_backoff(Duration::from_millis(100), Duration::from_millis(500));
+        let delays: Vec<u128> = (1..=5)
+            .map(|retry| relayer.backoff_delay(retry).as_millis())
+            .collect();
+        assert_eq!(delays, [100, 200, 400, 500, 500]);
+    }
+
+    #[tokio::test]
+    async fn the_broker_receives_exactly_what_was_in_the_outbox() {
+        let outbox = InMemoryOutboxStore::new();
+        let saved = vec![
+            Event::new("1", "UserCreated"),
+            Event::new("2", "OrderPlaced"),
+            Event::new("3", "OrderShipped"),
+        ];
+        outbox.save_events(saved.clone()).await.unwrap();
+        let relay = InMemoryRelay::new();
+        let relayer = MessageRelayer::new(outbox, relay.clone());
+
+        assert_eq!(relayer.run_once().await.unwrap().sent, 3);
+        let received = relay.received();
+        assert_eq!(received.len(), saved.len());
+        for (received, saved) in received.iter().zip(&saved) {
+            assert_same_event(received, saved);
+        }
+    }
+
+    #[tokio::test]
+    async fn fail_next_fails_exactly_that_many_sends() {
+        let relay = InMemoryRelay::new();
+        relay.fail_next(2);
+        let event = Event::new("1", "OrderPlaced");
+        assert!(relay.publish_event(&event).await.is_err());
+        assert!(relay.publish_event(&event).await.is_err());
+        assert!(relay.publish_event(&event).await.is_ok());
+        assert_eq!(ids(&relay.received()), ["1"]);
+    }
+
+    #[tokio::test]
+    async fn the_breaker_opens_short_circuits_and_recovers_after_a_probe() {
+        let inner = InMemoryRelay::new();
+        let breaker = CircuitBreaker::new(inner.clone(), 2, Duration::from_millis(50));
+        let event = Event::new("1", "OrderPlaced");
+
+        inner.fail_next(2);
+        assert!(breaker.publish_event(&event).await.is_err());
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.publish_event(&event).await.is_err());
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        // During the cooldown the inner broker isn't called at all.
+        let err = breaker.publish_event(&event).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RelayError>(),
+            Some(RelayError::CircuitOpen { .. })
+        ));
+        assert!(inner.received().is_empty());
+
+        time::sleep(Duration::from_millis(60)).await;
+        breaker.publish_event(&event).await.unwrap();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_eq!(ids(&inner.received()), ["1"]);
+    }
+
+    #[tokio::test]
+    async fn a_failed_probe_reopens_the_breaker() {
+        let inner = InMemoryRelay::new();
+        let breaker = CircuitBreaker::new(inner.clone(), 1, Duration::from_millis(20));
+        let event = Event::new("1", "OrderPlaced");
+
+        inner.fail_next(2);
+        assert!(breaker.publish_event(&event).await.is_err());
+        time::sleep(Duration::from_millis(30)).await;
+        let err = breaker.publish_event(&event).await.unwrap_err();
+        assert!(
+            err.downcast_ref::<RelayError>().is_none(),
+            "the probe should reach the inner broker"
+        );
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+
+    #[tokio::test]
+    async fn a_success_resets_the_consecutive_failure_count() {
+        let inner = InMemoryRelay::new();
+        let breaker = CircuitBreaker::new(inner.clone(), 2, Duration::from_secs(60));
+        let event = Event::new("1", "OrderPlaced");
+
+        for _ in 0..3 {
+            inner.fail_next(1);
+            assert!(breaker.publish_event(&event).await.is_err());
+            breaker.publish_event(&event).await.unwrap();
+        }
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn rejected_events_do_not_open_the_breaker() {
+        let (_dir, path) = scratch_path("dead_letters.txt");
+        let outbox = InMemoryOutboxStore::new();
+        outbox
+            .save_events(vec![
+                Event::new("big-1", &"x".repeat(10)),
+                Event::new("big-2", &"x".repeat(10)),
+                Event::new("ok", "fits"),
+            ])
+            .await
+            .unwrap();
+        let inner = InMemoryRelay::new().with_max_payload_bytes(5);
+        let breaker = CircuitBreaker::new(inner.clone(), 2, Duration::from_secs(60));
+        let relayer = MessageRelayer::new(outbox, breaker)
+            .with_dead_letter_store(FileDeadLetterStore::new(&path));
+
+        let stats = relayer.run_once().await.unwrap();
+        assert_eq!(
+            stats,
+            RelayStats {
+                sent: 1,
+                failed: 2,
+                dead_lettered: 2,
+                ..RelayStats::default()
+            }
+        );
+        assert_eq!(relayer.relay().state(), CircuitState::Closed);
+        assert_eq!(ids(&inner.received()), ["ok"]);
+    }
+
+    #[tokio::test]
+    async fn a_probe_dropped_mid_send_does_not_wedge_the_breaker() {
+        let inner = InMemoryRelay::new().with_latency(Duration::from_millis(100));
+        let breaker = CircuitBreaker::new(inner.clone(), 1, Duration::from_millis(20));
+        let event = Event::new("1", "OrderPlaced");
+
+        inner.fail_next(1);
+        assert!(breaker.publish_event(&event).await.is_err());
+        time::sleep(Duration::from_millis(30)).await;
+        // The caller gives up on the probe before the broker answers.
+        assert!(
+            time::timeout(Duration::from_millis(10), breaker.publish_event(&event))
+                .await
+                .is_err()
+        );
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        breaker.publish_event(&event).await.unwrap();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn an_open_breaker_ends_the_pass_without_dead_lettering() {
+        let (_dir, path) = scratch_path("dead_letters.txt");
+        let outbox = InMemoryOutboxStore::new();
+        outbox
+            .save_events(vec![
+                Event::new("1", "OrderPlaced"),
+                Event::new("2", "OrderPaid"),
+            ])
+            .await
+            .unwrap();
+        let inner = InMemoryRelay::new();
+        let breaker = CircuitBreaker::new(inner.clone(), 1, Duration::from_secs(60));
+        inner.fail_next(1);
+        assert!(breaker
+            .publish_event(&Event::new("0", "Warmup"))
+            .await
+            .is_err());
+        let relayer = MessageRelayer::new(outbox, breaker)
+            .with_max_retries(0)
+            .with_dead_letter_store(FileDeadLetterStore::new(&path));
+
+        for _ in 0..3 {
+            assert_eq!(relayer.run_once().await.unwrap(), RelayStats::default());
+        }
+        assert!(FileDeadLetterStore::new(&path)
+            .list()
+            .await
+            .unwrap()
+            .is_empty());
+        let left = relayer.store().get_unprocessed_events().await.unwrap();
+        assert_eq!(ids(&left), ["1", "2"]);
+        assert!(left
+            .iter()
+            .all(|e| e.retry_count == 0 && e.last_error.is_none()));
+    }
+
+    #[test]
+    fn errors_are_classified_as_retryable_or_fatal() {
+        let cases: Vec<(anyhow::Error, bool)> = vec![
+            (
+                OutboxError::EventNotFound {
+                    id: "1".to_string(),
+                }
+                .into(),
+                false,
+            ),
+            (
+                RelayError::CircuitOpen {
+                    retry_in: Duration::ZERO,
+                }
+                .into(),
+                true,
+            ),
+            (
+                RelayError::Rejected {
+                    reason: "too big".to_string(),
+                }
+                .into(),
+                false,
+            ),
+            (
+                RelayError::DeliveryTimedOut {
+                    after: Duration::from_secs(1),
+                }
+                .into(),
+                true,
+            ),
+            (RelayError::DeliveryFailed { status: 503 }.into(), true),
+            (RelayError::Connection("refused".to_string()).into(), true),
+            // Errors we know nothing about are assumed transient.
+            (anyhow!("connection reset"), true),
+            (
+                std::io::Error::from(std::io::ErrorKind::TimedOut).into(),
+                true,
+            ),
+        ];
+        for (err, retryable) in cases {
+            assert_eq!(is_retryable(&err), retryable, "{}", err);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_fatal_error_skips_the_retries() {
+        let (_dir, path) = scratch_path("dead_letters.txt");
+        let outbox = InMemoryOutboxStore::new();
+        outbox
+            .save_event(Event::new("1", &"x".repeat(10)))
+            .await
+            .unwrap();
+        let relay = InMemoryRelay::new().with_max_payload_bytes(5);
+        let relayer = MessageRelayer::new(outbox, relay)
+            .with_max_retries(3)
+            .with_dead_letter_store(FileDeadLetterStore::new(&path));
+
+        let stats = relayer.run_once().await.unwrap();
+        assert_eq!(
+            stats,
+            RelayStats {
+                failed: 1,
+                dead_lettered: 1,
+                ..RelayStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn every_relayed_event_is_logged_inside_a_span_with_its_id() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let logs = CapturedLogs::default().with_subscriber(|| {
+            runtime.block_on(async {
+                let outbox = InMemoryOutboxStore::new();
+                let events = vec![
+                    Event::new("a1", "UserCreated"),
+                    Event::new("b2", "OrderPlaced"),
+                ];
+                outbox.save_events(events).await.unwrap();
+                let relay = InMemoryRelay::new();
+                relay.fail_next(1);
+                let relayer = MessageRelayer::new(outbox, relay)
+                    .with_backoff(Duration::from_millis(1), Duration::from_millis(1));
+                relayer.run_once().await.unwrap();
+            })
+        });
+
+        for id in ["a1", "b2"] {
+            let span = format!("relay_event{{event_id={}}}", id);
+            assert!(
+                logs.lines()
+                    .any(|line| line.contains(&span) && line.contains("event sent")),
+                "{}",
+                logs
+            );
+        }
+        let retried = logs
+            .lines()
+            .find(|line| line.contains("send failed; retrying"))
+            .expect("a retry is logged");
+        assert!(
+            retried.contains("relay_event{event_id=a1}") && retried.contains("attempt=1"),
+            "{}",
+            retried
+        );
+    }
+
+    #[cfg(feature = "kafka")]
+    mod kafka {
+        use super::*;
+
+        // Needs a running cluster, so it passes without doing anything
+        // unless `KAFKA_BROKERS` is set.
+        #[tokio::test]
+        async fn the_cluster_acknowledges_a_sent_event() {
+            let Ok(brokers) = std::env::var("KAFKA_BROKERS") else {
+                eprintln!("KAFKA_BROKERS not set; skipping");
+                return;
+            };
+            let relay = KafkaRelay::new(&brokers, "outbox-test", Duration::from_secs(10)).unwrap();
+            relay
+                .publish_event(&Event::new("1", "OrderPlaced"))
+                .await
+                .unwrap();
+        }
+
+        #[tokio::test]
+        async fn an_unreachable_cluster_times_out_with_a_retryable_error() {
+            let addr = std::net::TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap();
+            let relay =
+                KafkaRelay::new(&addr.to_string(), "outbox-test", Duration::from_millis(200))
+                    .unwrap();
+            let err = relay
+                .publish_event(&Event::new("1", "OrderPlaced"))
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref::<RelayError>(),
+                    Some(RelayError::DeliveryTimedOut { .. })
+                ),
+                "{}",
+                err
+            );
+            assert!(is_retryable(&err));
+        }
+    }
+
+    #[test]
+    fn a_large_batch_returns_one_result_per_event_in_input_order() {
+        let events: Vec<Event> = (0..10_000)
+            .map(|n| Event::new(&n.to_string(), "OrderPlaced"))
+            .collect();
+        let results = process_batch_parallel(events, |event| {
+            let n: usize = event.id.parse()?;
+            if n % 1_000 == 999 {
+                return Err(anyhow!("event {} is invalid", n));
+            }
+            Ok(())
+        });
+
+        assert_eq!(results.len(), 10_000);
+        for (n, result) in results.iter().enumerate() {
+            match result {
+                Ok(()) => assert_ne!(n % 1_000, 999),
+                Err(e) => assert_eq!(e.to_string(), format!("event {} is invalid", n)),
+            }
+        }
+        assert_eq!(results.iter().filter(|r| r.is_err()).count(), 10);
+    }
+
+    #[tokio::test]
+    async fn the_run_loop_relays_events_added_between_passes_and_stops_on_cancel() {
+        let outbox = InMemoryOutboxStore::new();
+        let relay = InMemoryRelay::new();
+        let relayer = Arc::new(
+            MessageRelayer::new(outbox.clone(), relay.clone())
+                .with_max_idle_interval(Duration::from_millis(40)),
+        );
+        let cancel = CancellationToken::new();
+        let running = tokio::spawn({
+            let (relayer, cancel) = (Arc::clone(&relayer), cancel.clone());
+            async move { relayer.run(Duration::from_millis(10), cancel).await }
+        });
+
+        for n in 1..=3 {
+            outbox
+                .save_event(Event::new(&n.to_string(), "OrderPlaced"))
+                .await
+                .unwrap();
+            time::timeout(Duration::from_secs(2), async {
+                while relay.received().len() < n {
+                    time::sleep(Duration::from_millis(5)).await;
+                }
+            })
+            .await
+            .expect("a later pass relays the new event");
+        }
+
+        cancel.cancel();
+        time::timeout(Duration::from_secs(1), running)
+            .await
+            .expect("the loop stops")
+            .unwrap();
+        assert_eq!(ids(&relay.received()), ["1", "2", "3"]);
+        assert!(outbox.get_unprocessed_events().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_repeated_id_reaches_the_inner_broker_once() {
+        let dedup = DedupRelay::new(InMemoryRelay::new(), 16);
+        dedup
+            .publish_event(&Event::new("1", "OrderPlaced"))
+            .await
+            .unwrap();
+        dedup
+            .publish_event(&Event::new("1", "OrderPlaced"))
+            .await
+            .unwrap();
+
+        assert_eq!(ids(&dedup.inner().received()), ["1"]);
+        assert_eq!(dedup.skipped(), 1);
+    }
+
+    #[tokio::test]
+    async fn the_least_recently_seen_id_is_evicted_from_the_window() {
+        let dedup = DedupRelay::new(InMemoryRelay::new(), 2);
+        // "a" is seen again before "c" arrives, so "b" is the one evicted.
+        for id in ["a", "b", "a", "c", "a", "b"] {
+            dedup
+                .publish_event(&Event::new(id, "OrderPlaced"))
+                .await
+                .unwrap();
+        }
+        assert_eq!(ids(&dedup.inner().received()), ["a", "b", "c", "b"]);
+        assert_eq!(dedup.skipped(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_failed_send_can_be_retried() {
+        let dedup = DedupRelay::new(InMemoryRelay::new(), 16);
+        dedup.inner().fail_next(1);
+        let event = Event::new("1", "OrderPlaced");
+        assert!(dedup.publish_event(&event).await.is_err());
+        dedup.publish_event(&event).await.unwrap();
+        assert_eq!(ids(&dedup.inner().received()), ["1"]);
+        assert_eq!(dedup.skipped(), 0);
+    }
+
+    #[tokio::test]
+    async fn concurrent_sends_of_one_id_reach_the_inner_broker_once() {
+        let dedup = DedupRelay::new(
+            InMemoryRelay::new().with_latency(Duration::from_millis(20)),
+            16,
+        );
+        let event = Event::new("1", "OrderPlaced");
+        let (first, second) =
+            tokio::join!(dedup.publish_event(&event), dedup.publish_event(&event));
+        first.unwrap();
+        second.unwrap();
+        assert_eq!(ids(&dedup.inner().received()), ["1"]);
+    }
+
+    // Payloads are `<key>:<n>`, keyed by the part before the colon.
+    fn key_of(event: &Event) -> String {
+        event.payload.split(':').next().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn concurrent_relay_keeps_each_keys_order_and_runs_keys_in_parallel() {
+        let outbox = InMemoryOutboxStore::new();
+        let mut events = Vec::new();
+        for n in 0..3 {
+            for key in ["a", "b", "c", "d"] {
+                events.push(Event::new(
+                    &format!("{}{}", key, n),
+                    &format!("{}:{}", key, n),
+                ));
+            }
+        }
+        outbox.save_events(events).await.unwrap();
+        let relay = InMemoryRelay::new().with_latency(Duration::from_millis(50));
+        let relayer = MessageRelayer::new(outbox, relay.clone());
+
+        let started = time::Instant::now();
+        let stats = relayer.run_once_concurrent(4, key_of).await.unwrap();
+        let elapsed = started.elapsed();
+
+        assert_eq!(stats.sent, 12);
+        // Twelve sequential sends would take 600ms; four keys at a time take
+        // three rounds of 50ms.
+        assert!(elapsed < Duration::from_millis(400), "took {:?}", elapsed);
+        let received = relay.received();
+        for key in ["a", "b", "c", "d"] {
+            let order: Vec<&str> = received
+                .iter()
+                .filter(|e| key_of(e) == key)
+                .map(|e| e.payload.as_str())
+                .collect();
+            let expected: Vec<String> = (0..3).map(|n| format!("{}:{}", key, n)).collect();
+            assert_eq!(order, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_failed_event_holds_back_the_rest_of_its_key() {
+        let outbox = InMemoryOutboxStore::new();
+        outbox
+            .save_events(vec![
+                Event::new("a0", "a:0"),
+                Event::new("a1", "a:1"),
+                Event::new("b0", "b:0"),
+            ])
+            .await
+            .unwrap();
+        let relay = InMemoryRelay::new();
+        relay.fail_next(1);
+        let relayer = MessageRelayer::new(outbox, relay.clone()).with_max_retries(0);
+
+        let stats = relayer.run_once_concurrent(1, key_of).await.unwrap();
+        assert_eq!((stats.sent, stats.failed), (1, 1));
+        assert_eq!(ids(&relay.received()), ["b0"]);
+        assert_eq!(
+            ids(&relayer.store().get_unprocessed_events().await.unwrap()),
+            ["a0", "a1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn list_stats_and_requeue_report_on_and_change_the_store() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path);
+        store
+            .save_events(vec![
+                Event::new("1", "UserCreated").processed(),
+                Event::new("2", "OrderPlaced"),
+            ])
+            .await
+            .unwrap();
+        let bridge = bridge_config(&path);
+
+        let listed = run_command(Command::List, &path, &bridge).await.unwrap();
+        assert_eq!(listed.lines().count(), 1);
+        assert!(listed.contains(" 2 OrderPlaced retries=0"), "{}", listed);
+        let stats = run_command(Command::Stats, &path, &bridge).await.unwrap();
+        assert_eq!(stats, "processed: 1\nunprocessed: 1\n");
+
+        let requeued = run_command(
+            Command::Requeue {
+                id: "1".to_string(),
+            },
+            &path,
+            &bridge,
+        )
+        .await
+        .unwrap();
+        assert_eq!(requeued, "requeued 1\n");
+        assert_eq!(
+            run_command(Command::Stats, &path, &bridge).await.unwrap(),
+            "processed: 0\nunprocessed: 2\n"
+        );
+        let again = run_command(
+            Command::Requeue {
+                id: "1".to_string(),
+            },
+            &path,
+            &bridge,
+        )
+        .await
+        .unwrap();
+        assert_eq!(again, "1 is already unprocessed\n");
+    }
+
+    #[tokio::test]
+    async fn requeueing_an_unknown_id_is_an_error() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let err = run_command(
+            Command::Requeue {
+                id: "missing".to_string(),
+            },
+            &path,
+            &bridge_config(&path),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<OutboxError>(),
+            Some(OutboxError::EventNotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn check_lists_the_unprocessed_events_that_are_not_json() {
+        let (_dir, path) = scratch_path("outbox.txt");
+        let store = FileOutboxStore::new(&path);
+        store
+            .save_events(vec![
+                Event::new("1", "{\"user\":1}"),
+                Event::new("2", "not json"),
+                Event::new("3", "also not json").processed(),
+            ])
+            .await
+            .unwrap();
+
+        let output = run_command(Command::Check, &path, &bridge_config(&path))
+            .await
+            .unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2, "{}", output);
+        assert!(lines[0].starts_with("2 "), "{}", output);
+        assert_eq!(lines[1], "checked 2 event(s), 1 invalid");
+    }
+
+    #[test]
+    fn the_cli_parses_a_global_path_and_a_subcommand() {
+        let cli = Cli::try_parse_from(["outbox", "requeue", "42", "--path", "events.txt"]).unwrap();
+        assert_eq!(cli.path.as_deref(), Some("events.txt"));
+        assert!(matches!(cli.command, Some(Command::Requeue { id }) if id == "42"));
+        assert!(Cli::try_parse_from(["outbox", "requeue"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn a_reset_event_is_relayed_again() {
+        let outbox = InMemoryOutboxStore::new();
+        outbox
+            .save_event(Event::new("1", "OrderPlaced"))
+            .await
+            .unwrap();
+        let relay = InMemoryRelay::new();
+        let relayer = MessageRelayer::new(outbox, relay.clone());
+
+        relayer.run_once().await.unwrap();
+        relayer.store().reset_event("1").await.unwrap();
+        relayer.run_once().await.unwrap();
+        assert_eq!(ids(&relay.received()), ["1", "1"]);
+    }
+
+    #[cfg(feature = "http-client")]
+    mod webhook {
+        use super::*;
+        use wiremock::matchers::{body_string, header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        fn relay_error(err: &anyhow::Error) -> &RelayError {
+            err.downcast_ref::<RelayError>().expect("a RelayError")
+        }
+
+        #[tokio::test]
+        async fn a_2xx_response_is_a_successful_delivery() {
+            let server = MockServer::start().await;
+            Mock::given(method("POST"))
+                .and(path("/hooks/orders"))
+                .and(header("X-Outbox-Event-Id", "evt-1"))
+                .and(header("Authorization", "Bearer secret"))
+                .and(body_string(r#"{"order":42}"#))
+                .respond_with(ResponseTemplate::new(200))
+                .expect(1)
+                .mount(&server)
+                .await;
+
+            let relay = WebhookRelay::new(&format!("{}/hooks/orders", server.uri()))
+                .unwrap()
+                .with_header("Authorization", "Bearer secret");
+            relay
+                .publish_event(&Event::new("evt-1", r#"{"order":42}"#))
+                .await
+                .unwrap();
+        }
+
+        #[tokio::test]
+        async fn a_500_response_is_a_retryable_delivery_failure() {
+            let server = MockServer::start().await;
+            Mock::given(method("POST"))
+                .respond_with(ResponseTemplate::new(500))
+                .expect(1)
+                .mount(&server)
+                .await;
+
+            let relay = WebhookRelay::new(&server.uri()).unwrap();
+            let err = relay
+                .publish_event(&Event::new("evt-1", "payload"))
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                relay_error(&err),
+                RelayError::DeliveryFailed { status: 500 }
+            ));
+            assert!(relay_error(&err).is_retryable());
+        }
+
+        #[tokio::test]
+        async fn an_unreachable_endpoint_is_a_retryable_connection_error() {
+            // Bind a free port, then close it so nothing is listening there.
+            let addr = std::net::TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap();
+            let relay = WebhookRelay::new(&format!("http://{}", addr)).unwrap();
+            let err = relay
+                .publish_event(&Event::new("evt-1", "payload"))
+                .await
+                .unwrap_err();
+            assert!(matches!(relay_error(&err), RelayError::Connection(_)));
+            assert!(relay_error(&err).is_retryable());
+        }
+
+        #[tokio::test]
+        async fn the_json_body_is_the_serialized_event() {
+            let server = MockServer::start().await;
+            let event = Event::new("w1", "PostedToWebhook");
+            Mock::given(method("POST"))
+                .and(path("/events"))
+                .and(header("Content-Type", "application/json"))
+                .and(header("X-Outbox-Event-Id", "w1"))
+                .and(header("Authorization", "Bearer token"))
+                .and(body_string(serde_json::to_string(&event).unwrap()))
+                .respond_with(ResponseTemplate::new(202))
+                .expect(1)
+                .mount(&server)
+                .await;
+
+            let relay = WebhookRelay::new(&format!("{}/events", server.uri()))
+                .unwrap()
+                .with_json_body()
+                .with_header("Authorization", "Bearer token");
+            relay.publish_event(&event).await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn a_slow_response_is_a_retryable_timeout() {
+            let server = MockServer::start().await;
+            Mock::given(method("POST"))
+                .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+                .mount(&server)
+                .await;
+
+            let relay = WebhookRelay::new(&server.uri())
+                .unwrap()
+                .with_timeout(Duration::from_millis(50));
+            let err = relay
+                .publish_event(&Event::new("w1", "x"))
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                relay_error(&err),
+                RelayError::DeliveryTimedOut { .. }
+            ));
+            assert!(is_retryable(&err));
+        }
+
+        #[tokio::test]
+        async fn the_relayer_retries_a_500_and_then_delivers() {
+            let server = MockServer::start().await;
+            Mock::given(method("POST"))
+                .respond_with(ResponseTemplate::new(500))
+                .up_to_n_times(1)
+                .with_priority(1)
+                .mount(&server)
+                .await;
+            Mock::given(method("POST"))
+                .respond_with(ResponseTemplate::new(200))
+                .mount(&server)
+                .await;
+
+            let outbox = InMemoryOutboxStore::new();
+            outbox
+                .save_event(Event::new("w1", "PostedToWebhook"))
+                .await
+                .unwrap();
+            let relayer = MessageRelayer::new(outbox, WebhookRelay::new(&server.uri()).unwrap())
+                .with_max_retries(2)
+                .with_backoff(Duration::from_millis(1), Duration::from_millis(1));
+            let stats = relayer.run_once().await.unwrap();
+            assert_eq!(
+                stats,
+                RelayStats {
+                    sent: 1,
+                    retried: 1,
+                    ..RelayStats::default()
+                }
+            );
+            assert_eq!(server.received_requests().await.unwrap().len(), 2);
+        }
+
+        #[test]
+        fn describe_reports_the_target_url() {
+            let relay = WebhookRelay::new("http://hooks.example/orders").unwrap();
+            let descriptors = relay.describe();
+            assert_eq!(descriptors[0].name, "WebhookRelay");
+            assert_eq!(
+                descriptors[0].target.as_deref(),
+                Some("http://hooks.example/orders")
+            );
+        }
+    }
 }
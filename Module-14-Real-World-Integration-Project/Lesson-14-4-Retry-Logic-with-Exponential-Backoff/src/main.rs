@@ -67,6 +67,110 @@ where
     }
 }
 
+// --- A Reusable Retry Combinator ---
+
+// `retry_with_exponential_backoff` above is tied to `anyhow` and `String`
+// results. `retry_with_backoff` works for any `T` and `E`, takes its tuning
+// from a `Backoff` policy, and gives back the last error unchanged so the
+// caller can still match on it.
+
+use std::future::Future;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // Stop retrying once this much time has passed since the first attempt.
+    pub max_elapsed: Option<Duration>,
+    pub jitter: bool,
+}
+
+impl Backoff {
+    pub fn new(max_attempts: u32, base_delay: Duration) -> Self {
+        Backoff {
+            max_attempts,
+            base_delay,
+            max_delay: Duration::from_secs(30),
+            max_elapsed: None,
+            jitter: false,
+        }
+    }
+
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn with_max_elapsed(mut self, max_elapsed: Duration) -> Self {
+        self.max_elapsed = Some(max_elapsed);
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    // Delay before retry number `attempt` (1-based): base * 2^(attempt - 1),
+    // capped at `max_delay`. Jitter adds up to half the delay on top, still
+    // within the cap.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        if !self.jitter {
+            return delay;
+        }
+        let half_ms = (delay.as_millis() / 2) as u64;
+        let jitter = Duration::from_millis(rand::thread_rng().gen_range(0..=half_ms));
+        (delay + jitter).min(self.max_delay)
+    }
+}
+
+// Retries `op` on every error. `op` gets the 1-based attempt number.
+pub async fn retry_with_backoff<F, Fut, T, E>(policy: &Backoff, op: F) -> std::result::Result<T, E>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = std::result::Result<T, E>>,
+{
+    retry_with_backoff_when(policy, |_: &E| true, op).await
+}
+
+// Like `retry_with_backoff`, but errors for which `is_retryable` returns false
+// are returned straight away.
+pub async fn retry_with_backoff_when<F, Fut, T, E, P>(
+    policy: &Backoff,
+    is_retryable: P,
+    mut op: F,
+) -> std::result::Result<T, E>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = std::result::Result<T, E>>,
+    P: Fn(&E) -> bool,
+{
+    let started = Instant::now();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let err = match op(attempt).await {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        if !is_retryable(&err) || attempt >= policy.max_attempts {
+            return Err(err);
+        }
+
+        let delay = policy.delay(attempt);
+        if let Some(max_elapsed) = policy.max_elapsed {
+            if started.elapsed() + delay > max_elapsed {
+                return Err(err);
+            }
+        }
+        time::sleep(delay).await;
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     println!("--- Starting retry example ---");
@@ -82,5 +186,117 @@ async fn main() -> Result<()> {
         Err(e) => eprintln!("Final failure: {:?}", e),
     }
 
+    println!("\n--- Generic retry_with_backoff ---");
+    let policy = Backoff::new(4, Duration::from_millis(20))
+        .with_max_delay(Duration::from_millis(50))
+        .with_max_elapsed(Duration::from_secs(2))
+        .with_jitter(true);
+    println!("Delays for attempts 1..=4: {:?}", (1..=4).map(|n| policy.delay(n)).collect::<Vec<_>>());
+    let result = retry_with_backoff(&policy, failable_operation).await;
+    println!("retry_with_backoff: {:?}", result);
+
+    // A missing record won't appear by retrying, so it fails on the first call.
+    let result = retry_with_backoff_when(
+        &policy,
+        |e: &anyhow::Error| !e.to_string().contains("not found"),
+        |attempt| async move { Err::<String, _>(anyhow!("record not found (attempt {})", attempt)) },
+    )
+    .await;
+    println!("retry_with_backoff_when: {:?}", result);
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[derive(Debug, PartialEq)]
+    enum FetchError {
+        Timeout(u32),
+        NotFound,
+    }
+
+    // Fails with `Timeout` until `succeed_on`, counting calls in `calls`.
+    async fn flaky_fetch(attempt: u32, succeed_on: u32, calls: &AtomicU32) -> std::result::Result<u32, FetchError> {
+        calls.fetch_add(1, Ordering::SeqCst);
+        if attempt >= succeed_on {
+            Ok(attempt)
+        } else {
+            Err(FetchError::Timeout(attempt))
+        }
+    }
+
+    fn quick_policy() -> Backoff {
+        Backoff::new(4, Duration::from_millis(5)).with_max_delay(Duration::from_millis(10))
+    }
+
+    #[tokio::test]
+    async fn an_immediate_success_is_not_retried() {
+        let calls = AtomicU32::new(0);
+        let result = retry_with_backoff(&quick_policy(), |n| flaky_fetch(n, 1, &calls)).await;
+        assert_eq!(result, Ok(1));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn two_failures_are_retried_until_the_third_call_succeeds() {
+        let calls = AtomicU32::new(0);
+        let result = retry_with_backoff(&quick_policy(), |n| flaky_fetch(n, 3, &calls)).await;
+        assert_eq!(result, Ok(3));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn exhaustion_returns_the_last_error_unchanged() {
+        let calls = AtomicU32::new(0);
+        let result = retry_with_backoff(&quick_policy(), |n| flaky_fetch(n, 10, &calls)).await;
+        assert_eq!(result, Err(FetchError::Timeout(4)));
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn a_fatal_error_fails_fast() {
+        let calls = AtomicU32::new(0);
+        let result: std::result::Result<u32, FetchError> = retry_with_backoff_when(
+            &quick_policy(),
+            |e| matches!(e, FetchError::Timeout(_)),
+            |_| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async { Err(FetchError::NotFound) }
+            },
+        )
+        .await;
+        assert_eq!(result, Err(FetchError::NotFound));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retrying_stops_before_a_sleep_would_pass_max_elapsed() {
+        // Sleeps of 40ms and 80ms fit in 150ms; the next one (160ms) doesn't.
+        let policy = Backoff::new(10, Duration::from_millis(40))
+            .with_max_delay(Duration::from_secs(1))
+            .with_max_elapsed(Duration::from_millis(150));
+        let calls = AtomicU32::new(0);
+        let started = Instant::now();
+        let result = retry_with_backoff(&policy, |n| flaky_fetch(n, 10, &calls)).await;
+
+        assert_eq!(result, Err(FetchError::Timeout(3)));
+        assert!(started.elapsed() < Duration::from_millis(150));
+    }
+
+    #[test]
+    fn delays_double_up_to_the_cap_and_jitter_stays_under_it() {
+        let policy = Backoff::new(6, Duration::from_millis(10)).with_max_delay(Duration::from_millis(50));
+        let delays: Vec<u128> = (1..=5).map(|n| policy.delay(n).as_millis()).collect();
+        assert_eq!(delays, [10, 20, 40, 50, 50]);
+
+        let jittered = policy.with_jitter(true);
+        for _ in 0..100 {
+            let delay = jittered.delay(2);
+            assert!(delay >= Duration::from_millis(20) && delay <= Duration::from_millis(30), "{:?}", delay);
+            assert!(jittered.delay(5) <= Duration::from_millis(50));
+        }
+    }
+}
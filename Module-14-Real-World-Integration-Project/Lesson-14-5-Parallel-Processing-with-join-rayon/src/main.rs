@@ -19,10 +19,9 @@
 // to remain unblocked.
 
 use tokio::task;
-use tokio::time::{self, Duration};
 
-fn cpu_intensive_task(id: u32) -> u32 {
-    let mut sum = 0;
+fn cpu_intensive_task(id: u32) -> u64 {
+    let mut sum: u64 = 0;
     for i in 0..1_000_000 {
         sum += i;
     }
@@ -30,15 +29,53 @@ fn cpu_intensive_task(id: u32) -> u32 {
     sum
 }
 
+// `spawn_blocking` returns a `JoinHandle<T>`, and awaiting it yields
+// `Result<T, JoinError>`. The error case means the closure panicked (or the
+// task was cancelled during runtime shutdown). `run_cpu` turns that into a
+// typed error instead of letting callers `unwrap()` it.
+
+#[derive(Debug, thiserror::Error)]
+pub enum CpuError {
+    #[error("CPU task panicked: {0}")]
+    Panicked(String),
+    #[error("CPU task was cancelled")]
+    Cancelled,
+}
+
+pub async fn run_cpu<T, F>(f: F) -> Result<T, CpuError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match task::spawn_blocking(f).await {
+        Ok(value) => Ok(value),
+        Err(e) if e.is_panic() => {
+            let payload = e.into_panic();
+            // Panic payloads are usually a `&str` or a `String`.
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic payload".to_string());
+            Err(CpuError::Panicked(message))
+        }
+        Err(_) => Err(CpuError::Cancelled),
+    }
+}
+
 async fn spawn_blocking_example() {
     let mut handles = vec![];
 
     for i in 0..3 {
-        handles.push(task::spawn_blocking(move || cpu_intensive_task(i)));
+        handles.push(tokio::spawn(run_cpu(move || cpu_intensive_task(i))));
     }
 
-    for handle in handles {
-        handle.await.unwrap();
+    for (i, handle) in handles.into_iter().enumerate() {
+        match handle.await {
+            Ok(Ok(sum)) => println!("Task {} returned {}.", i, sum),
+            Ok(Err(e)) => eprintln!("Task {} failed: {}", i, e),
+            Err(e) => eprintln!("Task {} could not be joined: {}", i, e),
+        }
     }
 }
 
@@ -79,3 +116,26 @@ async fn main() {
     println!("\n--- Rayon Example ---");
     rayon_example();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn run_cpu_returns_the_closure_value() {
+        let sum = run_cpu(|| (1..=100u64).sum::<u64>()).await.unwrap();
+        assert_eq!(sum, 5050);
+    }
+
+    #[tokio::test]
+    async fn run_cpu_maps_a_panic_to_panicked_with_its_message() {
+        let err = run_cpu(|| -> u32 { panic!("bad input") }).await.unwrap_err();
+        assert!(matches!(&err, CpuError::Panicked(message) if message == "bad input"), "{:?}", err);
+        assert_eq!(err.to_string(), "CPU task panicked: bad input");
+
+        // A formatted message arrives as a `String` payload rather than `&str`.
+        let id = 7;
+        let err = run_cpu(move || -> u32 { panic!("job {} failed", id) }).await.unwrap_err();
+        assert!(matches!(&err, CpuError::Panicked(message) if message == "job 7 failed"), "{:?}", err);
+    }
+}
@@ -11,9 +11,12 @@
 // released. This can severely degrade performance, especially in highly
 // concurrent applications.
 
+use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use tokio::time::{self, Duration};
+use tokio::sync::{Mutex, MutexGuard};
+use tokio::time::{self, Duration, Instant};
+use tracing::{warn, Level};
+use tracing_subscriber::FmtSubscriber;
 
 async fn contended_mutex_example() {
     let counter = Arc::new(Mutex::new(0));
@@ -39,6 +42,90 @@ async fn contended_mutex_example() {
     println!("Final counter value (contended): {}", *counter.lock().await);
 }
 
+// --- Detecting Long-held Locks ---
+
+// Contention is hard to see from the outside: the symptom is latency, not an
+// error. A cheap diagnostic is to time how long each guard lives and complain
+// when it crosses a threshold. `TimedMutex` wraps a `tokio::sync::Mutex` and
+// hands out a `TimedMutexGuard` that records when the lock was acquired. When
+// the guard is dropped (i.e. the lock is released), it logs a `warn!` if the
+// lock was held for longer than the threshold given at `lock` time.
+
+pub struct TimedMutex<T> {
+    inner: Mutex<T>,
+}
+
+impl<T> TimedMutex<T> {
+    pub fn new(value: T) -> Self {
+        TimedMutex { inner: Mutex::new(value) }
+    }
+
+    // `label` identifies the call site in the warning, e.g. "flush_batch".
+    pub async fn lock(&self, label: &'static str, threshold: Duration) -> TimedMutexGuard<'_, T> {
+        let guard = self.inner.lock().await;
+        TimedMutexGuard {
+            guard,
+            label,
+            threshold,
+            acquired_at: Instant::now(),
+        }
+    }
+}
+
+pub struct TimedMutexGuard<'a, T> {
+    guard: MutexGuard<'a, T>,
+    label: &'static str,
+    threshold: Duration,
+    acquired_at: Instant,
+}
+
+impl<T> Deref for TimedMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for TimedMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for TimedMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        let held = self.acquired_at.elapsed();
+        if held > self.threshold {
+            warn!(
+                label = self.label,
+                held_ms = held.as_millis() as u64,
+                threshold_ms = self.threshold.as_millis() as u64,
+                "lock held longer than threshold"
+            );
+        }
+    }
+}
+
+async fn timed_mutex_example() {
+    let counter = TimedMutex::new(0);
+
+    // Well-behaved critical section: no warning.
+    {
+        let mut num = counter.lock("quick_increment", Duration::from_millis(50)).await;
+        *num += 1;
+    }
+
+    // Doing slow work while holding the lock: logs a warning on release.
+    {
+        let mut num = counter.lock("slow_increment", Duration::from_millis(50)).await;
+        time::sleep(Duration::from_millis(120)).await;
+        *num += 1;
+    }
+
+    println!("Final counter value (timed): {}", *counter.lock("read", Duration::from_millis(50)).await);
+}
+
 // --- Async Stalls ---
 
 // An async stall occurs when an `async` task performs a blocking operation
@@ -65,9 +152,77 @@ async fn async_stall_example() {
 
 #[tokio::main]
 async fn main() {
+    // Print warnings from `TimedMutexGuard` to stdout.
+    let subscriber = FmtSubscriber::builder().with_max_level(Level::WARN).finish();
+    tracing::subscriber::set_global_default(subscriber)
+        .expect("setting default subscriber failed");
+
     println!("--- Lock Contention Example ---");
     contended_mutex_example().await;
 
+    println!("\n--- Timed Mutex Example ---");
+    timed_mutex_example().await;
+
     println!("\n--- Async Stall Example ---");
     async_stall_example().await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl CapturedLogs {
+        fn with_subscriber(&self, f: impl FnOnce()) -> String {
+            let logs = self.clone();
+            let subscriber = tracing_subscriber::fmt().with_ansi(false).with_writer(move || logs.clone()).finish();
+            tracing::subscriber::with_default(subscriber, f);
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn hold_lock(label: &'static str, threshold: Duration, held_for: Duration) -> String {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        CapturedLogs::default().with_subscriber(|| {
+            runtime.block_on(async {
+                let mutex = TimedMutex::new(0);
+                let mut guard = mutex.lock(label, threshold).await;
+                time::sleep(held_for).await;
+                *guard += 1;
+            })
+        })
+    }
+
+    #[test]
+    fn holding_a_lock_past_the_threshold_logs_a_warning() {
+        let logs = hold_lock("slow_section", Duration::from_millis(10), Duration::from_millis(40));
+        assert!(logs.contains("WARN") && logs.contains("lock held longer than threshold"), "{}", logs);
+        assert!(logs.contains("label=\"slow_section\"") && logs.contains("threshold_ms=10"), "{}", logs);
+    }
+
+    #[test]
+    fn a_short_critical_section_logs_nothing() {
+        let logs = hold_lock("quick_section", Duration::from_secs(1), Duration::ZERO);
+        assert!(logs.is_empty(), "{}", logs);
+    }
+
+    #[tokio::test]
+    async fn the_guard_reads_and_writes_the_protected_value() {
+        let mutex = TimedMutex::new(vec![1]);
+        mutex.lock("push", Duration::from_secs(1)).await.push(2);
+        assert_eq!(*mutex.lock("read", Duration::from_secs(1)).await, vec![1, 2]);
+    }
+}
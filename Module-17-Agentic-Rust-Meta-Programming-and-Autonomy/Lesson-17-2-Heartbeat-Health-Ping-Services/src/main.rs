@@ -19,6 +19,9 @@
 // A heartbeat is typically a periodic message sent by a service to a central
 // monitoring system, indicating that it's alive and well.
 
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc;
 use tokio::time::{self, Duration};
 use anyhow::Result;
@@ -26,38 +29,149 @@ use anyhow::Result;
 // --- Agent Message (extended) ---
 
 #[derive(Debug, Clone)]
-enum AgentMessage {
+pub enum AgentMessage {
     PerformTask(String),
     Heartbeat,
     Shutdown,
 }
 
+// --- Heartbeat Payload ---
+
+// A bare worker id only proves the worker is alive. Carrying a little load
+// information lets the monitor spot workers that are alive but swamped.
+
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    pub worker_id: u32,
+    // Tasks the worker is executing right now.
+    pub in_flight_jobs: usize,
+    // Commands waiting in the worker's channel.
+    pub queue_depth: usize,
+    // Set on the heartbeat sent right after a task timed out: the worker is
+    // alive and idle again, but something it was asked to do went wrong.
+    pub degraded: bool,
+    pub timestamp: time::Instant,
+}
+
+impl Heartbeat {
+    pub fn load(&self) -> usize {
+        self.in_flight_jobs + self.queue_depth
+    }
+}
+
 // --- Worker Agent (modified to send heartbeats) ---
 
+// Whether the health monitor is still listening. A send on an `mpsc` channel
+// only fails once the receiver has been dropped, and a dropped receiver never
+// comes back, so retrying every interval would just log the same error forever.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MonitorLink {
+    Connected,
+    MonitorDisconnected,
+}
+
 pub struct WorkerAgent {
     id: u32,
     state: String,
-    heartbeat_sender: mpsc::Sender<u32>,
+    heartbeat_sender: mpsc::Sender<Heartbeat>,
+    monitor_link: MonitorLink,
+    // Commands from whoever owns the matching sender.
+    commands: mpsc::Receiver<AgentMessage>,
+    heartbeat_interval: time::Interval,
+    in_flight_jobs: usize,
+    heartbeats_sent: u64,
+    // How long the simulated work for one task takes.
+    task_duration: Duration,
+    // How long a task may run before the agent gives up on it.
+    task_timeout: Duration,
 }
 
 impl WorkerAgent {
-    pub fn new(id: u32, heartbeat_sender: mpsc::Sender<u32>) -> Self {
-        WorkerAgent { id, state: format!("Worker {} idle", id), heartbeat_sender }
+    // Returns the agent together with the sender used to give it commands.
+    pub fn new(id: u32, heartbeat_sender: mpsc::Sender<Heartbeat>) -> (Self, mpsc::Sender<AgentMessage>) {
+        let (command_sender, commands) = mpsc::channel(32);
+        let agent = WorkerAgent {
+            id,
+            state: format!("Worker {} idle", id),
+            heartbeat_sender,
+            monitor_link: MonitorLink::Connected,
+            commands,
+            heartbeat_interval: time::interval(Duration::from_secs(1)),
+            in_flight_jobs: 0,
+            heartbeats_sent: 0,
+            task_duration: Duration::from_millis(500),
+            task_timeout: Duration::from_secs(5),
+        };
+        (agent, command_sender)
+    }
+
+    pub fn with_task_duration(mut self, task_duration: Duration) -> Self {
+        self.task_duration = task_duration;
+        self
+    }
+
+    pub fn with_task_timeout(mut self, task_timeout: Duration) -> Self {
+        self.task_timeout = task_timeout;
+        self
+    }
+
+    // Runs one task under `task_timeout`. The heartbeat keeps ticking while
+    // the task runs, so a slow task never looks like a dead worker. If the
+    // timeout fires, the task is dropped (cancelling it), a degraded
+    // heartbeat goes out straight away, and the agent is idle again.
+    async fn perform_task(&mut self, task: String) {
+        self.state = format!("Worker {} processing: {}", self.id, task);
+        println!("{} received: {}", self.state, task);
+        self.in_flight_jobs += 1;
+
+        // Simulated work; a real agent would run the task here.
+        let work = time::timeout(self.task_timeout, time::sleep(self.task_duration));
+        tokio::pin!(work);
+        let finished = loop {
+            tokio::select! {
+                result = &mut work => break result.is_ok(),
+                _ = self.heartbeat_interval.tick(), if self.monitor_link == MonitorLink::Connected => {
+                    self.send_heartbeat(false).await;
+                }
+            }
+        };
+
+        self.in_flight_jobs -= 1;
+        self.state = format!("Worker {} idle", self.id);
+        if finished {
+            println!("Worker {} finished task.", self.id);
+        } else {
+            eprintln!("Worker {} gave up on task '{}' after {:?}.", self.id, task, self.task_timeout);
+            self.send_heartbeat(true).await;
+        }
+    }
+
+    async fn send_heartbeat(&mut self, degraded: bool) {
+        if self.monitor_link == MonitorLink::MonitorDisconnected {
+            return;
+        }
+        let heartbeat = Heartbeat {
+            worker_id: self.id,
+            in_flight_jobs: self.in_flight_jobs,
+            queue_depth: self.commands.len(),
+            degraded,
+            timestamp: time::Instant::now(),
+        };
+        println!("Worker {} sending heartbeat (queue depth {}).", self.id, heartbeat.queue_depth);
+        if self.heartbeat_sender.send(heartbeat).await.is_err() {
+            // `SendError` means the monitor's receiver is gone.
+            eprintln!("Worker {} lost its health monitor; no longer sending heartbeats.", self.id);
+            self.monitor_link = MonitorLink::MonitorDisconnected;
+        } else {
+            self.heartbeats_sent += 1;
+        }
     }
 
     async fn handle_message(&mut self, message: AgentMessage) -> Result<()> {
         match message {
-            AgentMessage::PerformTask(task) => {
-                self.state = format!("Worker {} processing: {}", self.id, task);
-                println!("{} received: {}", self.state, task);
-                time::sleep(Duration::from_millis(500)).await;
-                self.state = format!("Worker {} idle", self.id);
-                println!("Worker {} finished task.", self.id);
-            }
-            AgentMessage::Heartbeat => {
-                println!("Worker {} sending heartbeat.", self.id);
-                self.heartbeat_sender.send(self.id).await?;
-            }
+            AgentMessage::PerformTask(task) => self.perform_task(task).await,
+            AgentMessage::Heartbeat => self.send_heartbeat(false).await,
             AgentMessage::Shutdown => {
                 println!("Worker {} shutting down.", self.id);
             }
@@ -65,17 +179,19 @@ impl WorkerAgent {
         Ok(())
     }
 
-    async fn run(mut self, mut receiver: mpsc::Receiver<AgentMessage>) {
+    // Services heartbeats and commands until told to shut down, then returns
+    // how many heartbeats were delivered.
+    async fn run(mut self) -> u64 {
         println!("Worker {} started.", self.id);
-        let mut heartbeat_interval = time::interval(Duration::from_secs(1));
         loop {
             tokio::select! {
-                _ = heartbeat_interval.tick() => {
+                // Once the monitor is gone, stop ticking the heartbeat branch.
+                _ = self.heartbeat_interval.tick(), if self.monitor_link == MonitorLink::Connected => {
                     if let Err(e) = self.handle_message(AgentMessage::Heartbeat).await {
                         eprintln!("Worker {} heartbeat error: {:?}", self.id, e);
                     }
                 }
-                Some(message) = receiver.recv() => {
+                Some(message) = self.commands.recv() => {
                     if let AgentMessage::Shutdown = message {
                         let _ = self.handle_message(message).await;
                         break;
@@ -84,64 +200,651 @@ impl WorkerAgent {
                         eprintln!("Worker {} error handling message: {:?}", self.id, e);
                     }
                 }
+                // No monitor and no command channel left: nothing more to do.
+                else => break,
             }
         }
         println!("Worker {} stopped.", self.id);
+        self.heartbeats_sent
     }
 }
 
 // --- Health Monitor Service ---
 
 // A service that receives heartbeats and monitors the health of workers.
+// A worker that has been quiet for longer than `unhealthy_after` is logged as
+// unhealthy. Once it has been quiet for longer than `dead_after`, it is
+// evicted from the map and reported exactly once as a `WorkerDown` on a
+// channel, so a supervisor can restart it. A supervisor that falls behind
+// must not stall the monitor, so a report that finds the channel full is
+// dropped and counted instead. A worker that starts sending heartbeats
+// again is simply tracked afresh.
 
-async fn health_monitor(mut heartbeat_receiver: mpsc::Receiver<u32>) {
-    let mut last_heartbeat = std::collections::HashMap::new();
-    let mut monitor_interval = time::interval(Duration::from_secs(3));
+#[derive(Debug, Clone)]
+pub struct WorkerDown {
+    pub worker_id: u32,
+    pub last_heartbeat: time::Instant,
+}
+
+// How many recent heartbeat intervals per worker feed `report`.
+const INTERVAL_HISTORY: usize = 10;
 
-    println!("Health monitor started.");
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerStatus {
+    Healthy,
+    // Alive, but late, irregular, or reporting a timed-out task.
+    Degraded,
+    // Silent for longer than `dead_after`; about to be evicted.
+    Dead,
+}
 
-    loop {
-        tokio::select! {
-            _ = monitor_interval.tick() => {
-                println!("Monitor: Checking worker health...");
-                let now = time::Instant::now();
-                for (worker_id, last_time) in last_heartbeat.iter() {
-                    if now.duration_since(*last_time) > Duration::from_secs(2) {
-                        warn!("Monitor: Worker {} is unhealthy (last heartbeat {:?}).", worker_id, last_time);
+// One row of `HealthMonitor::report`. `avg_interval` and `jitter` (the
+// standard deviation of the intervals) cover the last few heartbeats, and
+// are `None` until the worker has sent at least two.
+#[derive(Debug, Clone)]
+pub struct WorkerHealth {
+    pub id: u32,
+    pub last_seen_ago: Duration,
+    pub avg_interval: Option<Duration>,
+    pub jitter: Option<Duration>,
+    pub status: WorkerStatus,
+}
+
+// Cloning the monitor shares its state, so one clone can run the monitoring
+// loop while another answers `healthy_workers`. The latest heartbeat from each
+// worker is kept in full, so the monitor can also judge load, along with the
+// gaps between its recent heartbeats, so it can judge regularity.
+#[derive(Clone)]
+pub struct HealthMonitor {
+    last_heartbeat: Arc<Mutex<HashMap<u32, Heartbeat>>>,
+    // Locked after `last_heartbeat` whenever both are needed.
+    intervals: Arc<Mutex<HashMap<u32, VecDeque<Duration>>>>,
+    // Workers the service needs before it can take traffic.
+    registered: Arc<Mutex<HashSet<u32>>>,
+    unhealthy_after: Duration,
+    dead_after: Duration,
+    // Jitter above this makes a worker `Degraded` in `report`.
+    max_jitter: Duration,
+    down_sender: mpsc::Sender<WorkerDown>,
+    // `WorkerDown` reports lost because `down_sender` was full.
+    dropped_down_reports: Arc<AtomicU64>,
+}
+
+impl HealthMonitor {
+    pub fn new(dead_after: Duration, down_sender: mpsc::Sender<WorkerDown>) -> Self {
+        HealthMonitor {
+            last_heartbeat: Arc::new(Mutex::new(HashMap::new())),
+            intervals: Arc::new(Mutex::new(HashMap::new())),
+            registered: Arc::new(Mutex::new(HashSet::new())),
+            unhealthy_after: Duration::from_secs(2),
+            dead_after,
+            max_jitter: Duration::from_millis(500),
+            down_sender,
+            dropped_down_reports: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn with_unhealthy_after(mut self, unhealthy_after: Duration) -> Self {
+        self.unhealthy_after = unhealthy_after;
+        self
+    }
+
+    pub fn with_max_jitter(mut self, max_jitter: Duration) -> Self {
+        self.max_jitter = max_jitter;
+        self
+    }
+
+    pub fn register(&self, worker_id: u32) {
+        self.registered.lock().unwrap().insert(worker_id);
+    }
+
+    // Registered workers that are not currently healthy, sorted. Empty means
+    // the service is ready.
+    pub fn unready_workers(&self) -> Vec<u32> {
+        let healthy = self.healthy_workers();
+        let mut unready: Vec<u32> = self
+            .registered
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|worker_id| !healthy.contains(worker_id))
+            .copied()
+            .collect();
+        unready.sort_unstable();
+        unready
+    }
+
+    pub fn record_heartbeat(&self, heartbeat: Heartbeat) {
+        let mut last_heartbeat = self.last_heartbeat.lock().unwrap();
+        if let Some(previous) = last_heartbeat.get(&heartbeat.worker_id) {
+            let interval = heartbeat.timestamp.saturating_duration_since(previous.timestamp);
+            let mut intervals = self.intervals.lock().unwrap();
+            let history = intervals.entry(heartbeat.worker_id).or_default();
+            if history.len() == INTERVAL_HISTORY {
+                history.pop_front();
+            }
+            history.push_back(interval);
+        }
+        last_heartbeat.insert(heartbeat.worker_id, heartbeat);
+    }
+
+    // A per-worker view for operators, sorted by id. A worker is `Dead` once
+    // silent for `dead_after`, and `Degraded` if it is silent for longer
+    // than `unhealthy_after`, its jitter exceeds `max_jitter`, or its last
+    // heartbeat said a task timed out.
+    pub fn report(&self) -> Vec<WorkerHealth> {
+        let now = time::Instant::now();
+        let last_heartbeat = self.last_heartbeat.lock().unwrap();
+        let intervals = self.intervals.lock().unwrap();
+        let mut report: Vec<WorkerHealth> = last_heartbeat
+            .values()
+            .map(|heartbeat| {
+                let last_seen_ago = now.saturating_duration_since(heartbeat.timestamp);
+                let (avg_interval, jitter) = match intervals.get(&heartbeat.worker_id) {
+                    Some(history) if !history.is_empty() => {
+                        let secs: Vec<f64> = history.iter().map(Duration::as_secs_f64).collect();
+                        let mean = secs.iter().sum::<f64>() / secs.len() as f64;
+                        let variance = secs.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / secs.len() as f64;
+                        (Some(Duration::from_secs_f64(mean)), Some(Duration::from_secs_f64(variance.sqrt())))
                     }
+                    _ => (None, None),
+                };
+                let status = if last_seen_ago > self.dead_after {
+                    WorkerStatus::Dead
+                } else if last_seen_ago > self.unhealthy_after
+                    || jitter.is_some_and(|jitter| jitter > self.max_jitter)
+                    || heartbeat.degraded
+                {
+                    WorkerStatus::Degraded
+                } else {
+                    WorkerStatus::Healthy
+                };
+                WorkerHealth { id: heartbeat.worker_id, last_seen_ago, avg_interval, jitter, status }
+            })
+            .collect();
+        report.sort_unstable_by_key(|health| health.id);
+        report
+    }
+
+    // Workers that have sent a heartbeat within `unhealthy_after`, sorted.
+    pub fn healthy_workers(&self) -> Vec<u32> {
+        let now = time::Instant::now();
+        let mut healthy: Vec<u32> = self
+            .last_heartbeat
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|heartbeat| now.duration_since(heartbeat.timestamp) <= self.unhealthy_after)
+            .map(|heartbeat| heartbeat.worker_id)
+            .collect();
+        healthy.sort_unstable();
+        healthy
+    }
+
+    // Live workers whose last reported load (in-flight jobs plus queued
+    // commands) is above `threshold`, sorted.
+    pub fn overloaded(&self, threshold: usize) -> Vec<u32> {
+        let mut overloaded: Vec<u32> = self
+            .last_heartbeat
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|heartbeat| heartbeat.load() > threshold)
+            .map(|heartbeat| heartbeat.worker_id)
+            .collect();
+        overloaded.sort_unstable();
+        overloaded
+    }
+
+    pub fn dropped_down_reports(&self) -> u64 {
+        self.dropped_down_reports.load(Ordering::Relaxed)
+    }
+
+    // Warns about unhealthy workers and evicts dead ones as of `now`.
+    fn check(&self, now: time::Instant) {
+        let mut down = Vec::new();
+        self.last_heartbeat.lock().unwrap().retain(|worker_id, heartbeat| {
+            let silent_for = now.duration_since(heartbeat.timestamp);
+            if silent_for > self.dead_after {
+                down.push(WorkerDown { worker_id: *worker_id, last_heartbeat: heartbeat.timestamp });
+                return false;
+            }
+            if silent_for > self.unhealthy_after {
+                eprintln!("Monitor: Worker {} is unhealthy (silent for {:?}).", worker_id, silent_for);
+            }
+            true
+        });
+        let mut intervals = self.intervals.lock().unwrap();
+        for worker_down in down {
+            intervals.remove(&worker_down.worker_id);
+            eprintln!("Monitor: Worker {} is down; evicting it.", worker_down.worker_id);
+            match self.down_sender.try_send(worker_down) {
+                Ok(()) => {}
+                Err(mpsc::error::TrySendError::Full(worker_down)) => {
+                    self.dropped_down_reports.fetch_add(1, Ordering::Relaxed);
+                    eprintln!("Monitor: down-report channel full; dropped report for Worker {}.", worker_down.worker_id);
                 }
+                // Nobody listening for failures is not the monitor's problem.
+                Err(mpsc::error::TrySendError::Closed(_)) => {}
             }
-            Some(worker_id) = heartbeat_receiver.recv() => {
-                println!("Monitor: Received heartbeat from Worker {}.", worker_id);
-                last_heartbeat.insert(worker_id, time::Instant::now());
+        }
+    }
+
+    pub async fn run(self, mut heartbeat_receiver: mpsc::Receiver<Heartbeat>) {
+        let mut monitor_interval = time::interval(Duration::from_secs(3));
+
+        println!("Health monitor started.");
+
+        loop {
+            tokio::select! {
+                _ = monitor_interval.tick() => {
+                    println!("Monitor: Checking worker health...");
+                    self.check(time::Instant::now());
+                }
+                Some(heartbeat) = heartbeat_receiver.recv() => {
+                    println!("Monitor: Received heartbeat from Worker {} (load {}).", heartbeat.worker_id, heartbeat.load());
+                    if heartbeat.degraded {
+                        eprintln!("Monitor: Worker {} reports degraded (a task timed out).", heartbeat.worker_id);
+                    }
+                    self.record_heartbeat(heartbeat);
+                }
             }
         }
     }
 }
 
+// --- HTTP Health Endpoints ---
+
+// Orchestrators such as Kubernetes and Cloud Run probe services over HTTP.
+// `/healthz` answers liveness: if the process can respond at all, it is alive.
+// `/readyz` answers readiness from the monitor's state: it returns 503 until
+// every registered worker is healthy, so no traffic is routed to an instance
+// whose workers are missing.
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Router;
+
+pub fn health_router(monitor: HealthMonitor) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/workers", get(workers))
+        .with_state(monitor)
+}
+
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+async fn readyz(State(monitor): State<HealthMonitor>) -> (StatusCode, String) {
+    let unready = monitor.unready_workers();
+    if unready.is_empty() {
+        (StatusCode::OK, "ready".to_string())
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, format!("waiting for workers {:?}", unready))
+    }
+}
+
+// One line per worker from `HealthMonitor::report`, for humans and scripts.
+async fn workers(State(monitor): State<HealthMonitor>) -> String {
+    monitor
+        .report()
+        .iter()
+        .map(|health| {
+            format!(
+                "worker {} {:?} last_seen_ago={:?} avg_interval={:?} jitter={:?}\n",
+                health.id, health.status, health.last_seen_ago, health.avg_interval, health.jitter
+            )
+        })
+        .collect()
+}
+
+pub async fn serve_health(monitor: HealthMonitor, addr: std::net::SocketAddr) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    println!("Health endpoints listening on {}", addr);
+    axum::serve(listener, health_router(monitor)).await?;
+    Ok(())
+}
+
+// Sends one request straight to the router, without a network socket.
+async fn probe(router: Router, path: &str) -> Result<StatusCode> {
+    use tower::ServiceExt;
+    let request = axum::http::Request::builder().uri(path).body(axum::body::Body::empty())?;
+    Ok(router.oneshot(request).await?.status())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let (heartbeat_tx, heartbeat_rx) = mpsc::channel(32);
 
-    let worker1 = WorkerAgent::new(1, heartbeat_tx.clone());
-    let worker2 = WorkerAgent::new(2, heartbeat_tx.clone());
+    // Worker 1's task outlasts its timeout: it keeps sending heartbeats while
+    // the task runs, then reports itself degraded and goes back to idle.
+    let (worker1, worker1_tx) = WorkerAgent::new(1, heartbeat_tx.clone());
+    let worker1 = worker1
+        .with_task_duration(Duration::from_secs(10))
+        .with_task_timeout(Duration::from_millis(2500));
+    let (worker2, worker2_tx) = WorkerAgent::new(2, heartbeat_tx.clone());
 
-    let worker1_handle = tokio::spawn(worker1.run(mpsc::channel(32).1)); // Dummy receiver
-    let worker2_handle = tokio::spawn(worker2.run(mpsc::channel(32).1)); // Dummy receiver
+    let worker1_handle = tokio::spawn(worker1.run());
+    let worker2_handle = tokio::spawn(worker2.run());
 
-    let monitor_handle = tokio::spawn(health_monitor(heartbeat_rx));
+    // Workers silent for more than 5 seconds are reported on `down_rx`.
+    let (down_tx, mut down_rx) = mpsc::channel(8);
+    let monitor = HealthMonitor::new(Duration::from_secs(5), down_tx)
+        .with_unhealthy_after(Duration::from_secs(2))
+        .with_max_jitter(Duration::from_millis(300));
+    monitor.register(1);
+    monitor.register(2);
+    let monitor_handle = tokio::spawn(monitor.clone().run(heartbeat_rx));
+
+    // Serve the health endpoints on `$PORT` (Cloud Run's convention) or 8080.
+    let port = std::env::var("PORT").ok().and_then(|p| p.parse().ok()).unwrap_or(8080);
+    let http_handle = tokio::spawn(serve_health(monitor.clone(), ([0, 0, 0, 0], port).into()));
 
     println!("Main: Agents and monitor started.");
 
-    // Simulate some runtime
-    time::sleep(Duration::from_secs(10)).await;
+    // Give both workers something to do.
+    worker1_tx.send(AgentMessage::PerformTask("resize images".to_string())).await?;
+    worker2_tx.send(AgentMessage::PerformTask("send emails".to_string())).await?;
 
-    // In a real app, you'd send shutdown signals here.
+    // Simulate some runtime, then stop Worker 2 so its heartbeats stop.
+    time::sleep(Duration::from_secs(3)).await;
+    println!("Main: Healthy workers: {:?}", monitor.healthy_workers());
+    println!("Main: Workers with load above 5: {:?}", monitor.overloaded(5));
+    for health in monitor.report() {
+        println!(
+            "Main: Worker {} {:?}: last seen {:.1}s ago, avg interval {:?}, jitter {:?}",
+            health.id, health.status, health.last_seen_ago.as_secs_f64(), health.avg_interval, health.jitter
+        );
+    }
+    worker2_tx.send(AgentMessage::Shutdown).await?;
+    let heartbeats = worker2_handle.await?;
+    println!("Main: Worker 2 stopped cleanly after {} heartbeats.", heartbeats);
+
+    // The monitor reports Worker 2 once, after `dead_after` has passed.
+    time::sleep(Duration::from_secs(7)).await;
+    while let Ok(worker_down) = down_rx.try_recv() {
+        println!("Main: Supervisor notified: {:?}", worker_down);
+    }
+    println!("Main: Healthy workers: {:?}", monitor.healthy_workers());
+    println!("Main: Dropped down reports: {}", monitor.dropped_down_reports());
+    println!("Main: GET /readyz -> {}", probe(health_router(monitor.clone()), "/readyz").await?);
 
     println!("Main: Shutting down.");
+    worker1_tx.send(AgentMessage::Shutdown).await?;
+    let heartbeats = worker1_handle.await?;
+    println!("Main: Worker 1 stopped cleanly after {} heartbeats.", heartbeats);
 
-    // For this example, we'll just let the program exit.
-    // In a real app, you'd gracefully shut down workers and monitor.
+    // The monitor loop and the HTTP server never end on their own.
+    monitor_handle.abort();
+    http_handle.abort();
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn a_closed_monitor_channel_is_detected_once() {
+        let (heartbeat_tx, heartbeat_rx) = mpsc::channel(8);
+        let (mut worker, _worker_tx) = WorkerAgent::new(1, heartbeat_tx);
+        drop(heartbeat_rx);
+
+        worker.send_heartbeat(false).await;
+        assert_eq!(worker.monitor_link, MonitorLink::MonitorDisconnected);
+        // Later heartbeats return early instead of failing the send again.
+        worker.send_heartbeat(false).await;
+        assert_eq!(worker.monitor_link, MonitorLink::MonitorDisconnected);
+        assert_eq!(worker.heartbeats_sent, 0);
+    }
+
+    #[tokio::test]
+    async fn a_worker_without_a_monitor_keeps_serving_commands() {
+        let (heartbeat_tx, heartbeat_rx) = mpsc::channel(8);
+        let (worker, worker_tx) = WorkerAgent::new(1, heartbeat_tx);
+        drop(heartbeat_rx);
+        let handle = tokio::spawn(worker.with_task_duration(Duration::from_millis(10)).run());
+
+        worker_tx.send(AgentMessage::PerformTask("report".to_string())).await.unwrap();
+        worker_tx.send(AgentMessage::Shutdown).await.unwrap();
+        let heartbeats = time::timeout(Duration::from_secs(1), handle).await.unwrap().unwrap();
+        assert_eq!(heartbeats, 0);
+    }
+
+
+    fn heartbeat_at(worker_id: u32, timestamp: time::Instant) -> Heartbeat {
+        Heartbeat { worker_id, in_flight_jobs: 0, queue_depth: 0, degraded: false, timestamp }
+    }
+
+    #[tokio::test]
+    async fn a_silent_worker_is_reported_down_exactly_once() {
+        let (down_tx, mut down_rx) = mpsc::channel(8);
+        let monitor = HealthMonitor::new(Duration::from_secs(5), down_tx);
+        let start = time::Instant::now();
+        monitor.record_heartbeat(heartbeat_at(1, start));
+        monitor.record_heartbeat(heartbeat_at(2, start));
+
+        // Worker 1 keeps beating; worker 2 stops.
+        monitor.record_heartbeat(heartbeat_at(1, start + Duration::from_secs(4)));
+        monitor.check(start + Duration::from_secs(4));
+        assert!(down_rx.try_recv().is_err());
+
+        monitor.check(start + Duration::from_secs(6));
+        monitor.check(start + Duration::from_secs(8));
+        let worker_down = down_rx.try_recv().unwrap();
+        assert_eq!(worker_down.worker_id, 2);
+        assert_eq!(worker_down.last_heartbeat, start);
+        assert!(down_rx.try_recv().is_err());
+        assert_eq!(monitor.healthy_workers(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn a_full_down_channel_drops_reports_instead_of_blocking() {
+        let (down_tx, _never_drained) = mpsc::channel(8);
+        let monitor = HealthMonitor::new(Duration::from_secs(5), down_tx);
+        let start = time::Instant::now();
+        for worker_id in 1..=10 {
+            monitor.record_heartbeat(heartbeat_at(worker_id, start));
+        }
+
+        let late = start + Duration::from_secs(6);
+        // Eight reports fill the channel; the check still returns.
+        monitor.check(late);
+        assert_eq!(monitor.dropped_down_reports(), 2);
+        assert!(monitor.healthy_workers().is_empty());
+
+        // The monitor keeps tracking workers after dropping reports.
+        monitor.record_heartbeat(heartbeat_at(11, late));
+        monitor.check(late);
+        assert_eq!(monitor.report().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn healthy_workers_leaves_out_late_ones() {
+        let (down_tx, _down_rx) = mpsc::channel(8);
+        let monitor = HealthMonitor::new(Duration::from_secs(5), down_tx).with_unhealthy_after(Duration::from_secs(2));
+        let now = time::Instant::now();
+        monitor.record_heartbeat(heartbeat_at(3, now));
+        monitor.record_heartbeat(heartbeat_at(1, now - Duration::from_secs(3)));
+        monitor.record_heartbeat(heartbeat_at(2, now - Duration::from_millis(500)));
+        assert_eq!(monitor.healthy_workers(), vec![2, 3]);
+    }
+
+
+    #[tokio::test]
+    async fn a_worker_runs_a_task_and_stops_on_shutdown() {
+        let (heartbeat_tx, mut heartbeat_rx) = mpsc::channel(32);
+        let (worker, worker_tx) = WorkerAgent::new(7, heartbeat_tx);
+        let handle = tokio::spawn(worker.with_task_duration(Duration::from_millis(20)).run());
+
+        worker_tx.send(AgentMessage::PerformTask("index".to_string())).await.unwrap();
+        worker_tx.send(AgentMessage::Shutdown).await.unwrap();
+        let heartbeats = time::timeout(Duration::from_secs(1), handle).await.unwrap().unwrap();
+
+        assert!(heartbeats >= 1);
+        assert_eq!(heartbeat_rx.recv().await.unwrap().worker_id, 7);
+        // The worker is gone, so its command channel is closed.
+        assert!(worker_tx.send(AgentMessage::Heartbeat).await.is_err());
+    }
+
+
+    #[tokio::test]
+    async fn overloaded_picks_workers_above_the_threshold() {
+        let (down_tx, _down_rx) = mpsc::channel(8);
+        let monitor = HealthMonitor::new(Duration::from_secs(5), down_tx);
+        for (worker_id, in_flight_jobs, queue_depth) in [(1, 1, 0), (2, 1, 12), (3, 0, 5), (4, 1, 5)] {
+            monitor.record_heartbeat(Heartbeat {
+                worker_id,
+                in_flight_jobs,
+                queue_depth,
+                degraded: false,
+                timestamp: time::Instant::now(),
+            });
+        }
+        assert_eq!(monitor.overloaded(5), vec![2, 4]);
+        assert_eq!(monitor.overloaded(0), vec![1, 2, 3, 4]);
+        assert!(monitor.overloaded(20).is_empty());
+    }
+
+
+    #[tokio::test]
+    async fn healthz_is_always_ok() {
+        let (down_tx, _down_rx) = mpsc::channel(8);
+        let monitor = HealthMonitor::new(Duration::from_secs(5), down_tx);
+        monitor.register(1);
+        assert_eq!(probe(health_router(monitor), "/healthz").await.unwrap(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn readyz_waits_for_every_registered_worker() {
+        let (down_tx, _down_rx) = mpsc::channel(8);
+        let monitor = HealthMonitor::new(Duration::from_secs(5), down_tx);
+        monitor.register(1);
+        monitor.register(2);
+        let router = health_router(monitor.clone());
+        assert_eq!(probe(router.clone(), "/readyz").await.unwrap(), StatusCode::SERVICE_UNAVAILABLE);
+
+        monitor.record_heartbeat(heartbeat_at(1, time::Instant::now()));
+        assert_eq!(probe(router.clone(), "/readyz").await.unwrap(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(monitor.unready_workers(), vec![2]);
+
+        monitor.record_heartbeat(heartbeat_at(2, time::Instant::now()));
+        assert_eq!(probe(router.clone(), "/readyz").await.unwrap(), StatusCode::OK);
+
+        // A worker that goes quiet makes the service unready again.
+        monitor.record_heartbeat(heartbeat_at(2, time::Instant::now() - Duration::from_secs(3)));
+        assert_eq!(probe(router, "/readyz").await.unwrap(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+
+    #[tokio::test]
+    async fn a_task_past_its_timeout_keeps_heartbeats_flowing() {
+        let (heartbeat_tx, mut heartbeat_rx) = mpsc::channel(32);
+        let (worker, worker_tx) = WorkerAgent::new(4, heartbeat_tx);
+        let worker = worker
+            .with_task_duration(Duration::from_secs(10))
+            .with_task_timeout(Duration::from_millis(1200));
+        let started = time::Instant::now();
+        let handle = tokio::spawn(worker.run());
+
+        worker_tx.send(AgentMessage::PerformTask("stuck upload".to_string())).await.unwrap();
+        worker_tx.send(AgentMessage::Shutdown).await.unwrap();
+        // The worker gets to the shutdown long before the task would finish.
+        time::timeout(Duration::from_secs(3), handle).await.unwrap().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(3));
+
+        let mut heartbeats = Vec::new();
+        while let Ok(heartbeat) = heartbeat_rx.try_recv() {
+            heartbeats.push(heartbeat);
+        }
+        // The 1s interval ticked while the task was running.
+        assert!(heartbeats.iter().any(|h| h.in_flight_jobs == 1 && !h.degraded), "{:?}", heartbeats);
+        let last = heartbeats.last().unwrap();
+        assert!(last.degraded && last.in_flight_jobs == 0, "{:?}", heartbeats);
+        assert_eq!(heartbeats.iter().filter(|h| h.degraded).count(), 1);
+    }
+
+
+    // Worker 1 beats steadily and worker 2 irregularly around the same
+    // mean. Worker 3 is silent past `dead_after`, worker 4 past
+    // `unhealthy_after`, and worker 5's last task timed out.
+    fn report_monitor() -> HealthMonitor {
+        let (down_tx, _down_rx) = mpsc::channel(8);
+        let monitor = HealthMonitor::new(Duration::from_secs(5), down_tx)
+            .with_unhealthy_after(Duration::from_secs(2))
+            .with_max_jitter(Duration::from_millis(300));
+        let now = time::Instant::now();
+        let beats: [(u32, &[u64], bool); 5] = [
+            (1, &[4000, 3000, 2000, 1000, 200], false),
+            (2, &[4100, 3900, 2100, 1900, 100], false),
+            (3, &[9000, 8000, 7000], false),
+            (4, &[5000, 4000, 3000], false),
+            (5, &[2000, 1000, 0], true),
+        ];
+        for (worker_id, ages_ms, degraded) in beats {
+            for (i, age_ms) in ages_ms.iter().enumerate() {
+                monitor.record_heartbeat(Heartbeat {
+                    degraded: degraded && i == ages_ms.len() - 1,
+                    ..heartbeat_at(worker_id, now - Duration::from_millis(*age_ms))
+                });
+            }
+        }
+        monitor
+    }
+
+    fn assert_close(actual: Option<Duration>, expected_ms: f64) {
+        let actual_ms = actual.unwrap().as_secs_f64() * 1000.0;
+        assert!((actual_ms - expected_ms).abs() < 1.0, "{} != {}", actual_ms, expected_ms);
+    }
+
+    #[test]
+    fn report_computes_jitter_and_classifies_workers() {
+        let report = report_monitor().report();
+        let statuses: Vec<(u32, WorkerStatus)> = report.iter().map(|health| (health.id, health.status)).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (1, WorkerStatus::Healthy),
+                (2, WorkerStatus::Degraded),
+                (3, WorkerStatus::Dead),
+                (4, WorkerStatus::Degraded),
+                (5, WorkerStatus::Degraded),
+            ]
+        );
+
+        // Intervals of 1000, 1000, 1000 and 800ms.
+        assert_close(report[0].avg_interval, 950.0);
+        assert_close(report[0].jitter, 7500f64.sqrt());
+        // Intervals of 200, 1800, 200 and 1800ms.
+        assert_close(report[1].avg_interval, 1000.0);
+        assert_close(report[1].jitter, 800.0);
+        assert_close(report[4].jitter, 0.0);
+        assert!(report[2].last_seen_ago >= Duration::from_secs(7));
+    }
+
+    #[test]
+    fn a_single_heartbeat_has_no_interval_stats() {
+        let (down_tx, _down_rx) = mpsc::channel(8);
+        let monitor = HealthMonitor::new(Duration::from_secs(5), down_tx);
+        monitor.record_heartbeat(heartbeat_at(1, time::Instant::now()));
+        let report = monitor.report();
+        assert_eq!(report[0].status, WorkerStatus::Healthy);
+        assert!(report[0].avg_interval.is_none() && report[0].jitter.is_none());
+    }
+
+    #[tokio::test]
+    async fn the_workers_endpoint_lists_one_line_per_worker() {
+        use tower::ServiceExt;
+        let request = axum::http::Request::builder().uri("/workers").body(axum::body::Body::empty()).unwrap();
+        let response = health_router(report_monitor()).oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(body.lines().count(), 5);
+        assert!(body.starts_with("worker 1 Healthy"), "{}", body);
+        assert!(body.contains("worker 3 Dead"), "{}", body);
+    }
+}
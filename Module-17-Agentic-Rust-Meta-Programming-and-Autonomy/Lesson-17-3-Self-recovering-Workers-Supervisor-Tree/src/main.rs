@@ -21,8 +21,9 @@
 // - If a supervisor fails, its parent supervisor takes action.
 // This creates a resilient fault-tolerant structure.
 
-use tokio::sync::mpsc;
-use tokio::time::{self, Duration};
+use std::collections::VecDeque;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::{self, Duration, Instant};
 use anyhow::Result;
 
 // --- Worker Messages ---
@@ -36,22 +37,34 @@ enum WorkerMessage {
 // --- Worker Task (can fail) ---
 
 // This worker can deliberately panic to simulate unexpected failures.
-async fn worker_job(id: u32, mut rx: mpsc::Receiver<WorkerMessage>) -> Result<()> {
-    println!("Worker {} started.", id);
-    let mut work_factor = 0;
+// `generation` counts how many times the supervisor has started this worker,
+// so the logs show which incarnation handled each message. A message on
+// `shutdown` stops the worker like `Stop` does, once its current work is
+// done. It is checked first, so work already queued for the worker is dropped.
+async fn worker_job(
+    id: u32,
+    generation: u32,
+    mut rx: mpsc::Receiver<WorkerMessage>,
+    mut shutdown: broadcast::Receiver<()>,
+) -> Result<()> {
+    println!("Worker {} (generation {}) started.", id, generation);
     loop {
         tokio::select! {
+            biased;
+            _ = shutdown.recv() => {
+                println!("Worker {} saw the shutdown signal. Exiting.", id);
+                break;
+            }
             Some(msg) = rx.recv() => {
                 match msg {
                     WorkerMessage::StartWork(factor) => {
-                        work_factor = factor;
                         println!("Worker {} starting work with factor {}.", id, factor);
                         if factor % 3 == 0 {
                             eprintln!("Worker {} is purposefully panicking with factor {}.", id, factor);
                             panic!("Simulated worker panic!");
                         }
-                        time::sleep(Duration::from_millis(100 * work_factor as u64)).await;
-                        println!("Worker {} finished work with factor {}.", id, factor);
+                        time::sleep(Duration::from_millis(100 * factor as u64)).await;
+                        println!("Worker {} (generation {}) finished work with factor {}.", id, generation, factor);
                     }
                     WorkerMessage::Stop => {
                         println!("Worker {} received stop signal. Exiting.", id);
@@ -67,30 +80,316 @@ async fn worker_job(id: u32, mut rx: mpsc::Receiver<WorkerMessage>) -> Result<()
     Ok(())
 }
 
+// --- Restart Policy ---
+
+// Restarting forever is dangerous: a worker that crashes on startup would spin
+// in a restart loop. The policy caps how many restarts are allowed inside a
+// sliding time window, and spaces them out with exponential backoff.
+
+#[derive(Debug, Clone)]
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    // The delay before the `attempt`-th restart in the window (1-based):
+    // `initial`, then doubling each time, capped at `max`.
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32 << attempt.saturating_sub(1).min(16);
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RestartPolicy {
+    max_restarts: u32,
+    within: Duration,
+    backoff: Backoff,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            max_restarts: 5,
+            within: Duration::from_secs(60),
+            backoff: Backoff { initial: Duration::from_secs(1), max: Duration::from_secs(30) },
+        }
+    }
+}
+
+impl RestartPolicy {
+    // Records a restart at `now` in `recent` (the restarts still inside the
+    // window) and returns the delay to wait before it. Returns `None` if the
+    // budget is already used up.
+    fn next_delay(&self, recent: &mut VecDeque<Instant>, now: Instant) -> Option<Duration> {
+        while recent.front().is_some_and(|t| now.duration_since(*t) > self.within) {
+            recent.pop_front();
+        }
+        if recent.len() >= self.max_restarts as usize {
+            return None;
+        }
+        recent.push_back(now);
+        Some(self.backoff.delay(recent.len() as u32))
+    }
+}
+
+// How a supervisor ended. `GaveUp` is the permanent failure its parent needs
+// to hear about.
+#[derive(Debug, PartialEq)]
+enum SupervisorExit {
+    Graceful,
+    GaveUp { failures: u32 },
+}
+
 // --- Supervisor Task ---
 
 // The supervisor monitors its children workers and restarts them if they fail.
-async fn supervisor(worker_id: u32, mut main_tx: mpsc::Sender<WorkerMessage>) {
+// It owns both ends of the plumbing: `inbox` receives messages from the rest
+// of the application, and `worker_tx` reaches whichever worker is currently
+// alive. Each restart creates a fresh channel, so forwarding always targets the
+// live worker. When `inbox` closes, the supervisor tells the worker to stop.
+// Restarts follow `policy`; the return value tells the caller whether the
+// worker finished or the supervisor gave up on it.
+//
+// `shutdown` is a broadcast shared by every supervisor, so the application
+// can stop them all at once even while other code still holds inbox
+// senders. On the signal the supervisor tells its worker to `Stop`, waits
+// for the current work to finish, and exits without restarting anything.
+// Messages still queued in `inbox` are dropped.
+async fn supervisor(
+    worker_id: u32,
+    mut inbox: mpsc::Receiver<WorkerMessage>,
+    policy: RestartPolicy,
+    mut shutdown: broadcast::Receiver<()>,
+) -> SupervisorExit {
     println!("Supervisor for Worker {} started.", worker_id);
-    loop {
+    // A message the previous worker never received because it died first.
+    let mut pending: Option<WorkerMessage> = None;
+    let mut generation = 0;
+    let mut failures = 0;
+    // When each restart inside the current window happened.
+    let mut recent_restarts: VecDeque<Instant> = VecDeque::new();
+    let exit = loop {
+        generation += 1;
         let (worker_tx, worker_rx) = mpsc::channel(1);
-        let handle = tokio::spawn(worker_job(worker_id, worker_rx));
+        let mut handle = tokio::spawn(worker_job(worker_id, generation, worker_rx, shutdown.resubscribe()));
+        let mut shutting_down = false;
 
-        // Forward messages from main to the worker
-        let main_tx_clone = main_tx.send(WorkerMessage::StartWork(worker_id)).await.unwrap();
+        // Forward messages from main to the worker until it exits.
+        let outcome = loop {
+            if let Some(msg) = pending.take() {
+                if let Err(mpsc::error::SendError(msg)) = worker_tx.send(msg).await {
+                    pending = Some(msg);
+                    break (&mut handle).await;
+                }
+            }
+            tokio::select! {
+                outcome = &mut handle => break outcome,
+                msg = inbox.recv() => match msg {
+                    Some(msg) => pending = Some(msg),
+                    None => {
+                        // Nobody can send us more work; let the worker finish.
+                        let _ = worker_tx.send(WorkerMessage::Stop).await;
+                        break (&mut handle).await;
+                    }
+                },
+                _ = shutdown.recv() => {
+                    println!("Supervisor for Worker {} received shutdown; stopping its worker.", worker_id);
+                    shutting_down = true;
+                    // The worker may already be gone, or may have seen the
+                    // broadcast itself; either way it won't need `Stop`.
+                    let _ = worker_tx.send(WorkerMessage::Stop).await;
+                    break (&mut handle).await;
+                }
+            }
+        };
 
-        // Wait for the worker to finish or panic
-        if let Err(e) = handle.await {
-            eprintln!("Supervisor: Worker {} failed: {:?}. Restarting...", worker_id, e);
-            // In a real system, you might implement backoff or retry limits.
-            time::sleep(Duration::from_secs(1)).await; // Delay before restarting
+        // The worker finished or panicked. During shutdown even a panic is
+        // not restarted.
+        if shutting_down {
+            if let Err(e) = outcome {
+                eprintln!("Supervisor: Worker {} failed while shutting down: {:?}.", worker_id, e);
+            }
+            break SupervisorExit::Graceful;
+        }
+        if let Err(e) = outcome {
+            failures += 1;
+            let Some(delay) = policy.next_delay(&mut recent_restarts, Instant::now()) else {
+                eprintln!(
+                    "Supervisor: Worker {} failed: {:?}. {} restarts within {:?}; giving up.",
+                    worker_id, e, policy.max_restarts, policy.within
+                );
+                break SupervisorExit::GaveUp { failures };
+            };
+            eprintln!("Supervisor: Worker {} failed: {:?}. Restarting in {:?}...", worker_id, e, delay);
+            // Delay before restarting, unless shutdown arrives meanwhile.
+            tokio::select! {
+                _ = time::sleep(delay) => {}
+                _ = shutdown.recv() => {
+                    println!("Supervisor for Worker {} received shutdown during backoff.", worker_id);
+                    break SupervisorExit::Graceful;
+                }
+            }
         } else {
             // Worker exited gracefully (e.g., after receiving a Stop message)
             println!("Supervisor: Worker {} exited gracefully.", worker_id);
-            break; // Supervisor can exit if worker exited gracefully
+            break SupervisorExit::Graceful; // Supervisor can exit if worker exited gracefully
         }
-    }
+    };
     println!("Supervisor for Worker {} stopped.", worker_id);
+    exit
+}
+
+// --- Supervising Several Children ---
+
+// `supervisor` above watches one worker. A `Supervisor` watches a group of
+// children and decides, according to its `Strategy`, which of them to restart
+// when one fails:
+// - `OneForOne`: only the failed child.
+// - `OneForAll`: every child, for children that only make sense together.
+// - `RestForOne`: the failed child and every child started after it, for
+//   children that depend on the ones before them.
+// Children are registered with a start function that spawns the task and
+// returns its `JoinHandle`, so the supervisor can start them again.
+
+use tokio::task::{AbortHandle, JoinHandle};
+
+#[derive(Debug, Clone, Copy)]
+enum Strategy {
+    OneForOne,
+    OneForAll,
+    RestForOne,
+}
+
+type StartFn = Box<dyn Fn() -> JoinHandle<()> + Send + Sync>;
+
+struct Child {
+    name: String,
+    start: StartFn,
+    // `None` once the child has exited normally.
+    running: Option<AbortHandle>,
+    // Bumped on every start, so exits from a replaced instance are ignored.
+    generation: u32,
+    restarts: u32,
+}
+
+// Sent by a small monitor task when a child's `JoinHandle` resolves.
+struct ChildExit {
+    index: usize,
+    generation: u32,
+    panicked: bool,
+}
+
+struct Supervisor {
+    strategy: Strategy,
+    policy: RestartPolicy,
+    children: Vec<Child>,
+    exits_tx: mpsc::Sender<ChildExit>,
+    exits_rx: mpsc::Receiver<ChildExit>,
+}
+
+impl Supervisor {
+    fn new(strategy: Strategy) -> Self {
+        let (exits_tx, exits_rx) = mpsc::channel(16);
+        Supervisor { strategy, policy: RestartPolicy::default(), children: Vec::new(), exits_tx, exits_rx }
+    }
+
+    fn with_policy(mut self, policy: RestartPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    // Children are started in registration order.
+    fn with_child(mut self, name: &str, start: impl Fn() -> JoinHandle<()> + Send + Sync + 'static) -> Self {
+        self.children.push(Child {
+            name: name.to_string(),
+            start: Box::new(start),
+            running: None,
+            generation: 0,
+            restarts: 0,
+        });
+        self
+    }
+
+    // How often each child has been restarted, in registration order.
+    fn restart_counts(&self) -> Vec<(&str, u32)> {
+        self.children.iter().map(|c| (c.name.as_str(), c.restarts)).collect()
+    }
+
+    fn start_child(&mut self, index: usize) {
+        let child = &mut self.children[index];
+        child.generation += 1;
+        let handle = (child.start)();
+        child.running = Some(handle.abort_handle());
+
+        let generation = child.generation;
+        let exits_tx = self.exits_tx.clone();
+        tokio::spawn(async move {
+            let panicked = handle.await.is_err();
+            let _ = exits_tx.send(ChildExit { index, generation, panicked }).await;
+        });
+    }
+
+    fn stop_child(&mut self, index: usize) {
+        if let Some(running) = self.children[index].running.take() {
+            running.abort();
+        }
+    }
+
+    // Starts every child and supervises them until they have all exited
+    // normally, or until the restart budget is exhausted.
+    async fn run(&mut self) -> SupervisorExit {
+        for index in 0..self.children.len() {
+            self.start_child(index);
+        }
+        let mut failures = 0;
+        let mut recent_restarts = VecDeque::new();
+
+        while self.children.iter().any(|c| c.running.is_some()) {
+            let Some(exit) = self.exits_rx.recv().await else { break };
+            if exit.generation != self.children[exit.index].generation {
+                continue; // An instance we stopped ourselves.
+            }
+            self.children[exit.index].running = None;
+            if !exit.panicked {
+                println!("Supervisor: child {} exited gracefully.", self.children[exit.index].name);
+                continue;
+            }
+
+            failures += 1;
+            let Some(delay) = self.policy.next_delay(&mut recent_restarts, Instant::now()) else {
+                eprintln!("Supervisor: child {} failed; restart budget exhausted, giving up.", self.children[exit.index].name);
+                for index in 0..self.children.len() {
+                    self.stop_child(index);
+                }
+                return SupervisorExit::GaveUp { failures };
+            };
+
+            let affected = match self.strategy {
+                Strategy::OneForOne => exit.index..exit.index + 1,
+                Strategy::OneForAll => 0..self.children.len(),
+                Strategy::RestForOne => exit.index..self.children.len(),
+            };
+            // Stop in reverse start order, then start again in order.
+            for index in affected.clone().rev() {
+                self.stop_child(index);
+            }
+            eprintln!(
+                "Supervisor ({:?}): child {} failed. Restarting {:?} in {:?}...",
+                self.strategy,
+                self.children[exit.index].name,
+                self.children[affected.clone()].iter().map(|c| c.name.as_str()).collect::<Vec<_>>(),
+                delay
+            );
+            time::sleep(delay).await;
+            for index in affected {
+                self.children[index].restarts += 1;
+                self.start_child(index);
+            }
+        }
+        SupervisorExit::Graceful
+    }
 }
 
 #[tokio::main]
@@ -98,28 +397,226 @@ async fn main() -> Result<()> {
     // This is a simplified example. In a real supervisor tree, the main task
     // would be a top-level supervisor for multiple supervisors.
 
-    let (main_tx, mut main_rx) = mpsc::channel(10);
-
+    // Each supervisor gets its own inbox; main keeps the sending halves.
+    // Every supervisor also subscribes to one shutdown broadcast.
+    let (shutdown_tx, _) = broadcast::channel(1);
+    let mut senders = Vec::new();
+    let mut supervisors = Vec::new();
     for i in 0..3 {
-        let tx_clone = main_tx.clone();
-        tokio::spawn(supervisor(i, tx_clone));
+        let (tx, rx) = mpsc::channel(10);
+        senders.push(tx);
+        supervisors.push(tokio::spawn(supervisor(i, rx, RestartPolicy::default(), shutdown_tx.subscribe())));
     }
 
-    // Send some work messages to the workers.
-    main_tx.send(WorkerMessage::StartWork(1)).await?;
-    main_tx.send(WorkerMessage::StartWork(2)).await?;
-    main_tx.send(WorkerMessage::StartWork(3)).await?;
-    main_tx.send(WorkerMessage::StartWork(4)).await?;
-    main_tx.send(WorkerMessage::StartWork(5)).await?;
+    // Send some work messages to the workers. Factor 3 makes Worker 0 panic;
+    // the factor 5 sent afterwards must reach its restarted replacement.
+    senders[0].send(WorkerMessage::StartWork(1)).await?;
+    senders[1].send(WorkerMessage::StartWork(2)).await?;
+    senders[2].send(WorkerMessage::StartWork(4)).await?;
+    senders[0].send(WorkerMessage::StartWork(3)).await?;
+    senders[0].send(WorkerMessage::StartWork(5)).await?;
 
-    // Wait for some time to see restarts
-    time::sleep(Duration::from_secs(10)).await;
+    // Dropping the senders closes every inbox. Each supervisor then stops its
+    // worker once the queued work is done, and exits.
+    drop(senders);
+    for handle in supervisors {
+        handle.await?;
+    }
+
+    // Shutdown by broadcast. In a real application inbox senders are cloned
+    // into many places, so waiting for all of them to drop may never end.
+    // Here main keeps the senders alive, lets the workers get busy, and then
+    // broadcasts shutdown: every worker finishes its current work, each
+    // supervisor exits, and main awaits them all.
+    let mut senders = Vec::new();
+    let mut supervisors = Vec::new();
+    for i in 20..24 {
+        let (tx, rx) = mpsc::channel(10);
+        senders.push(tx);
+        supervisors.push((i, tokio::spawn(supervisor(i, rx, RestartPolicy::default(), shutdown_tx.subscribe()))));
+    }
+    for (tx, factor) in senders.iter().zip([2, 4, 5, 1]) {
+        tx.send(WorkerMessage::StartWork(factor)).await?;
+        tx.send(WorkerMessage::StartWork(1)).await?;
+    }
+    time::sleep(Duration::from_millis(150)).await;
+    let started = Instant::now();
+    println!("Main: broadcasting shutdown.");
+    shutdown_tx.send(())?;
+    for (id, handle) in supervisors {
+        println!("Main: supervisor {} finished with {:?}.", id, handle.await?);
+    }
+    println!("Main: all supervisors stopped {:?} after the signal; senders still held: {}.", started.elapsed(), senders.len());
+    drop(senders);
 
-    // Stop all workers (conceptual - in a real system, supervisor would manage this)
-    // The current main_tx is being cloned in the supervisor, so it's not a direct channel to workers.
-    // This example focuses on worker self-recovery, not main-initiated graceful shutdown of supervisors.
+    // A group of children under one `Supervisor`. "db", "cache" and "api"
+    // are started in that order, work for a moment, and exit. The tests make
+    // "cache" fail and check which children each strategy restarts.
+    for strategy in [Strategy::OneForOne, Strategy::OneForAll, Strategy::RestForOne] {
+        let mut tree = Supervisor::new(strategy)
+            .with_policy(RestartPolicy {
+                max_restarts: 3,
+                within: Duration::from_secs(10),
+                backoff: Backoff { initial: Duration::from_millis(20), max: Duration::from_millis(200) },
+            })
+            .with_child("db", || tokio::spawn(time::sleep(Duration::from_millis(200))))
+            .with_child("cache", || tokio::spawn(time::sleep(Duration::from_millis(50))))
+            .with_child("api", || tokio::spawn(time::sleep(Duration::from_millis(200))));
+        let exit = tree.run().await;
+        println!("{:?} ended {:?}; restarts: {:?}", strategy, exit, tree.restart_counts());
+    }
 
     println!("Main application finished.");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    fn quick_policy(max_restarts: u32) -> RestartPolicy {
+        RestartPolicy {
+            max_restarts,
+            within: Duration::from_secs(10),
+            backoff: Backoff { initial: Duration::from_millis(10), max: Duration::from_millis(50) },
+        }
+    }
+
+    // Runs a supervisor fed `factors` in order until its inbox closes.
+    async fn supervise(factors: &[u32], policy: RestartPolicy) -> SupervisorExit {
+        let (shutdown_tx, _) = broadcast::channel(1);
+        let (tx, rx) = mpsc::channel(10);
+        let handle = tokio::spawn(supervisor(1, rx, policy, shutdown_tx.subscribe()));
+        for factor in factors {
+            let _ = tx.send(WorkerMessage::StartWork(*factor)).await;
+        }
+        drop(tx);
+        time::timeout(Duration::from_secs(5), handle).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn work_sent_after_a_panic_reaches_the_restarted_worker() {
+        // With one restart allowed, a second panic can only come from the
+        // restarted worker receiving the factor 6.
+        assert_eq!(supervise(&[1, 3, 6], quick_policy(1)).await, SupervisorExit::GaveUp { failures: 2 });
+    }
+
+    #[tokio::test]
+    async fn a_restarted_worker_finishes_its_work_and_exits() {
+        assert_eq!(supervise(&[1, 3, 2], quick_policy(1)).await, SupervisorExit::Graceful);
+    }
+
+
+    #[tokio::test]
+    async fn a_worker_that_always_panics_is_given_up_on() {
+        let exit = supervise(&[3, 6, 9, 12], quick_policy(2)).await;
+        assert_eq!(exit, SupervisorExit::GaveUp { failures: 3 });
+    }
+
+    #[tokio::test]
+    async fn a_worker_that_panics_twice_then_succeeds_recovers() {
+        assert_eq!(supervise(&[3, 6, 1], quick_policy(2)).await, SupervisorExit::Graceful);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let backoff = Backoff { initial: Duration::from_millis(100), max: Duration::from_millis(500) };
+        let delays: Vec<u128> = (1..=5).map(|attempt| backoff.delay(attempt).as_millis()).collect();
+        assert_eq!(delays, vec![100, 200, 400, 500, 500]);
+    }
+
+    #[test]
+    fn restarts_outside_the_window_free_up_the_budget() {
+        let policy = RestartPolicy { within: Duration::from_secs(10), ..quick_policy(2) };
+        let mut recent = VecDeque::new();
+        let start = Instant::now();
+        assert_eq!(policy.next_delay(&mut recent, start), Some(Duration::from_millis(10)));
+        assert_eq!(policy.next_delay(&mut recent, start + Duration::from_secs(1)), Some(Duration::from_millis(20)));
+        assert_eq!(policy.next_delay(&mut recent, start + Duration::from_secs(2)), None);
+        // The first restart has left the window; the second has not.
+        assert_eq!(policy.next_delay(&mut recent, start + Duration::from_secs(11)), Some(Duration::from_millis(20)));
+    }
+
+
+    // "cache" panics the first time it runs; "db" and "api" just work for a
+    // moment and exit.
+    async fn restarts_after_cache_fails(strategy: Strategy) -> Vec<(String, u32)> {
+        let cache_runs = Arc::new(AtomicU32::new(0));
+        let mut tree = Supervisor::new(strategy)
+            .with_policy(quick_policy(3))
+            .with_child("db", || tokio::spawn(time::sleep(Duration::from_millis(100))))
+            .with_child("cache", move || {
+                let run = cache_runs.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    time::sleep(Duration::from_millis(20)).await;
+                    if run == 0 {
+                        panic!("cache failed to warm up");
+                    }
+                })
+            })
+            .with_child("api", || tokio::spawn(time::sleep(Duration::from_millis(100))));
+        assert_eq!(tree.run().await, SupervisorExit::Graceful);
+        tree.restart_counts().into_iter().map(|(name, restarts)| (name.to_string(), restarts)).collect()
+    }
+
+    fn counts(db: u32, cache: u32, api: u32) -> Vec<(String, u32)> {
+        vec![("db".to_string(), db), ("cache".to_string(), cache), ("api".to_string(), api)]
+    }
+
+    #[tokio::test]
+    async fn one_for_one_restarts_only_the_failed_child() {
+        assert_eq!(restarts_after_cache_fails(Strategy::OneForOne).await, counts(0, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn one_for_all_restarts_every_child() {
+        assert_eq!(restarts_after_cache_fails(Strategy::OneForAll).await, counts(1, 1, 1));
+    }
+
+    #[tokio::test]
+    async fn rest_for_one_restarts_the_failed_child_and_later_ones() {
+        assert_eq!(restarts_after_cache_fails(Strategy::RestForOne).await, counts(0, 1, 1));
+    }
+
+    #[tokio::test]
+    async fn a_group_that_keeps_failing_is_given_up_on() {
+        let mut tree = Supervisor::new(Strategy::OneForAll)
+            .with_policy(quick_policy(2))
+            .with_child("db", || tokio::spawn(time::sleep(Duration::from_secs(10))))
+            .with_child("cache", || tokio::spawn(async { panic!("cache is broken") }));
+        let exit = time::timeout(Duration::from_secs(5), tree.run()).await.unwrap();
+        assert_eq!(exit, SupervisorExit::GaveUp { failures: 3 });
+    }
+
+
+    #[tokio::test]
+    async fn a_shutdown_broadcast_stops_every_supervisor() {
+        let (shutdown_tx, _) = broadcast::channel(1);
+        let slow_backoff = RestartPolicy {
+            backoff: Backoff { initial: Duration::from_secs(30), max: Duration::from_secs(30) },
+            ..quick_policy(5)
+        };
+        let mut senders = Vec::new();
+        let mut supervisors = Vec::new();
+        for (id, factor) in [(1, 2), (2, 4), (3, 3), (4, 1)] {
+            let (tx, rx) = mpsc::channel(10);
+            supervisors.push(tokio::spawn(supervisor(id, rx, slow_backoff.clone(), shutdown_tx.subscribe())));
+            tx.send(WorkerMessage::StartWork(factor)).await.unwrap();
+            tx.send(WorkerMessage::StartWork(1)).await.unwrap();
+            senders.push(tx);
+        }
+        // Worker 3 has panicked by now, so its supervisor is in its 30s backoff.
+        time::sleep(Duration::from_millis(150)).await;
+
+        shutdown_tx.send(()).unwrap();
+        for handle in supervisors {
+            let exit = time::timeout(Duration::from_secs(2), handle).await.unwrap().unwrap();
+            assert_eq!(exit, SupervisorExit::Graceful);
+        }
+        // Every inbox sender was still held, so only the broadcast stopped them.
+        assert_eq!(senders.len(), 4);
+    }
+}